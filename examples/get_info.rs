@@ -14,24 +14,21 @@
 
 extern crate pjlink;
 
-use pjlink::{ErrorStatus, ErrorType, InputType, PjlinkDevice, PowerStatus};
+use pjlink::{ErrorType, InputType, PjlinkDevice, PowerStatus};
 use std::env;
 
 fn main() {
     let host = match env::args().nth(1) {
         Some(hst) => hst,
         None => {
-            let my_name = env::args().nth(0).unwrap();
+            let my_name = env::args().next().unwrap();
             panic!("Usage: {} [host][password]", my_name)
         }
     };
 
-    let password = match env::args().nth(2) {
-        Some(pwd) => pwd,
-        None => String::from(""),
-    };
+    let password = env::args().nth(2).unwrap_or_default();
 
-    let device: PjlinkDevice = if password != "" {
+    let device: PjlinkDevice = if !password.is_empty() {
         PjlinkDevice::new_with_password(&host, &password).unwrap()
     } else {
         PjlinkDevice::new(&host).unwrap()
@@ -99,13 +96,11 @@ fn main() {
 
     match device.get_lamp() {
         Ok(response) => {
-            let mut lamp_count = 1;
-            for lamp in response.iter() {
+            for (lamp_count, lamp) in (1..).zip(response.iter()) {
                 println!(
                     "{} Lamp {}: Hours: {} On: {}",
                     host, lamp_count, lamp.hours, lamp.on
                 );
-                lamp_count += 1;
             }
         }
         Err(err) => println!("{} Lamp: error occurred: {}", host, err),
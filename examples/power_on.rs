@@ -22,17 +22,14 @@ fn main() {
     let host = match env::args().nth(1) {
         Some(hst) => hst,
         None => {
-            let my_name = env::args().nth(0).unwrap();
+            let my_name = env::args().next().unwrap();
             panic!("Usage: {} [host][password]", my_name)
         }
     };
 
-    let password = match env::args().nth(2) {
-        Some(pwd) => pwd,
-        None => String::from("")
-    };
+    let password = env::args().nth(2).unwrap_or_default();
 
-    let device: PjlinkDevice = if password != "" {
+    let device: PjlinkDevice = if !password.is_empty() {
         PjlinkDevice::new_with_password(&host, &password).unwrap()
     } else {
         PjlinkDevice::new(&host).unwrap()
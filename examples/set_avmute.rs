@@ -17,10 +17,10 @@ extern crate pjlink;
 use pjlink::{AvMute, PjlinkDevice};
 use std::env;
 
-static USAGE: &'static str = "[host][video mute (true, false)][audio mute (true, false)][password]";
+static USAGE: &str = "[host][video mute (true, false)][audio mute (true, false)][password]";
 
 fn main() {
-    let my_name = env::args().nth(0).unwrap();
+    let my_name = env::args().next().unwrap();
 
     let host = match env::args().nth(1) {
         Some(hst) => hst,
@@ -29,12 +29,9 @@ fn main() {
         }
     };
 
-    let password = match env::args().nth(4) {
-        Some(pwd) => pwd,
-        None => String::from(""),
-    };
+    let password = env::args().nth(4).unwrap_or_default();
 
-    let device: PjlinkDevice = if password != "" {
+    let device: PjlinkDevice = if !password.is_empty() {
         PjlinkDevice::new_with_password(&host, &password).unwrap()
     } else {
         PjlinkDevice::new(&host).unwrap()
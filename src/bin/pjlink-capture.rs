@@ -0,0 +1,100 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+use pjlink::{DeviceProfile, Password, PjlinkDevice};
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+static USAGE: &str = "host output [--port port] [--password-env variable | --password-file file]";
+
+fn usage(my_name: &str, problem: &str) -> ! {
+    eprintln!("{}", problem);
+    eprintln!("Usage: {} {}", my_name, USAGE);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(my_name: &str, arg: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => usage(my_name, &format!("Invalid value for {}: {}", arg, value)),
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let my_name = args.next().unwrap_or_default();
+    let (host, output) = match (args.next(), args.next()) {
+        (Some(host), Some(output)) if !host.starts_with("--") && !output.starts_with("--") => {
+            (host, output)
+        }
+        _ => usage(&my_name, "Missing host or output file"),
+    };
+
+    let mut port = pjlink::PORT;
+    let mut password = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(&my_name, &format!("Missing value for {}", arg)),
+        };
+        match arg.as_str() {
+            "--port" => port = parse(&my_name, &arg, &value),
+            "--password-env" => password = Some(Password::from_env(&value)),
+            "--password-file" => password = Some(Password::from_file(&value)),
+            _ => usage(&my_name, &format!("Unknown option {}", arg)),
+        }
+    }
+
+    let mut builder = PjlinkDevice::builder(&host).port(port);
+    match password {
+        Some(Ok(password)) => builder = builder.password(password),
+        Some(Err(err)) => {
+            eprintln!("Unable to load the password: {}", err);
+            process::exit(1);
+        }
+        None => {}
+    }
+    let device = match builder.build() {
+        Ok(device) => device,
+        Err(err) => usage(&my_name, &err.to_string()),
+    };
+
+    // Failed commands are reported and left out of the profile
+    let (profile, failures) = DeviceProfile::capture_partial(&device);
+    for (request, err) in &failures {
+        eprintln!("{} {}: error occurred: {}", host, request, err);
+    }
+    if profile.iter().next().is_none() {
+        eprintln!("{} Capture: no responses captured", host);
+        process::exit(1);
+    }
+
+    if let Err(err) = profile.save(&output) {
+        eprintln!("Unable to save {}: {}", output, err);
+        process::exit(1);
+    }
+    println!(
+        "{} Captured {} responses to {}, {} failed",
+        host,
+        profile.iter().count(),
+        output,
+        failures.len()
+    );
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+}
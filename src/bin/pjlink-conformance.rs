@@ -0,0 +1,528 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Probes a PJLink device and prints a pass/fail report of how closely it follows the
+// specification. Only queries, commands that set the state the device is already in,
+// and commands with invalid parameters are sent, so the device is left as it was.
+
+extern crate pjlink;
+
+use pjlink::{connect_within, Password, PjlinkDevice};
+use std::env;
+use std::io::prelude::*;
+use std::io::Error;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+static USAGE: &str = "host [--port port] [--password-env variable | --password-file file]";
+
+// Replies slower than this fail the timing check
+const MAX_RESPONSE_TIME: Duration = Duration::from_secs(2);
+
+// How long to wait for the greeting
+const GREETING_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn usage(my_name: &str, problem: &str) -> ! {
+    eprintln!("{}", problem);
+    eprintln!("Usage: {} {}", my_name, USAGE);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(my_name: &str, arg: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => usage(my_name, &format!("Invalid value for {}: {}", arg, value)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+struct Check {
+    name: String,
+    outcome: Outcome,
+    detail: String,
+    time: Option<Duration>,
+}
+
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, name: &str, outcome: Outcome, detail: &str, time: Option<Duration>) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            outcome,
+            detail: detail.to_string(),
+            time,
+        });
+    }
+
+    fn pass(&mut self, name: &str, detail: &str) {
+        self.add(name, Outcome::Pass, detail, None);
+    }
+
+    fn fail(&mut self, name: &str, detail: &str) {
+        self.add(name, Outcome::Fail, detail, None);
+    }
+
+    fn skip(&mut self, name: &str, detail: &str) {
+        self.add(name, Outcome::Skip, detail, None);
+    }
+
+    fn count(&self, outcome: Outcome) -> usize {
+        self.checks.iter().filter(|c| c.outcome == outcome).count()
+    }
+
+    fn print(&self) {
+        for check in &self.checks {
+            let outcome = match check.outcome {
+                Outcome::Pass => "PASS",
+                Outcome::Fail => "FAIL",
+                Outcome::Skip => "SKIP",
+            };
+            let time = match check.time {
+                Some(time) => format!("{:>6} ms", time.as_millis()),
+                None => String::new(),
+            };
+            // Garbage from the device is shown escaped
+            let detail: String = check
+                .detail
+                .chars()
+                .map(|c| match c {
+                    ' '..='~' => c.to_string(),
+                    _ => c.escape_default().to_string(),
+                })
+                .collect();
+            println!("{}  {:<16} {:>9}  {}", outcome, check.name, time, detail);
+        }
+        println!();
+        println!(
+            "{} passed, {} failed, {} skipped",
+            self.count(Outcome::Pass),
+            self.count(Outcome::Fail),
+            self.count(Outcome::Skip)
+        );
+    }
+}
+
+// Checks a reply value against the specification
+type Validator = fn(&str) -> bool;
+
+struct Probe<'a> {
+    device: &'a PjlinkDevice,
+    report: Report,
+}
+
+impl<'a> Probe<'a> {
+    // Send one command and check the framing of the reply, its value, and that any
+    // error code is one the specification allows for it. Returns the value if the
+    // device answered with one.
+    fn command(
+        &mut self,
+        class: u8,
+        command: &str,
+        valid: Validator,
+        allowed_errors: &[&str],
+    ) -> Option<String> {
+        let name = format!("%{}{}", class, command);
+        let start = Instant::now();
+        let result = self.device.send_class_command(class, command);
+        let time = start.elapsed();
+
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                self.report
+                    .add(&name, Outcome::Fail, &err.to_string(), Some(time));
+                return None;
+            }
+        };
+
+        let prefix = format!("%{}{}=", class, &command[..4]);
+        let (outcome, value) = if !response.starts_with(&prefix) {
+            (Outcome::Fail, None)
+        } else {
+            let value = &response[prefix.len()..];
+            if value.len() == 4 && value.starts_with("ERR") {
+                if allowed_errors.contains(&value) {
+                    (Outcome::Pass, None)
+                } else {
+                    (Outcome::Fail, None)
+                }
+            } else if valid(value) {
+                (Outcome::Pass, Some(value.to_string()))
+            } else {
+                (Outcome::Fail, None)
+            }
+        };
+        self.report.add(&name, outcome, &response, Some(time));
+        value
+    }
+
+    // Send a command that has to be answered with this error code
+    fn error(&mut self, class: u8, command: &str, code: &str) {
+        let name = format!("%{}{}", class, command);
+        let start = Instant::now();
+        let result = self.device.send_class_command(class, command);
+        let time = start.elapsed();
+
+        match result {
+            Ok(response) => {
+                let outcome = if response.ends_with(&format!("={}", code)) {
+                    Outcome::Pass
+                } else {
+                    Outcome::Fail
+                };
+                let detail = format!("{} (expected {})", response, code);
+                self.report.add(&name, outcome, &detail, Some(time));
+            }
+            Err(err) => self
+                .report
+                .add(&name, Outcome::Fail, &err.to_string(), Some(time)),
+        }
+    }
+}
+
+fn digits(value: &str, len: usize, max: char) -> bool {
+    value.len() == len && value.chars().all(|c| c >= '0' && c <= max)
+}
+
+fn is_text32(value: &str) -> bool {
+    value.len() <= 32
+}
+
+fn is_text64(value: &str) -> bool {
+    value.len() <= 64
+}
+
+fn is_text128(value: &str) -> bool {
+    value.len() <= 128
+}
+
+fn is_power(value: &str) -> bool {
+    digits(value, 1, '3')
+}
+
+fn is_input(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 2 && (b'1'..=b'6').contains(&bytes[0]) && bytes[1].is_ascii_alphanumeric()
+}
+
+fn is_class1_input(value: &str) -> bool {
+    is_input(value) && (b'1'..=b'9').contains(&value.as_bytes()[1])
+}
+
+fn is_avmute(value: &str) -> bool {
+    ["10", "11", "20", "21", "30", "31"].contains(&value)
+}
+
+fn is_error_status(value: &str) -> bool {
+    digits(value, 6, '2')
+}
+
+fn is_lamps(value: &str) -> bool {
+    let fields: Vec<&str> = value.split(' ').collect();
+    fields.len().is_multiple_of(2)
+        && fields.len() <= 16
+        && fields.chunks(2).all(|lamp| {
+            !lamp[0].is_empty()
+                && lamp[0].len() <= 5
+                && lamp[0].chars().all(|c| c.is_ascii_digit())
+                && (lamp[1] == "0" || lamp[1] == "1")
+        })
+}
+
+fn is_class1_inputs(value: &str) -> bool {
+    value.split(' ').all(is_class1_input)
+}
+
+fn is_inputs(value: &str) -> bool {
+    value.split(' ').all(is_input)
+}
+
+fn is_class(value: &str) -> bool {
+    value == "1" || value == "2"
+}
+
+fn is_resolution(value: &str) -> bool {
+    let mut parts = value.split('x');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(width), Some(height), None) => {
+            !width.is_empty()
+                && !height.is_empty()
+                && width
+                    .chars()
+                    .chain(height.chars())
+                    .all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+fn is_input_resolution(value: &str) -> bool {
+    value == "-" || value == "*" || is_resolution(value)
+}
+
+fn is_hours(value: &str) -> bool {
+    !value.is_empty() && value.len() <= 5 && value.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_freeze(value: &str) -> bool {
+    value == "0" || value == "1"
+}
+
+fn is_ok(value: &str) -> bool {
+    value == "OK"
+}
+
+// Read the greeting on a raw connection and check its format
+fn check_greeting(host: &str, port: u16, report: &mut Report) -> Result<bool, Error> {
+    let start = Instant::now();
+    let mut stream = connect_within(host, port, GREETING_TIMEOUT)?;
+
+    let mut greeting = Vec::new();
+    let mut byte = [0];
+    while greeting.len() < 32 {
+        stream.read_exact(&mut byte)?;
+        if byte[0] == b'\r' {
+            break;
+        }
+        greeting.push(byte[0]);
+    }
+    let time = start.elapsed();
+    let greeting = String::from_utf8_lossy(&greeting).to_string();
+
+    let auth = if greeting == "PJLINK 0" {
+        Some(false)
+    } else if greeting.len() == 17
+        && greeting.starts_with("PJLINK 1 ")
+        && greeting[9..].chars().all(|c| c.is_ascii_hexdigit())
+    {
+        Some(true)
+    } else {
+        None
+    };
+
+    let outcome = if auth.is_some() {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    report.add("greeting", outcome, &greeting, Some(time));
+    Ok(auth == Some(true))
+}
+
+fn main() {
+    let mut args = env::args();
+    let my_name = args.next().unwrap_or_default();
+    let host = match args.next() {
+        Some(ref host) if !host.starts_with("--") => host.clone(),
+        _ => usage(&my_name, "Missing host"),
+    };
+
+    let mut port = pjlink::PORT;
+    let mut password = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(&my_name, &format!("Missing value for {}", arg)),
+        };
+        match arg.as_str() {
+            "--port" => port = parse(&my_name, &arg, &value),
+            "--password-env" => password = Some(Password::from_env(&value)),
+            "--password-file" => password = Some(Password::from_file(&value)),
+            _ => usage(&my_name, &format!("Unknown option {}", arg)),
+        }
+    }
+    let password = match password {
+        Some(Ok(password)) => Some(password),
+        Some(Err(err)) => {
+            eprintln!("Unable to load the password: {}", err);
+            process::exit(1);
+        }
+        None => None,
+    };
+
+    let mut report = Report { checks: Vec::new() };
+    println!("PJLink conformance report for {}:{}", host, port);
+    println!();
+
+    let auth = match check_greeting(&host, port, &mut report) {
+        Ok(auth) => auth,
+        Err(err) => {
+            report.fail("greeting", &err.to_string());
+            report.print();
+            process::exit(1);
+        }
+    };
+
+    // Authentication
+    let mut builder = PjlinkDevice::builder(&host).port(port);
+    let has_password = password.is_some();
+    if let Some(password) = password {
+        builder = builder.password(password);
+    }
+    let device = match builder.build() {
+        Ok(device) => device,
+        Err(err) => usage(&my_name, &err.to_string()),
+    };
+
+    if auth {
+        if !has_password {
+            report.skip(
+                "auth",
+                "The device requires a password, give one to run the remaining checks",
+            );
+            report.print();
+            process::exit(1);
+        }
+
+        let wrong = PjlinkDevice::builder(&host)
+            .port(port)
+            .password("conformance-wrong-password")
+            .build()
+            .unwrap();
+        match wrong.send_command("POWR ?") {
+            Ok(ref response) if response == "PJLINK ERRA" => {
+                report.pass("auth wrong", "Wrong password answered with PJLINK ERRA")
+            }
+            Ok(response) => report.fail(
+                "auth wrong",
+                &format!("Wrong password answered with {}", response),
+            ),
+            Err(err) => report.fail("auth wrong", &err.to_string()),
+        }
+
+        match device.send_command("POWR ?") {
+            Ok(ref response) if response == "PJLINK ERRA" => {
+                report.fail("auth", "The password was rejected");
+                report.print();
+                process::exit(1);
+            }
+            Ok(_) => report.pass("auth", "The password was accepted"),
+            Err(err) => report.fail("auth", &err.to_string()),
+        }
+    } else if has_password {
+        report.skip(
+            "auth",
+            "The device does not use authentication, the password was not used",
+        );
+    }
+
+    let mut probe = Probe {
+        device: &device,
+        report,
+    };
+
+    // Class 1
+    let power = probe.command(1, "POWR ?", is_power, &["ERR3", "ERR4"]);
+    if let Some(ref power) = power {
+        match power.as_str() {
+            "0" | "1" => {
+                probe.command(1, &format!("POWR {}", power), is_ok, &["ERR3", "ERR4"]);
+            }
+            _ => probe
+                .report
+                .skip("%1POWR set", "The device is warming up or cooling down"),
+        }
+    }
+    let input = probe.command(1, "INPT ?", is_class1_input, &["ERR3", "ERR4"]);
+    match input {
+        Some(ref input) => {
+            probe.command(1, &format!("INPT {}", input), is_ok, &["ERR3", "ERR4"]);
+        }
+        None => probe
+            .report
+            .skip("%1INPT set", "The current input is not available"),
+    }
+    let mute = probe.command(1, "AVMT ?", is_avmute, &["ERR3", "ERR4"]);
+    match mute {
+        Some(ref mute) => {
+            probe.command(1, &format!("AVMT {}", mute), is_ok, &["ERR3", "ERR4"]);
+        }
+        None => probe
+            .report
+            .skip("%1AVMT set", "The current AV mute is not available"),
+    }
+    probe.command(1, "ERST ?", is_error_status, &["ERR3", "ERR4"]);
+    probe.command(1, "LAMP ?", is_lamps, &["ERR1", "ERR3", "ERR4"]);
+    probe.command(1, "INST ?", is_class1_inputs, &["ERR3", "ERR4"]);
+    probe.command(1, "NAME ?", is_text64, &["ERR3", "ERR4"]);
+    probe.command(1, "INF1 ?", is_text32, &["ERR3", "ERR4"]);
+    probe.command(1, "INF2 ?", is_text32, &["ERR3", "ERR4"]);
+    probe.command(1, "INFO ?", is_text32, &["ERR3", "ERR4"]);
+    let class = probe.command(1, "CLSS ?", is_class, &["ERR3", "ERR4"]);
+
+    // Error codes
+    probe.error(1, "XXXX ?", "ERR1");
+    probe.error(1, "POWR 7", "ERR2");
+    probe.error(1, "AVMT 99", "ERR2");
+    probe.error(1, "INPT 99", "ERR2");
+
+    // Class 2
+    if class.as_deref() == Some("2") {
+        probe.command(2, "SNUM ?", is_text32, &["ERR3", "ERR4"]);
+        probe.command(2, "SVER ?", is_text32, &["ERR3", "ERR4"]);
+        let inputs = probe.command(2, "INST ?", is_inputs, &["ERR3", "ERR4"]);
+        for input in inputs.unwrap_or_default().split_whitespace() {
+            probe.command(2, &format!("INNM ?{}", input), is_text64, &["ERR3", "ERR4"]);
+        }
+        let input = probe.command(2, "INPT ?", is_input, &["ERR3", "ERR4"]);
+        if let Some(ref input) = input {
+            probe.command(2, &format!("INPT {}", input), is_ok, &["ERR3", "ERR4"]);
+        }
+        probe.command(2, "IRES ?", is_input_resolution, &["ERR3", "ERR4"]);
+        probe.command(2, "RRES ?", is_resolution, &["ERR3", "ERR4"]);
+        probe.command(2, "FILT ?", is_hours, &["ERR1", "ERR3", "ERR4"]);
+        probe.command(2, "RLMP ?", is_text128, &["ERR1", "ERR3", "ERR4"]);
+        probe.command(2, "RFIL ?", is_text128, &["ERR1", "ERR3", "ERR4"]);
+        let freeze = probe.command(2, "FREZ ?", is_freeze, &["ERR1", "ERR3", "ERR4"]);
+        if let Some(ref freeze) = freeze {
+            probe.command(2, &format!("FREZ {}", freeze), is_ok, &["ERR3", "ERR4"]);
+        }
+        probe.error(2, "FREZ 7", "ERR2");
+    } else if class.is_some() {
+        probe.error(2, "SNUM ?", "ERR1");
+    }
+
+    // Timing
+    let mut report = probe.report;
+    let slowest = report
+        .checks
+        .iter()
+        .filter_map(|check| check.time.map(|time| (time, check.name.clone())))
+        .max();
+    match slowest {
+        Some((time, name)) if time > MAX_RESPONSE_TIME => {
+            report.fail("timing", &format!("{} took {} ms", name, time.as_millis()))
+        }
+        Some((time, name)) => report.pass(
+            "timing",
+            &format!("Slowest reply {} in {} ms", name, time.as_millis()),
+        ),
+        None => report.skip("timing", "No replies were timed"),
+    }
+
+    report.print();
+    if report.count(Outcome::Fail) > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+use pjlink::server::{Emulator, EmulatorConfig, Farm, FaultProfile, Server, UdpServer};
+use pjlink::{DeviceProfile, Password};
+use std::env;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+static USAGE: &str = "[--bind address] [--port port] [--class 1|2] [--name name]
+    [--password-env variable | --password-file file] [--warmup seconds] [--cooling seconds]
+    [--faults rules] [--seed seed] [--profile file]
+    [--udp-port port] [--controller address:port] [--mac address]";
+
+fn usage(my_name: &str, problem: &str) -> ! {
+    eprintln!("{}", problem);
+    eprintln!("Usage: {} {}", my_name, USAGE);
+    eprintln!("       {} --config file", my_name);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(my_name: &str, arg: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => usage(my_name, &format!("Invalid value for {}: {}", arg, value)),
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let my_name = args.next().unwrap_or_default();
+
+    let mut bind = String::from("0.0.0.0");
+    let mut port = pjlink::PORT;
+    let mut config = EmulatorConfig::default();
+    let mut password = None;
+    let mut faults = String::new();
+    let mut seed = 0;
+    let mut profile = None;
+    let mut udp_port = None;
+    let mut controller = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            match args.next() {
+                Some(path) => run_farm(&path),
+                None => usage(&my_name, "Missing value for --config"),
+            }
+        }
+
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(&my_name, &format!("Missing value for {}", arg)),
+        };
+
+        match arg.as_str() {
+            "--bind" => bind = value.clone(),
+            "--port" => port = parse(&my_name, &arg, &value),
+            "--class" => {
+                config.class = match parse(&my_name, &arg, &value) {
+                    class @ 1..=2 => class,
+                    _ => usage(&my_name, &format!("Invalid value for {}: {}", arg, value)),
+                }
+            }
+            "--name" => config.name = value.clone(),
+            "--password-env" => password = Some(Password::from_env(&value)),
+            "--password-file" => password = Some(Password::from_file(&value)),
+            "--warmup" => config.warmup = Duration::from_secs(parse(&my_name, &arg, &value)),
+            "--cooling" => config.cooling = Duration::from_secs(parse(&my_name, &arg, &value)),
+            "--faults" => faults = value.clone(),
+            "--seed" => seed = parse(&my_name, &arg, &value),
+            "--udp-port" => udp_port = Some(parse::<u16>(&my_name, &arg, &value)),
+            "--controller" => controller = Some(parse(&my_name, &arg, &value)),
+            "--mac" => config.mac_address = value.clone(),
+            "--profile" => match DeviceProfile::load(&value) {
+                Ok(loaded) => {
+                    config = EmulatorConfig::from_profile(&loaded);
+                    profile = Some(loaded);
+                }
+                Err(err) => {
+                    eprintln!("Unable to load the profile {}: {}", value, err);
+                    process::exit(1);
+                }
+            },
+            _ => usage(&my_name, &format!("Unknown option {}", arg)),
+        }
+    }
+
+    let emulator = Emulator::new(config);
+    let server = match Server::bind((bind.as_str(), port), emulator.clone()) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Unable to listen on {}:{}: {}", bind, port, err);
+            process::exit(1);
+        }
+    };
+
+    let server = match FaultProfile::parse(seed, &faults) {
+        Ok(profile) => server.faults(profile),
+        Err(err) => usage(&my_name, &err.to_string()),
+    };
+
+    let server = match profile {
+        Some(profile) => server.replay(profile),
+        None => server,
+    };
+
+    let server = match password {
+        Some(Ok(password)) => server.password(password).unwrap(),
+        Some(Err(err)) => {
+            eprintln!("Unable to load the password: {}", err);
+            process::exit(1);
+        }
+        None => server,
+    };
+
+    // Kept until the process exits
+    let _udp = udp_port.map(|udp_port| {
+        let udp = match UdpServer::bind((bind.as_str(), udp_port), emulator) {
+            Ok(udp) => udp,
+            Err(err) => {
+                eprintln!("Unable to listen on UDP {}:{}: {}", bind, udp_port, err);
+                process::exit(1);
+            }
+        };
+        let udp = match controller {
+            Some(controller) => udp.controller(controller),
+            None => udp,
+        };
+        println!("PJLink UDP listening on {}", udp.local_addr().unwrap());
+        udp.spawn().unwrap()
+    });
+
+    println!(
+        "PJLink emulator listening on {}",
+        server.local_addr().unwrap()
+    );
+    if let Err(err) = server.run() {
+        eprintln!("An error occurred: {}", err);
+        process::exit(1);
+    }
+}
+
+// Serve every device in a farm config file until the process is killed
+fn run_farm(path: &str) -> ! {
+    let farm = match Farm::load(path) {
+        Ok(farm) => farm,
+        Err(err) => {
+            eprintln!("Unable to start the devices in {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    for device in farm.devices() {
+        println!("{} listening on {}", device.name(), device.local_addr());
+    }
+    println!("PJLink emulator serving {} devices", farm.devices().len());
+    loop {
+        thread::park();
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Runs control macros written for pjlink::Script against a device.
+
+extern crate pjlink;
+
+use pjlink::{Password, PjlinkDevice, Script};
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+static USAGE: &str =
+    "run script host [--port port] [--password-env variable | --password-file file]";
+
+fn usage(my_name: &str, problem: &str) -> ! {
+    eprintln!("{}", problem);
+    eprintln!("Usage: {} {}", my_name, USAGE);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(my_name: &str, arg: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => usage(my_name, &format!("Invalid value for {}: {}", arg, value)),
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let my_name = args.next().unwrap_or_default();
+    match args.next() {
+        Some(ref command) if command == "run" => (),
+        Some(command) => usage(&my_name, &format!("Unknown command {}", command)),
+        None => usage(&my_name, "Missing command"),
+    }
+    let (path, host) = match (args.next(), args.next()) {
+        (Some(path), Some(host)) => (path, host),
+        _ => usage(&my_name, "Missing script or host"),
+    };
+
+    let mut port = pjlink::PORT;
+    let mut password = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(&my_name, &format!("Missing value for {}", arg)),
+        };
+        match arg.as_str() {
+            "--port" => port = parse(&my_name, &arg, &value),
+            "--password-env" => password = Some(Password::from_env(&value)),
+            "--password-file" => password = Some(Password::from_file(&value)),
+            _ => usage(&my_name, &format!("Unknown option {}", arg)),
+        }
+    }
+
+    // Check the whole script before sending anything
+    let script = match Script::load(&path) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    let mut builder = PjlinkDevice::builder(&host).port(port);
+    match password {
+        Some(Ok(password)) => builder = builder.password(password),
+        Some(Err(err)) => {
+            eprintln!("Unable to load the password: {}", err);
+            process::exit(1);
+        }
+        None => (),
+    }
+    let device = match builder.build() {
+        Ok(device) => device,
+        Err(err) => usage(&my_name, &err.to_string()),
+    };
+
+    let result = script.run(&device, |message| {
+        if message.warning {
+            eprintln!("{}:{}: warning: {}", path, message.line, message.text);
+        } else {
+            println!("{}", message.text);
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    }
+}
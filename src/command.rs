@@ -0,0 +1,109 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+
+use {pjlink_error, PjlinkDevice};
+
+/// A command with a typed response, for commands the crate has no method for such as
+/// vendor extensions. It is run by `PjlinkDevice::execute` with the same authentication,
+/// framing, error mapping and retries as the built-in commands.
+///
+/// ```
+/// use std::io::{Error, ErrorKind};
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{PjlinkCommand, PjlinkDevice};
+///
+/// // FILT ? (class 2), the hours the filter has been used
+/// struct FilterHours;
+///
+/// impl PjlinkCommand for FilterHours {
+///     type Output = u32;
+///
+///     fn class(&self) -> u8 {
+///         2
+///     }
+///
+///     fn encode(&self) -> String {
+///         String::from("FILT ?")
+///     }
+///
+///     fn decode(&self, value: &str) -> Result<u32, Error> {
+///         value.parse().map_err(|_| Error::new(ErrorKind::InvalidData, value))
+///     }
+/// }
+///
+/// let emulator = Emulator::new(EmulatorConfig {
+///     class: 2,
+///     filter_hours: 120,
+///     ..EmulatorConfig::default()
+/// });
+/// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(device.execute(&FilterHours).unwrap(), 120);
+/// ```
+pub trait PjlinkCommand {
+    /// The typed response
+    type Output;
+
+    /// The class prefix the command is sent with, 1 by default
+    fn class(&self) -> u8 {
+        1
+    }
+
+    /// The command body without the class prefix, e.g. "POWR 1" or "SNUM ?".
+    /// The first four characters are the command name the reply has to echo.
+    fn encode(&self) -> String;
+
+    /// Turn the value of the reply, everything after the '=', into the response.
+    /// ERR replies never get here, they are returned as errors carrying a
+    /// [pjlink::PjlinkErrorCode](enum.PjlinkErrorCode.html).
+    fn decode(&self, value: &str) -> Result<Self::Output, Error>;
+}
+
+impl PjlinkDevice {
+    /// Run a [pjlink::PjlinkCommand](trait.PjlinkCommand.html) and decode its response
+    pub fn execute<C: PjlinkCommand + ?Sized>(&self, command: &C) -> Result<C::Output, Error> {
+        let class = command.class();
+        let body = command.encode();
+        let response = self.send_class_command(class, &body)?;
+
+        if let Some(error) = response
+            .strip_prefix("PJLINK ")
+            .filter(|error| error.len() == 4)
+        {
+            return Err(pjlink_error(error));
+        }
+
+        let expected = format!("%{}{}=", class, body.get(0..4).unwrap_or(&body));
+        let value = match response.strip_prefix(expected.as_str()) {
+            Some(value) => value,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Got a response we didn't expect: {}", response),
+                ))
+            }
+        };
+
+        if value.len() == 4 && value.starts_with("ERR") {
+            return Err(pjlink_error(value));
+        }
+        command.decode(value)
+    }
+}
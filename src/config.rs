@@ -0,0 +1,117 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A small INI style format shared by the configuration files in the crate:
+//
+//     # comment
+//     [section name]
+//     key = value
+//
+// Keys before the first section belong to a section with an empty name. Keys keep
+// their case, so that they can name devices, and settings are looked up without case.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+pub(crate) struct Section {
+    pub(crate) name: String,
+    pub(crate) line: usize,
+    pub(crate) entries: Vec<Entry>,
+}
+
+pub(crate) struct Entry {
+    pub(crate) key: String,
+    pub(crate) value: String,
+    pub(crate) line: usize,
+}
+
+impl Section {
+    // The last value of a key, so later lines override earlier ones
+    pub(crate) fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().rev().find(|entry| entry.is(key))
+    }
+}
+
+impl Entry {
+    // Is this the setting with this lowercase name
+    pub(crate) fn is(&self, key: &str) -> bool {
+        self.key.eq_ignore_ascii_case(key)
+    }
+
+    pub(crate) fn parse<T: FromStr>(&self) -> Result<T, Error> {
+        self.value
+            .parse()
+            .map_err(|_| self.invalid(&format!("invalid value for {}", self.key)))
+    }
+
+    pub(crate) fn invalid(&self, problem: &str) -> Error {
+        invalid(self.line, problem)
+    }
+}
+
+// An InvalidData error pointing at a line of the file
+pub(crate) fn invalid(line: usize, problem: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Line {}: {}", line, problem),
+    )
+}
+
+pub(crate) fn parse(text: &str) -> Result<Vec<Section>, Error> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        line: 0,
+        entries: Vec::new(),
+    }];
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.len() < 3 {
+                return Err(invalid(number, "expected [section name]"));
+            }
+            sections.push(Section {
+                name: line[1..line.len() - 1].trim().to_string(),
+                line: number,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        match line.find('=') {
+            Some(equals) => sections.last_mut().unwrap().entries.push(Entry {
+                key: line[..equals].trim().to_string(),
+                value: line[equals + 1..].trim().to_string(),
+                line: number,
+            }),
+            None => return Err(invalid(number, "expected key = value")),
+        }
+    }
+
+    Ok(sections)
+}
+
+pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Section>, Error> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    parse(&text)
+}
@@ -0,0 +1,222 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {parse_power_status, pjlink_error, Fleet, PjlinkDevice, PjlinkErrorCode, PowerStatus};
+
+/// What `Fleet::emergency` does to every device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emergency {
+    /// Blank the picture and mute the sound (AVMT 31)
+    AllMute,
+    /// Turn the device off (POWR 0)
+    AllOff,
+}
+
+impl Emergency {
+    /// The command sent to every device
+    pub fn command(self) -> &'static str {
+        match self {
+            Emergency::AllMute => "AVMT 31",
+            Emergency::AllOff => "POWR 0",
+        }
+    }
+}
+
+/// Timeouts and retries for `Fleet::emergency_with`
+#[derive(Clone, Copy, Debug)]
+pub struct EmergencyOptions {
+    /// How long connecting to a device and each read and write may take. Asking a
+    /// device that answered ERR3 for its power status has to fit in the same time.
+    pub timeout: Duration,
+    /// Total number of rounds, every round after the first only goes to the
+    /// devices that have not confirmed yet
+    pub attempts: u32,
+    /// The wait between rounds
+    pub retry_delay: Duration,
+}
+
+impl Default for EmergencyOptions {
+    fn default() -> EmergencyOptions {
+        EmergencyOptions {
+            timeout: Duration::from_millis(500),
+            attempts: 3,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Which devices confirmed an emergency command, keyed like `FleetResults`
+#[derive(Debug, Default)]
+pub struct EmergencyReport {
+    /// The devices that answered OK
+    pub confirmed: Vec<String>,
+    /// The devices that did not, with the error of their last attempt
+    pub unconfirmed: BTreeMap<String, Error>,
+    /// How long it took until the last device answered or gave up
+    pub elapsed: Duration,
+}
+
+impl EmergencyReport {
+    /// Did every device confirm
+    pub fn is_complete(&self) -> bool {
+        self.unconfirmed.is_empty()
+    }
+}
+
+impl Fleet {
+    /// Send an emergency command to every device at once with the default options
+    pub fn emergency(&self, emergency: Emergency) -> EmergencyReport {
+        self.emergency_with(emergency, EmergencyOptions::default())
+    }
+
+    /// Send an emergency command to every device at once, on a thread each and ignoring
+    /// the fleet's concurrency and deadline. The devices' retry policies are not used,
+    /// devices that did not answer OK are tried again in the next round instead.
+    ///
+    /// A device that answers ERR3 (unavailable) is asked for its power status, and
+    /// counts as confirmed if it is off or cooling down: there is no picture or sound
+    /// to mute and it is already on its way to standby.
+    ///
+    /// ```
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{AvMute, Emergency, Fleet, PjlinkDevice, PowerStatus};
+    ///
+    /// let mut fleet = Fleet::new();
+    /// let mut servers = Vec::new();
+    /// for _ in 0..3 {
+    ///     let emulator = Emulator::new(EmulatorConfig {
+    ///         power: PowerStatus::On,
+    ///         ..EmulatorConfig::default()
+    ///     });
+    ///     let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    ///     let device = PjlinkDevice::builder("127.0.0.1")
+    ///         .port(server.local_addr().port())
+    ///         .build()
+    ///         .unwrap();
+    ///     fleet.add(device);
+    ///     servers.push(server);
+    /// }
+    ///
+    /// let report = fleet.emergency(Emergency::AllMute);
+    /// assert!(report.is_complete());
+    /// for device in fleet.devices() {
+    ///     assert_eq!(device.get_avmute().unwrap(), AvMute { video: true, audio: true });
+    /// }
+    /// ```
+    pub fn emergency_with(
+        &self,
+        emergency: Emergency,
+        options: EmergencyOptions,
+    ) -> EmergencyReport {
+        let started = Instant::now();
+        let mut report = EmergencyReport::default();
+        let mut stragglers: Vec<Arc<PjlinkDevice>> = self.devices().to_vec();
+
+        for attempt in 0..options.attempts.max(1) {
+            if attempt > 0 {
+                if stragglers.is_empty() {
+                    break;
+                }
+                thread::sleep(options.retry_delay);
+            }
+
+            let threads: Vec<_> = stragglers
+                .drain(..)
+                .map(|device| {
+                    let timeout = options.timeout;
+                    let worker = thread::spawn({
+                        let device = Arc::clone(&device);
+                        move || device.send_emergency(emergency, timeout)
+                    });
+                    (device, worker)
+                })
+                .collect();
+
+            for (device, worker) in threads {
+                let key = Fleet::key(&device);
+                let result = worker
+                    .join()
+                    .unwrap_or_else(|_| Err(Error::other("The emergency command panicked")));
+                match result {
+                    Ok(()) => {
+                        report.unconfirmed.remove(&key);
+                        report.confirmed.push(key);
+                    }
+                    Err(e) => {
+                        report.unconfirmed.insert(key, e);
+                        stragglers.push(device);
+                    }
+                }
+            }
+        }
+
+        report.elapsed = started.elapsed();
+        report
+    }
+}
+
+impl PjlinkDevice {
+    // Send an emergency command once, Ok only if the device answers OK or
+    // is already off or cooling down
+    fn send_emergency(&self, emergency: Emergency, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        let command = emergency.command();
+        let result = self
+            .open_session_within(timeout)?
+            .send(1, command)
+            .and_then(|response| check_reply(command, &response));
+
+        match result {
+            Err(ref e) if PjlinkErrorCode::from_error(e) == Some(PjlinkErrorCode::Unavailable) => {
+                // Asking for the power status only gets what is left of the timeout
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::from_secs(0) {
+                    return result.map(|_| ());
+                }
+                let response = self.open_session_within(remaining)?.send(1, "POWR ?")?;
+                let power =
+                    check_reply("POWR ?", &response).and_then(|value| parse_power_status(&value));
+                match power {
+                    Ok(PowerStatus::Off) | Ok(PowerStatus::Cooling) => Ok(()),
+                    _ => result.map(|_| ()),
+                }
+            }
+            _ => result.map(|_| ()),
+        }
+    }
+}
+
+// The value of a reply to command, or the error the device answered with
+fn check_reply(command: &str, response: &str) -> Result<String, Error> {
+    if let Some(error) = response
+        .strip_prefix("PJLINK ")
+        .filter(|error| error.len() == 4)
+    {
+        return Err(pjlink_error(error));
+    }
+    match response.strip_prefix(&format!("%1{}=", &command[0..4])) {
+        Some(value) if value.len() == 4 && value.starts_with("ERR") => Err(pjlink_error(value)),
+        Some(value) if command.ends_with('?') || value == "OK" => Ok(value.to_string()),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Got a response we didn't expect: {}", response),
+        )),
+    }
+}
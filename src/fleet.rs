@@ -0,0 +1,268 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {PjlinkDevice, PORT};
+
+/// The result for every device an operation was run on, keyed by `Fleet::key`
+pub type FleetResults<T> = BTreeMap<String, Result<T, Error>>;
+
+/// Many devices that are operated on together, e.g. the projectors of a room.
+///
+/// An operation is a closure that is run for every device, or a subset of them, on up to
+/// `concurrency` threads at once. The result of each device is returned keyed by its host.
+/// Devices that have not finished by the deadline get a `TimedOut` error and are left to
+/// finish on their own.
+///
+/// ```
+/// use std::time::Duration;
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{Fleet, PjlinkDevice, PowerStatus};
+///
+/// let mut fleet = Fleet::new().concurrency(4).deadline(Duration::from_secs(10));
+/// let mut servers = Vec::new();
+/// for _ in 0..3 {
+///     let emulator = Emulator::new(EmulatorConfig::default());
+///     let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+///     let device = PjlinkDevice::builder("127.0.0.1")
+///         .port(server.local_addr().port())
+///         .build()
+///         .unwrap();
+///     fleet.add(device);
+///     servers.push(server);
+/// }
+///
+/// let results = fleet.run(|device| device.power_on());
+/// assert_eq!(results.len(), 3);
+/// for (host, result) in &results {
+///     assert_eq!(result.as_ref().unwrap(), &PowerStatus::Warmup, "{}", host);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Fleet {
+    devices: Vec<Arc<PjlinkDevice>>,
+    concurrency: usize,
+    deadline: Option<Duration>,
+}
+
+impl Default for Fleet {
+    fn default() -> Fleet {
+        Fleet::new()
+    }
+}
+
+impl Fleet {
+    /// An empty fleet that runs operations on up to 8 devices at once without a deadline
+    pub fn new() -> Fleet {
+        Fleet {
+            devices: Vec::new(),
+            concurrency: 8,
+            deadline: None,
+        }
+    }
+
+    /// How many devices an operation runs on at once, at least 1
+    pub fn concurrency(mut self, concurrency: usize) -> Fleet {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How long an operation may take over all devices
+    pub fn deadline(mut self, deadline: Duration) -> Fleet {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Add a device, replacing a device with the same key
+    pub fn add(&mut self, device: PjlinkDevice) {
+        self.add_shared(Arc::new(device));
+    }
+
+    /// Add a device that is also used elsewhere, replacing a device with the same key
+    pub fn add_shared(&mut self, device: Arc<PjlinkDevice>) {
+        let key = Fleet::key(&device);
+        self.devices.retain(|existing| Fleet::key(existing) != key);
+        self.devices.push(device);
+    }
+
+    /// Remove the device with this key
+    pub fn remove(&mut self, key: &str) -> Option<Arc<PjlinkDevice>> {
+        let index = self
+            .devices
+            .iter()
+            .position(|device| Fleet::key(device) == key)?;
+        Some(self.devices.remove(index))
+    }
+
+    /// The device with this key
+    pub fn device(&self, key: &str) -> Option<&Arc<PjlinkDevice>> {
+        self.devices.iter().find(|device| Fleet::key(device) == key)
+    }
+
+    /// The devices in the order they were added
+    pub fn devices(&self) -> &[Arc<PjlinkDevice>] {
+        &self.devices
+    }
+
+    /// The number of devices
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Does the fleet have no devices
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// The key of a device in the results: its host, followed by the port
+    /// if it does not listen on the standard PJLink port
+    pub fn key(device: &PjlinkDevice) -> String {
+        if device.port == PORT {
+            device.host.clone()
+        } else {
+            format!("{}:{}", device.host, device.port)
+        }
+    }
+
+    /// Run an operation on every device
+    pub fn run<T, F>(&self, operation: F) -> FleetResults<T>
+    where
+        T: Send + 'static,
+        F: Fn(&PjlinkDevice) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        self.run_devices(self.devices.clone(), operation)
+    }
+
+    /// Run an operation on the devices with these keys. A key without a device
+    /// gets a `NotFound` error.
+    pub fn run_on<T, F>(&self, keys: &[&str], operation: F) -> FleetResults<T>
+    where
+        T: Send + 'static,
+        F: Fn(&PjlinkDevice) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        let mut devices = Vec::new();
+        let mut missing = Vec::new();
+        for &key in keys {
+            match self.device(key) {
+                Some(device) => devices.push(Arc::clone(device)),
+                None => missing.push(key),
+            }
+        }
+
+        let mut results = self.run_devices(devices, operation);
+        for key in missing {
+            results.insert(
+                key.to_string(),
+                Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("There is no device {} in the fleet", key),
+                )),
+            );
+        }
+        results
+    }
+
+    // Run an operation on the given devices, which need not be in the fleet
+    pub(crate) fn run_devices<T, F>(
+        &self,
+        devices: Vec<Arc<PjlinkDevice>>,
+        operation: F,
+    ) -> FleetResults<T>
+    where
+        T: Send + 'static,
+        F: Fn(&PjlinkDevice) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        self.run_devices_until(devices, self.deadline_from_now(), operation)
+    }
+
+    // When an operation started now has to be finished by
+    pub(crate) fn deadline_from_now(&self) -> Option<Instant> {
+        self.deadline.map(|deadline| Instant::now() + deadline)
+    }
+
+    // Same as run_devices with a deadline that may have started earlier, e.g. for the whole
+    // of an operation made of several runs. Nothing is started once it has passed.
+    pub(crate) fn run_devices_until<T, F>(
+        &self,
+        devices: Vec<Arc<PjlinkDevice>>,
+        deadline: Option<Instant>,
+        operation: F,
+    ) -> FleetResults<T>
+    where
+        T: Send + 'static,
+        F: Fn(&PjlinkDevice) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        let keys: Vec<String> = devices.iter().map(|device| Fleet::key(device)).collect();
+        let workers = match deadline {
+            Some(deadline) if Instant::now() >= deadline => 0,
+            _ => self.concurrency.min(devices.len()),
+        };
+        let pending = Arc::new(Mutex::new(devices.into_iter().enumerate()));
+        let operation = Arc::new(operation);
+        let (sender, receiver) = channel();
+
+        for _ in 0..workers {
+            let pending = Arc::clone(&pending);
+            let operation = Arc::clone(&operation);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let next = pending.lock().unwrap().next();
+                let (index, device) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                // Once the deadline has passed nobody is listening anymore
+                if sender.send((index, operation(&device))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut results = BTreeMap::new();
+        while results.len() < keys.len() {
+            let received = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => receiver.recv_timeout(remaining),
+                    None => Err(RecvTimeoutError::Timeout),
+                },
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok((index, result)) => {
+                    results.insert(keys[index].clone(), result);
+                }
+                Err(_) => break,
+            }
+        }
+
+        // Stop the workers from picking up devices that would miss the deadline anyway
+        pending.lock().unwrap().by_ref().for_each(drop);
+        for key in keys {
+            results.entry(key).or_insert_with(|| {
+                Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "The device did not finish before the fleet deadline",
+                ))
+            });
+        }
+        results
+    }
+}
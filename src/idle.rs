@@ -0,0 +1,345 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use worker::Worker;
+use {Fleet, PjlinkCommand, PjlinkDevice, PjlinkErrorCode, PowerStatus};
+
+/// When [pjlink::IdleShutdown](struct.IdleShutdown.html) turns devices off
+#[derive(Clone, Copy, Debug)]
+pub struct IdlePolicy {
+    /// How long a device may be on without a signal before it is turned off
+    pub idle_timeout: Duration,
+    /// How long before the shutdown the warning hooks are called
+    pub warning: Duration,
+    /// How often the devices are checked
+    pub poll_interval: Duration,
+}
+
+impl Default for IdlePolicy {
+    fn default() -> IdlePolicy {
+        IdlePolicy {
+            idle_timeout: Duration::from_secs(30 * 60),
+            warning: Duration::from_secs(5 * 60),
+            poll_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Something `IdleShutdown::check` did
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdleEvent {
+    /// The device will be turned off after `remaining` unless it gets a signal
+    Warning { device: String, remaining: Duration },
+    /// The device was turned off, or the attempt failed
+    Shutdown {
+        device: String,
+        result: Result<(), String>,
+    },
+}
+
+// IRES ? (class 2), "-" when there is no signal
+struct InputResolution;
+
+impl PjlinkCommand for InputResolution {
+    type Output = String;
+
+    fn class(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self) -> String {
+        String::from("IRES ?")
+    }
+
+    fn decode(&self, value: &str) -> Result<String, Error> {
+        Ok(value.to_string())
+    }
+}
+
+impl PjlinkDevice {
+    // Is the device on and showing nothing. Class 2 devices report a missing signal with
+    // IRES ?, for class 1 devices an input that is unavailable counts.
+    fn is_idle(&self) -> Result<bool, Error> {
+        if self.get_power_status()? != PowerStatus::On {
+            return Ok(false);
+        }
+
+        let class: u8 = self.get_class()?.trim().parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "The device reported an invalid class",
+            )
+        })?;
+        if class >= 2 {
+            match self.execute(&InputResolution) {
+                Ok(resolution) => return Ok(resolution == "-"),
+                Err(ref e)
+                    if PjlinkErrorCode::from_error(e) == Some(PjlinkErrorCode::Unavailable) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.get_input() {
+            Ok(_) => Ok(false),
+            Err(ref e) if PjlinkErrorCode::from_error(e) == Some(PjlinkErrorCode::Unavailable) => {
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Idle {
+    since: Instant,
+    // When the device is turned off, set by the warning
+    shutdown_at: Option<Instant>,
+}
+
+type WarningCallback = Box<dyn Fn(&str, Duration) + Send>;
+type ShutdownCallback = Box<dyn Fn(&str, &Result<(), String>) + Send>;
+
+struct Shared {
+    fleet: Fleet,
+    policy: IdlePolicy,
+    exempt: Mutex<BTreeSet<String>>,
+    idle: Mutex<BTreeMap<String, Idle>>,
+    warnings: Mutex<Vec<WarningCallback>>,
+    shutdowns: Mutex<Vec<ShutdownCallback>>,
+}
+
+/// Turns off devices that have been on without a signal for too long, to save lamp hours.
+///
+/// Every device of the fleet that is not exempt is checked every `poll_interval`. Class 2
+/// devices are idle while IRES ? reports no signal, class 1 devices while their input is
+/// unavailable. The warning hooks are called once when a device is within `warning` of
+/// being turned off, and a device that gets a signal starts over. A device is never turned
+/// off without a warning first: if no check fell within the warning time, the device
+/// is warned when it reaches the idle timeout and turned off `warning` later.
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{Fleet, IdleEvent, IdlePolicy, IdleShutdown, PjlinkDevice, PowerStatus};
+///
+/// let emulator = Emulator::new(EmulatorConfig {
+///     class: 2,
+///     power: PowerStatus::On,
+///     ..EmulatorConfig::default()
+/// });
+/// let server = Server::bind("127.0.0.1:0", emulator.clone()).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+/// let mut fleet = Fleet::new();
+/// fleet.add(device);
+///
+/// let idle = IdleShutdown::new(fleet.clone(), IdlePolicy {
+///     idle_timeout: Duration::from_secs(30 * 60),
+///     warning: Duration::from_secs(5 * 60),
+///     ..IdlePolicy::default()
+/// });
+/// idle.on_warning(|device, remaining| println!("{} turns off in {:?}", device, remaining));
+///
+/// // The source is unplugged
+/// emulator.set_signal(false);
+/// let start = Instant::now();
+/// assert!(idle.check(start).is_empty());
+/// assert!(matches!(idle.check(start + Duration::from_secs(26 * 60))[0], IdleEvent::Warning { .. }));
+/// assert!(matches!(idle.check(start + Duration::from_secs(30 * 60))[0], IdleEvent::Shutdown { .. }));
+/// assert_eq!(fleet.devices()[0].get_power_status().unwrap(), PowerStatus::Cooling);
+/// ```
+pub struct IdleShutdown {
+    shared: Arc<Shared>,
+    running: Option<Worker>,
+}
+
+impl IdleShutdown {
+    /// Watch the devices of a fleet
+    pub fn new(fleet: Fleet, policy: IdlePolicy) -> IdleShutdown {
+        IdleShutdown {
+            shared: Arc::new(Shared {
+                fleet,
+                policy,
+                exempt: Mutex::new(BTreeSet::new()),
+                idle: Mutex::new(BTreeMap::new()),
+                warnings: Mutex::new(Vec::new()),
+                shutdowns: Mutex::new(Vec::new()),
+            }),
+            running: None,
+        }
+    }
+
+    /// Never turn off the device with this key, e.g. a display that shows a blank screen on purpose
+    pub fn exempt(&self, key: &str) {
+        self.shared.exempt.lock().unwrap().insert(key.to_string());
+        self.shared.idle.lock().unwrap().remove(key);
+    }
+
+    /// Watch a device that was exempt again
+    pub fn unexempt(&self, key: &str) {
+        self.shared.exempt.lock().unwrap().remove(key);
+    }
+
+    /// Restart the idle time of a device, e.g. when somebody asks to keep it on after a warning
+    pub fn reset(&self, key: &str) {
+        self.shared.idle.lock().unwrap().remove(key);
+    }
+
+    /// Called with the device key and the time left before it is turned off
+    pub fn on_warning<F: Fn(&str, Duration) + Send + 'static>(&self, callback: F) {
+        self.shared
+            .warnings
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+
+    /// Called with the device key and the result when a device is turned off
+    pub fn on_shutdown<F: Fn(&str, &Result<(), String>) + Send + 'static>(&self, callback: F) {
+        self.shared
+            .shutdowns
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+
+    /// Check the devices now, as if it were `now`
+    pub fn check(&self, now: Instant) -> Vec<IdleEvent> {
+        self.shared.check(now)
+    }
+
+    /// Is the background thread running
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start checking the devices on a background thread.
+    /// Starting a policy that is already running does nothing.
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+
+        let shared = Arc::clone(&self.shared);
+        self.running = Some(Worker::every(
+            String::from("pjlink idle shutdown"),
+            shared.policy.poll_interval,
+            move || {
+                shared.check(Instant::now());
+            },
+        )?);
+        Ok(())
+    }
+
+    /// Stop the background thread and wait for it to finish
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+}
+
+impl Shared {
+    fn check(&self, now: Instant) -> Vec<IdleEvent> {
+        let keys: Vec<String> = {
+            let exempt = self.exempt.lock().unwrap();
+            self.fleet
+                .devices()
+                .iter()
+                .map(|device| Fleet::key(device))
+                .filter(|key| !exempt.contains(key))
+                .collect()
+        };
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_str()).collect();
+        let results = self.fleet.run_on(&keys, |device| device.is_idle());
+
+        let policy = self.policy;
+        let mut to_shut_down = Vec::new();
+        let mut events = Vec::new();
+        {
+            let mut idle = self.idle.lock().unwrap();
+            for (key, result) in results {
+                match result {
+                    Ok(true) => {
+                        let state = idle.entry(key.clone()).or_insert(Idle {
+                            since: now,
+                            shutdown_at: None,
+                        });
+                        let elapsed = now.saturating_duration_since(state.since);
+                        match state.shutdown_at {
+                            Some(shutdown_at) if now >= shutdown_at => {
+                                idle.remove(&key);
+                                to_shut_down.push(key);
+                            }
+                            Some(_) => (),
+                            None if elapsed + policy.warning >= policy.idle_timeout => {
+                                // Past the timeout already, so give the full warning time
+                                let remaining = match policy.idle_timeout.checked_sub(elapsed) {
+                                    Some(remaining) if !remaining.is_zero() => remaining,
+                                    _ => policy.warning,
+                                };
+                                state.shutdown_at = Some(now + remaining);
+                                events.push(IdleEvent::Warning {
+                                    device: key,
+                                    remaining,
+                                });
+                            }
+                            None => (),
+                        }
+                    }
+                    Ok(false) => {
+                        idle.remove(&key);
+                    }
+                    // A device that can't be reached keeps its idle time until it answers
+                    Err(_) => (),
+                }
+            }
+        }
+
+        for event in &events {
+            if let IdleEvent::Warning {
+                ref device,
+                remaining,
+            } = *event
+            {
+                for callback in self.warnings.lock().unwrap().iter() {
+                    callback(device, remaining);
+                }
+            }
+        }
+
+        if !to_shut_down.is_empty() {
+            let keys: Vec<&str> = to_shut_down.iter().map(|key| key.as_str()).collect();
+            for (key, result) in self
+                .fleet
+                .run_on(&keys, |device| device.power_off().map(|_| ()))
+            {
+                let result = result.map_err(|e| e.to_string());
+                for callback in self.shutdowns.lock().unwrap().iter() {
+                    callback(&key, &result);
+                }
+                events.push(IdleEvent::Shutdown {
+                    device: key,
+                    result,
+                });
+            }
+        }
+        events
+    }
+}
@@ -12,33 +12,141 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::error;
+use std::fmt;
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 extern crate md5;
 
+mod command;
+mod config;
+mod emergency;
+mod fleet;
+mod idle;
+mod lockout;
+mod managed;
+mod monitored;
+mod password;
+mod power;
+mod profile;
+mod queue;
+mod quirks;
+mod reconcile;
+mod retry;
+mod rng;
+mod room;
+mod schedule;
+mod script;
+pub mod server;
+mod stagger;
+mod status;
+mod worker;
+
+pub use command::PjlinkCommand;
+pub use emergency::{Emergency, EmergencyOptions, EmergencyReport};
+pub use fleet::{Fleet, FleetResults};
+pub use idle::{IdleEvent, IdlePolicy, IdleShutdown};
+use lockout::AuthTracker;
+pub use lockout::{AuthLockedOut, AuthLockout};
+pub use managed::ManagedDevice;
+pub use monitored::MonitoredDevice;
+pub use password::{IntoPassword, Password, MAX_PASSWORD_LEN};
+pub use power::{PowerTimeout, POWER_POLL_INTERVAL};
+pub use profile::DeviceProfile;
+pub use queue::{CommandOutcome, CommandQueue, PendingCommand, QueuedCommand};
+pub use quirks::Quirks;
+pub use reconcile::{Change, Correction, Reconciler};
+pub use retry::RetryPolicy;
+pub use room::{DeviceState, RoomPresets, RoomState};
+pub use schedule::{Execution, ExecutionOutcome, Schedule, ScheduledAction, Scheduler};
+pub use script::{Script, ScriptMessage};
+pub use stagger::{GroupPowerReport, Stagger};
+pub use status::{DeviceStatus, StatusField};
+
 const AUTH: char = '1';
 const NOAUTH: char = '0';
-static PORT: &'static str = "4352";
+/// The TCP (and UDP) port PJLink devices listen on
+pub const PORT: u16 = 4352;
+/// How long connecting to a device, and each read and write, may take by default,
+/// see `PjlinkDeviceBuilder::io_timeout`
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The error codes a device can report, as defined by the PJLink specification.
+/// The std::io::Error returned for a device error carries one of these.
+///
+/// ```no_run
+/// # use pjlink::{PjlinkDevice, PjlinkErrorCode};
+/// # let device = PjlinkDevice::new("192.168.1.1").unwrap();
+/// if let Err(err) = device.get_power_status() {
+///     if PjlinkErrorCode::from_error(&err) == Some(PjlinkErrorCode::Unavailable) {
+///         println!("The device is busy, try again later");
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PjlinkErrorCode {
+    /// ERR1
+    UndefinedCommand,
+    /// ERR2
+    InvalidParameter,
+    /// ERR3
+    Unavailable,
+    /// ERR4
+    DeviceFailure,
+    /// ERRA
+    Authorization,
+}
+
+impl PjlinkErrorCode {
+    /// The error code a std::io::Error was created from, if any
+    pub fn from_error(err: &Error) -> Option<PjlinkErrorCode> {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<PjlinkErrorCode>())
+            .cloned()
+    }
+
+    /// The error code as it appears on the wire, e.g. ERR3
+    pub fn code(self) -> &'static str {
+        match self {
+            PjlinkErrorCode::UndefinedCommand => "ERR1",
+            PjlinkErrorCode::InvalidParameter => "ERR2",
+            PjlinkErrorCode::Unavailable => "ERR3",
+            PjlinkErrorCode::DeviceFailure => "ERR4",
+            PjlinkErrorCode::Authorization => "ERRA",
+        }
+    }
+}
+
+impl fmt::Display for PjlinkErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            PjlinkErrorCode::UndefinedCommand => "Undefined command",
+            PjlinkErrorCode::InvalidParameter => "Invalid parameter",
+            PjlinkErrorCode::Unavailable => "Unavailable at this time",
+            PjlinkErrorCode::DeviceFailure => "Projector/Display Failure",
+            PjlinkErrorCode::Authorization => "Authorization Error",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl error::Error for PjlinkErrorCode {}
 
 // Return the correct error message based on the PJ Link specification
 fn pjlink_error(error_msg: &str) -> Error {
     match &error_msg[0..4] {
-        "ERR1" => Error::new(ErrorKind::InvalidData, "Undefined command".to_string()),
-        "ERR2" => Error::new(ErrorKind::InvalidData, "Invalid parameter".to_string()),
-        "ERR3" => Error::new(
-            ErrorKind::InvalidData,
-            "Unavailable at this time".to_string(),
-        ),
-        "ERR4" => Error::new(
-            ErrorKind::InvalidData,
-            "Projector/Display Failure".to_string(),
-        ),
-        "ERRA" => Error::new(
-            ErrorKind::PermissionDenied,
-            "Authorization Error".to_string(),
-        ),
+        "ERR1" => Error::new(ErrorKind::InvalidData, PjlinkErrorCode::UndefinedCommand),
+        "ERR2" => Error::new(ErrorKind::InvalidData, PjlinkErrorCode::InvalidParameter),
+        "ERR3" => Error::new(ErrorKind::InvalidData, PjlinkErrorCode::Unavailable),
+        "ERR4" => Error::new(ErrorKind::InvalidData, PjlinkErrorCode::DeviceFailure),
+        "ERRA" => Error::new(ErrorKind::PermissionDenied, PjlinkErrorCode::Authorization),
         _ => Error::new(
             ErrorKind::InvalidData,
             format!("Error reported from the projector {}", error_msg),
@@ -48,31 +156,25 @@ fn pjlink_error(error_msg: &str) -> Error {
 
 // Parse the response from the device
 fn parse_response(response: &str) -> Result<PjlinkResponse, Error> {
-    let mut equals_sign: usize = 0;
     let len = response.len();
     //lets find the equals sign
-    for (i, c) in response.chars().enumerate() {
-        if c == '=' || c == ' ' {
-            equals_sign = i;
-            break;
-        }
-    }
+    let equals_sign = response.find(['=', ' ']).unwrap_or(0);
 
-    let command = if &response[0..1] != "%" {
-        CommandType::PJLINK
+    let command = if !response.starts_with('%') {
+        CommandType::Pjlink
     } else {
-        match &response[2..equals_sign] {
-            "POWR" => CommandType::Power,
-            "INPT" => CommandType::Input,
-            "AVMT" => CommandType::AvMute,
-            "ERST" => CommandType::ErrorStatus,
-            "LAMP" => CommandType::Lamp,
-            "INST" => CommandType::InputList,
-            "NAME" => CommandType::Name,
-            "INF1" => CommandType::Manufacturer,
-            "INF2" => CommandType::ProductName,
-            "INFO" => CommandType::Information,
-            "CLSS" => CommandType::Class,
+        match response.get(2..equals_sign) {
+            Some("POWR") => CommandType::Power,
+            Some("INPT") => CommandType::Input,
+            Some("AVMT") => CommandType::AvMute,
+            Some("ERST") => CommandType::ErrorStatus,
+            Some("LAMP") => CommandType::Lamp,
+            Some("INST") => CommandType::InputList,
+            Some("NAME") => CommandType::Name,
+            Some("INF1") => CommandType::Manufacturer,
+            Some("INF2") => CommandType::ProductName,
+            Some("INFO") => CommandType::Information,
+            Some("CLSS") => CommandType::Class,
             _ => {
                 return Err(Error::new(
                     ErrorKind::InvalidInput,
@@ -82,10 +184,10 @@ fn parse_response(response: &str) -> Result<PjlinkResponse, Error> {
         }
     };
 
-    let value = &response[equals_sign + 1..len];
+    let value = response.get(equals_sign + 1..len).unwrap_or("");
 
     // Did we get and error report and if so lets return it so the functions don't have check for errors.
-    if value.len() == 4 && &value[0..3] == "ERR" {
+    if value.len() == 4 && value.starts_with("ERR") {
         return Err(pjlink_error(value));
     }
 
@@ -95,10 +197,159 @@ fn parse_response(response: &str) -> Result<PjlinkResponse, Error> {
     })
 }
 
+// The value of a POWR ? reply
+fn parse_power_status(value: &str) -> Result<PowerStatus, Error> {
+    match value.get(0..1).unwrap_or("") {
+        "0" => Ok(PowerStatus::Off),
+        "1" => Ok(PowerStatus::On),
+        "2" => Ok(PowerStatus::Cooling),
+        "3" => Ok(PowerStatus::Warmup),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid Response: {}", value),
+        )), // Invalid Response
+    }
+}
+
+// The value of an INPT ? reply, with numbers outside 1-9 if the device has that quirk
+fn parse_input(value: &str, extended: bool) -> Result<InputType, Error> {
+    let input = if extended {
+        InputType::from_extended_code(value)
+    } else {
+        InputType::from_code(parse_number::<u8>(value)?)
+    };
+    match input {
+        Some(input_type) => Ok(input_type),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid input:: {}", value),
+        )),
+    }
+}
+
+// The value of an AVMT ? reply
+fn parse_avmute(value: &str) -> Result<AvMute, Error> {
+    let status = parse_number::<u8>(value)?;
+    match AvMute::from_code(status) {
+        Some(mutes) => Ok(mutes),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid result:: {}", status),
+        )),
+    }
+}
+
+// The value of a LAMP ? reply, pairs of hours and on/off
+fn parse_lamps(value: &str) -> Result<Vec<Lamp>, Error> {
+    let mut status = value.split_whitespace();
+    let mut lamps = Vec::new();
+    while let Some(l) = status.next() {
+        let hours = parse_number::<u16>(l)?;
+
+        let on = match status.next() {
+            Some(x) => x == "1",
+            None => false,
+        };
+        lamps.push(Lamp { hours, on });
+    }
+    Ok(lamps)
+}
+
+// The value of an ERST ? reply, one digit for each kind of error
+fn parse_error_status(value: &str) -> ErrorStatus {
+    let mut status = value.chars().map(|e| match e {
+        '1' => ErrorType::Warning,
+        '2' => ErrorType::Error,
+        _ => ErrorType::NoError,
+    });
+    let mut next = || status.next().unwrap_or(ErrorType::NoError);
+
+    ErrorStatus {
+        fan_error: next(),
+        lamp_error: next(),
+        temperature_error: next(),
+        cover_open_error: next(),
+        filter_error: next(),
+        other_error: next(),
+    }
+}
+
+// Whether a command is sent again: ERR3 replies and transient connection errors
+fn should_retry(result: &Result<String, Error>) -> bool {
+    match *result {
+        Ok(ref response) => response.ends_with("=ERR3"),
+        Err(ref e) => retry::is_transient(e),
+    }
+}
+
+fn invalid_response(value: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Invalid Response:: {}", value),
+    )
+}
+
+/// Connect to the first address of the host that answers within the timeout.
+/// The timeout also applies to every read and write on the returned stream.
+pub fn connect_within(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let mut last_error = Error::new(ErrorKind::NotFound, "The host has no addresses");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// The longest reply we are willing to wait for, anything longer isn't PJLink.
+const MAX_REPLY: usize = 1024;
+
+// Read a single '\r' terminated line from the device, without the '\r'.
+// Replies can arrive in several pieces so keep reading until the line is complete.
+fn read_line<R: Read>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let mut line = Vec::new();
+    let mut buffer = [0u8; 256];
+    loop {
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "The device closed the connection before the reply was complete.",
+            ));
+        }
+        line.extend_from_slice(&buffer[0..len]);
+        if let Some(end) = line.iter().position(|&b| b == b'\r') {
+            line.truncate(end);
+            return Ok(line);
+        }
+        if line.len() > MAX_REPLY {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The reply from the device is too long.",
+            ));
+        }
+    }
+}
+
+// Parse a number in a reply, reporting garbage as an error rather than panicking.
+fn parse_number<T: FromStr>(value: &str) -> Result<T, Error> {
+    value.trim().parse::<T>().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid Response: {}", value),
+        )
+    })
+}
+
 // This is the list of standard command/response types from the PJLink spec.
 // At this point I would think that this would only be used internally.
 enum CommandType {
-    PJLINK,
+    Pjlink,
     Power,
     Input,
     AvMute,
@@ -113,6 +364,7 @@ enum CommandType {
 }
 
 /// Power status is based off of the PJLink specification and is used to be returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PowerStatus {
     Off,
     On,
@@ -120,6 +372,7 @@ pub enum PowerStatus {
     Warmup,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InputType {
     RGB(u8),
     Video(u8),
@@ -128,22 +381,132 @@ pub enum InputType {
     Network(u8),
 }
 
+impl InputType {
+    /// The two digit input number used by the INPT command, e.g. 31 for Digital(1)
+    pub fn code(self) -> u8 {
+        match self {
+            InputType::RGB(i_num) => i_num + 10,
+            InputType::Video(i_num) => i_num + 20,
+            InputType::Digital(i_num) => i_num + 30,
+            InputType::Storage(i_num) => i_num + 40,
+            InputType::Network(i_num) => i_num + 50,
+        }
+    }
+
+    /// The INPT parameter with input numbers above 9 as letters, e.g. 3A for Digital(10)
+    pub fn extended_code(self) -> String {
+        let (kind, number) = match self {
+            InputType::RGB(i_num) => (1, i_num),
+            InputType::Video(i_num) => (2, i_num),
+            InputType::Digital(i_num) => (3, i_num),
+            InputType::Storage(i_num) => (4, i_num),
+            InputType::Network(i_num) => (5, i_num),
+        };
+        match std::char::from_digit(u32::from(number), 36) {
+            Some(digit) => format!("{}{}", kind, digit.to_ascii_uppercase()),
+            None => format!("{}{}", kind, number),
+        }
+    }
+
+    /// The input for an INPT value whose number may be outside 1-9, either as a
+    /// letter (3A for Digital(10)) or as several digits (310 for Digital(10))
+    pub fn from_extended_code(code: &str) -> Option<InputType> {
+        let kind = code.get(0..1)?;
+        let number = code.get(1..)?;
+        let number = match number.len() {
+            1 => number.chars().next()?.to_digit(36)? as u8,
+            _ => number.parse::<u8>().ok()?,
+        };
+        if number == 0 {
+            return None;
+        }
+        match kind {
+            "1" => Some(InputType::RGB(number)),
+            "2" => Some(InputType::Video(number)),
+            "3" => Some(InputType::Digital(number)),
+            "4" => Some(InputType::Storage(number)),
+            "5" => Some(InputType::Network(number)),
+            _ => None,
+        }
+    }
+
+    /// The input for a two digit INPT input number
+    pub fn from_code(code: u8) -> Option<InputType> {
+        match code {
+            11..=19 => Some(InputType::RGB(code - 10)),
+            21..=29 => Some(InputType::Video(code - 20)),
+            31..=39 => Some(InputType::Digital(code - 30)),
+            41..=49 => Some(InputType::Storage(code - 40)),
+            51..=59 => Some(InputType::Network(code - 50)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ErrorType {
     NoError,
     Warning,
     Error,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct AvMute {
     pub audio: bool,
     pub video: bool,
 }
 
+impl AvMute {
+    /// The AVMT setting for these mutes, e.g. 31 for audio and video muted
+    pub fn code(self) -> u8 {
+        match self {
+            AvMute {
+                video: true,
+                audio: false,
+            } => 11,
+            AvMute {
+                video: false,
+                audio: true,
+            } => 21,
+            AvMute {
+                video: true,
+                audio: true,
+            } => 31,
+            _ => 30,
+        }
+    }
+
+    /// The mutes reported by an AVMT query
+    pub fn from_code(code: u8) -> Option<AvMute> {
+        match code {
+            11 => Some(AvMute {
+                audio: false,
+                video: true,
+            }),
+            21 => Some(AvMute {
+                audio: true,
+                video: false,
+            }),
+            31 => Some(AvMute {
+                audio: true,
+                video: true,
+            }),
+            30 => Some(AvMute {
+                audio: false,
+                video: false,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Lamp {
     pub hours: u16,
     pub on: bool,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ErrorStatus {
     pub fan_error: ErrorType,
     pub lamp_error: ErrorType,
@@ -160,46 +523,411 @@ struct PjlinkResponse {
 
 pub struct PjlinkDevice {
     pub host: String,
-    password: String,
-    //managed: bool, // Currently not implemented but will add managed monitoring support with call backs with the status changes
-    //monitored: bool, // Currenly not implemented by will allow you to monitor a device with out mainting authority over it.
+    pub port: u16,
+    password: Mutex<Option<Password>>,
+    auth: AuthTracker,
+    retry_policy: RetryPolicy,
+    io_timeout: Duration,
+    attempts: AtomicU32,
+    quirks: Mutex<Quirks>,
+    // Checked by detect_quirks before the built-in table
+    known_quirks: Vec<(String, String, Quirks)>,
+    // Look the quirks up before the next command
+    detect_quirks: AtomicBool,
+    // When the last reply arrived, for Quirks::reconnect_delay
+    last_reply: Mutex<Option<Instant>>,
+}
+
+// An open connection to a device that has sent its greeting. Several commands can
+// be sent over it, the first one carries the authentication digest.
+struct Session<'a> {
+    device: &'a PjlinkDevice,
+    stream: TcpStream,
+    digest: Option<String>,
+    authenticated: bool,
+    quirks: Quirks,
+}
+
+impl<'a> Session<'a> {
+    fn send(&mut self, class: u8, command: &str) -> Result<String, Error> {
+        let cmd = match self.digest.take() {
+            Some(digest) => format!("{}%{}{}\r", digest, class, command),
+            None => format!("%{}{}\r", class, command),
+        };
+        self.stream.write_all(cmd.as_bytes())?;
+
+        let response = String::from_utf8_lossy(&read_line(&mut self.stream)?).to_string();
+        *self.device.last_reply.lock().unwrap() = Some(Instant::now());
+        let response = self.quirks.normalize(response);
+        if response.starts_with("PJLINK ERRA") {
+            self.device.auth.failure();
+        } else if self.authenticated {
+            self.device.auth.reset();
+        }
+        Ok(response)
+    }
+}
+
+/// Builds a [pjlink::PjlinkDevice](struct.PjlinkDevice.html), see `PjlinkDevice::builder`
+pub struct PjlinkDeviceBuilder {
+    host: String,
+    port: u16,
+    password: Result<Option<Password>, Error>,
+    auth_lockout: AuthLockout,
+    retry_policy: RetryPolicy,
+    io_timeout: Duration,
+    quirks: Quirks,
+    known_quirks: Vec<(String, String, Quirks)>,
+    detect_quirks: bool,
+}
+
+impl PjlinkDeviceBuilder {
+    /// The port the device listens on if it is not the standard PJLink port 4352
+    pub fn port(mut self, port: u16) -> PjlinkDeviceBuilder {
+        self.port = port;
+        self
+    }
+
+    /// The password used to authenticate with the device, an empty string means none.
+    /// An invalid password is reported when `build` is called.
+    pub fn password<P: IntoPassword>(mut self, password: P) -> PjlinkDeviceBuilder {
+        self.password = password.into_password();
+        self
+    }
+
+    /// How many authorization failures to allow before refusing to contact the device,
+    /// see [pjlink::AuthLockout](struct.AuthLockout.html) for the defaults.
+    pub fn auth_lockout(mut self, auth_lockout: AuthLockout) -> PjlinkDeviceBuilder {
+        self.auth_lockout = auth_lockout;
+        self
+    }
+
+    /// How commands are retried, see [pjlink::RetryPolicy](struct.RetryPolicy.html)
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> PjlinkDeviceBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// How long connecting to the device, and each read and write, may take before
+    /// the attempt fails with an error, `DEFAULT_IO_TIMEOUT` unless set
+    pub fn io_timeout(mut self, io_timeout: Duration) -> PjlinkDeviceBuilder {
+        self.io_timeout = io_timeout;
+        self
+    }
+
+    /// Work around the ways the device deviates from the specification,
+    /// see [pjlink::Quirks](struct.Quirks.html)
+    pub fn quirks(mut self, quirks: Quirks) -> PjlinkDeviceBuilder {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Quirks for `PjlinkDevice::detect_quirks` to use when the manufacturer (INF1 ?) and
+    /// the start of the product name (INF2 ?) match, both compared without case. An empty
+    /// product matches every product of the manufacturer. These are checked in the order
+    /// they were added and before the built-in table, see `Quirks::lookup`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use pjlink::{PjlinkDevice, Quirks};
+    ///
+    /// let device = PjlinkDevice::builder("192.168.1.1")
+    ///     .known_quirks(
+    ///         "Acme",
+    ///         "PJ-",
+    ///         Quirks {
+    ///             greeting_delay: Duration::from_millis(100),
+    ///             ..Quirks::default()
+    ///         },
+    ///     )
+    ///     .auto_quirks()
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn known_quirks(
+        mut self,
+        manufacturer: &str,
+        product: &str,
+        quirks: Quirks,
+    ) -> PjlinkDeviceBuilder {
+        self.known_quirks.push((
+            manufacturer.trim().to_uppercase(),
+            product.trim().to_uppercase(),
+            quirks,
+        ));
+        self
+    }
+
+    /// Identify the device with INF1 ? and INF2 ? before the first command and use
+    /// its known quirks, see `PjlinkDevice::detect_quirks`
+    pub fn auto_quirks(mut self) -> PjlinkDeviceBuilder {
+        self.detect_quirks = true;
+        self
+    }
+
+    /// Constructs the PjlinkDevice
+    pub fn build(self) -> Result<PjlinkDevice, Error> {
+        Ok(PjlinkDevice {
+            host: self.host,
+            port: self.port,
+            password: Mutex::new(self.password?),
+            auth: AuthTracker::new(self.auth_lockout),
+            retry_policy: self.retry_policy,
+            io_timeout: self.io_timeout,
+            attempts: AtomicU32::new(0),
+            quirks: Mutex::new(self.quirks),
+            known_quirks: self.known_quirks,
+            detect_quirks: AtomicBool::new(self.detect_quirks),
+            last_reply: Mutex::new(None),
+        })
+    }
 }
 
 impl PjlinkDevice {
     /// Constructs a new PjlinkDevice.
     pub fn new(host: &str) -> Result<PjlinkDevice, Error> {
-        let pwd = String::from("");
-        PjlinkDevice::new_with_password(host, &pwd)
+        PjlinkDevice::builder(host).build()
     }
 
-    /// Contructs a new PjlinkDevice that has a password
-    pub fn new_with_password(host: &str, password: &str) -> Result<PjlinkDevice, Error> {
-        Ok(PjlinkDevice {
+    /// Contructs a new PjlinkDevice that has a password.
+    /// The password can be a string or a [pjlink::Password](struct.Password.html) and
+    /// an error is returned if it is not a valid PJLink password.
+    pub fn new_with_password<P: IntoPassword>(
+        host: &str,
+        password: P,
+    ) -> Result<PjlinkDevice, Error> {
+        PjlinkDevice::builder(host).password(password).build()
+    }
+
+    /// Start building a PjlinkDevice for the given host
+    ///
+    /// ```
+    /// use pjlink::{Password, PjlinkDevice};
+    ///
+    /// let device = PjlinkDevice::builder("192.168.1.1")
+    ///     .password(Password::new("JBMIAProjectorLink").unwrap())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(host: &str) -> PjlinkDeviceBuilder {
+        PjlinkDeviceBuilder {
             host: host.to_string(),
-            password: String::from(password),
-            //managed: false, // Hard coded for now until it is implemented
-            //monitored: false, // Hard coded for now until it is implemented
-        })
+            port: PORT,
+            password: Ok(None),
+            auth_lockout: AuthLockout::default(),
+            retry_policy: RetryPolicy::default(),
+            io_timeout: DEFAULT_IO_TIMEOUT,
+            quirks: Quirks::default(),
+            known_quirks: Vec::new(),
+            detect_quirks: false,
+        }
+    }
+
+    /// Replace the policy used to retry commands
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// The number of attempts the most recent command needed.
+    /// The device is shared by every caller, so when several threads send commands at
+    /// once, e.g. through a `Fleet`, `ManagedDevice` or `CommandQueue`, this may be the
+    /// count of another thread's command. Use `send_class_command_with_attempts` to get
+    /// the count of a particular command.
+    pub fn last_attempts(&self) -> u32 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// The quirks used with the device
+    pub fn quirks(&self) -> Quirks {
+        *self.quirks.lock().unwrap()
+    }
+
+    /// Replace the quirks used with the device
+    pub fn set_quirks(&self, quirks: Quirks) {
+        *self.quirks.lock().unwrap() = quirks;
+        self.detect_quirks.store(false, Ordering::SeqCst);
+    }
+
+    /// Identify the device with INF1 ? and INF2 ? and use its quirks from the ones given to
+    /// `PjlinkDeviceBuilder::known_quirks`, or else from the built-in table, see
+    /// `Quirks::lookup`. Lenient replies are accepted while identifying.
+    /// Returns the quirks now in use, which are left unchanged for an unknown device
+    /// or when `set_quirks` was called in the meantime.
+    ///
+    /// ```
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{PjlinkDevice, Quirks};
+    ///
+    /// let emulator = Emulator::new(EmulatorConfig {
+    ///     manufacturer: String::from("ACME"),
+    ///     ..EmulatorConfig::default()
+    /// });
+    /// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    /// let device = PjlinkDevice::builder("127.0.0.1")
+    ///     .port(server.local_addr().port())
+    ///     .known_quirks(
+    ///         "Acme",
+    ///         "",
+    ///         Quirks {
+    ///             lenient_replies: true,
+    ///             ..Quirks::default()
+    ///         },
+    ///     )
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let quirks = device.detect_quirks().unwrap();
+    /// assert!(quirks.lenient_replies);
+    /// ```
+    pub fn detect_quirks(&self) -> Result<Quirks, Error> {
+        self.detect_quirks.store(false, Ordering::SeqCst);
+        let current = self.quirks();
+        let identifying = Quirks {
+            lenient_replies: true,
+            ..current
+        };
+        let manufacturer = match self.identify("INF1 ?", identifying)? {
+            PjlinkResponse {
+                action: CommandType::Manufacturer,
+                value,
+            } => value,
+            result => return Err(invalid_response(&result.value)),
+        };
+        let product_name = match self.identify("INF2 ?", identifying)? {
+            PjlinkResponse {
+                action: CommandType::ProductName,
+                value,
+            } => value,
+            result => return Err(invalid_response(&result.value)),
+        };
+
+        let found = quirks::find(&self.known_quirks, &manufacturer, &product_name);
+        let mut quirks = self.quirks.lock().unwrap();
+        // Quirks set while identifying the device win
+        if let Some(found) = found {
+            if *quirks == current {
+                *quirks = found;
+            }
+        }
+        Ok(*quirks)
+    }
+
+    // Send an identity query for detect_quirks with the given quirks instead of the
+    // device's, retried like any other command
+    fn identify(&self, command: &str, quirks: Quirks) -> Result<PjlinkResponse, Error> {
+        let (result, _) = self.retry_policy.run(
+            || {
+                self.open_session_with(self.io_timeout, quirks)?
+                    .send(1, command)
+            },
+            should_retry,
+        );
+        parse_response(&result?)
+    }
+
+    /// Change the password used with the device, an empty string removes it. This also clears any
+    /// lockout caused by earlier authorization failures.
+    pub fn set_password<P: IntoPassword>(&self, password: P) -> Result<(), Error> {
+        *self.password.lock().unwrap() = password.into_password()?;
+        self.auth.reset();
+        Ok(())
     }
 
     /// Send a command and a Result with the raw string or an error
+    ///
+    /// ERR3 replies and transient connection errors are retried according to the
+    /// device's [pjlink::RetryPolicy](struct.RetryPolicy.html).
+    ///
+    /// After too many consecutive authorization failures (PJLINK ERRA) the command is
+    /// refused without contacting the device and the error carries a
+    /// [pjlink::AuthLockedOut](struct.AuthLockedOut.html).
     pub fn send_command(&self, command: &str) -> Result<String, Error> {
-        let host_port = [&self.host, ":", PORT].concat();
-        let mut client_buffer = [0u8; 256];
-        let mut stream = try!(TcpStream::connect(host_port));
+        self.send_class_command(1, command)
+    }
 
-        let _ = stream.read(&mut client_buffer); //Did we get the hello string?
+    /// Send a command with the given class prefix, e.g. `send_class_command(2, "SNUM ?")`
+    /// sends %2SNUM ?. Otherwise the same as `send_command`.
+    pub fn send_class_command(&self, class: u8, command: &str) -> Result<String, Error> {
+        self.send_class_command_with_attempts(class, command).0
+    }
 
-        let cmd: String = match client_buffer[7] as char {
+    /// Same as `send_class_command`, also returning the number of attempts the command needed
+    ///
+    /// ```no_run
+    /// use pjlink::PjlinkDevice;
+    ///
+    /// let device = PjlinkDevice::new("192.168.1.1").unwrap();
+    /// let (result, attempts) = device.send_class_command_with_attempts(1, "POWR ?");
+    /// println!("{:?} after {} attempts", result, attempts);
+    /// ```
+    pub fn send_class_command_with_attempts(
+        &self,
+        class: u8,
+        command: &str,
+    ) -> (Result<String, Error>, u32) {
+        if self.detect_quirks.load(Ordering::SeqCst) {
+            // Failing to identify the device is not a reason to fail the command
+            let _ = self.detect_quirks();
+        }
+        let (result, attempts) = self
+            .retry_policy
+            .run(|| self.send_command_once(class, command), should_retry);
+        self.attempts.store(attempts, Ordering::Relaxed);
+        (result, attempts)
+    }
+
+    // A single attempt at sending a command
+    fn send_command_once(&self, class: u8, command: &str) -> Result<String, Error> {
+        self.open_session()?.send(class, command)
+    }
+
+    // Connect to the device and read the greeting
+    fn open_session(&self) -> Result<Session<'_>, Error> {
+        self.open_session_within(self.io_timeout)
+    }
+
+    // Same as open_session with another timeout for connecting, reading and writing
+    fn open_session_within(&self, timeout: Duration) -> Result<Session<'_>, Error> {
+        self.open_session_with(timeout, self.quirks())
+    }
+
+    // Same as open_session_within with other quirks than the device's
+    fn open_session_with(&self, timeout: Duration, quirks: Quirks) -> Result<Session<'_>, Error> {
+        self.auth.check()?;
+
+        if quirks.close_after_reply {
+            let last_reply = *self.last_reply.lock().unwrap();
+            if let Some(wait) =
+                last_reply.and_then(|last| quirks.reconnect_delay.checked_sub(last.elapsed()))
+            {
+                thread::sleep(wait);
+            }
+        }
+
+        let mut stream = connect_within(&self.host, self.port, timeout)?;
+
+        let greeting = read_line(&mut stream)?; //Did we get the hello string?
+        if quirks.greeting_delay > Duration::from_secs(0) {
+            thread::sleep(quirks.greeting_delay);
+        }
+
+        let auth_mode = greeting.get(7).cloned().unwrap_or(0) as char;
+        let digest = match auth_mode {
             // Does the connection require auth or not
             AUTH => {
                 // Connection requires auth
-                let rnd_num = String::from_utf8_lossy(&client_buffer[9..17]).to_string();
-                if &self.password != "" {
-                    // We got a password
-                    let pwd_str = format!("{}{}", rnd_num, &self.password);
-                    let digest = md5::compute(pwd_str);
-                    format!("{:x}%1{}\r", digest, command)
+                if greeting.len() < 17 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "The device sent an incomplete greeting.",
+                    ));
+                }
+                if let Some(ref password) = *self.password.lock().unwrap() {
+                    // We got a password, the digest is built without copying it.
+                    let mut context = md5::Context::new();
+                    context.consume(&greeting[9..17]);
+                    context.consume(password.expose());
+                    Some(format!("{:x}", context.compute()))
                 } else {
                     // No password was supplied so we are going to raise an error.
                     return Err(Error::new(
@@ -208,10 +936,7 @@ impl PjlinkDevice {
                     ));
                 }
             }
-            NOAUTH => {
-                // Connection requires no auth
-                format!("%1{}\r", command)
-            }
+            NOAUTH => None, // Connection requires no auth
 
             _ => {
                 return Err(Error::new(
@@ -221,28 +946,19 @@ impl PjlinkDevice {
             }
         };
 
-        let result = stream.write(cmd.as_bytes());
-        match result {
-            Ok(_) => (),
-            Err(e) => return Err(e),
-        };
-        let result = stream.read(&mut client_buffer);
-        let len = match result {
-            Ok(len) => len,
-            Err(e) => return Err(e),
-        };
-
-        let response = String::from_utf8_lossy(&client_buffer[0..len - 1]).to_string();
-        Ok(response)
+        Ok(Session {
+            device: self,
+            stream,
+            digest,
+            authenticated: auth_mode == AUTH,
+            quirks,
+        })
     }
 
     // a wrapper around send_command that will parse the response
     fn send(&self, cmd: &str) -> Result<PjlinkResponse, Error> {
         match self.send_command(cmd) {
-            Ok(send_result) => match parse_response(&send_result) {
-                Ok(parse_result) => Ok(parse_result),
-                Err(e) => Err(e),
-            },
+            Ok(send_result) => parse_response(&send_result),
             Err(e) => Err(e),
         }
     }
@@ -250,26 +966,13 @@ impl PjlinkDevice {
     /// Check the power status of the device and returns an enum
     pub fn get_power_status(&self) -> Result<PowerStatus, Error> {
         match self.send("POWR ?") {
-            Ok(result) => {
-                match result.action {
-                    CommandType::Power => {
-                        match &result.value[0..1] {
-                            "0" => Ok(PowerStatus::Off),
-                            "1" => Ok(PowerStatus::On),
-                            "2" => Ok(PowerStatus::Cooling),
-                            "3" => Ok(PowerStatus::Warmup),
-                            _ => Err(Error::new(
-                                ErrorKind::InvalidInput,
-                                format!("Invalid Response: {}", result.value),
-                            )), // Invalid Response
-                        }
-                    }
-                    _ => Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        format!("Got a response we didn't expect: {}", result.value),
-                    )),
-                }
-            }
+            Ok(result) => match result.action {
+                CommandType::Power => parse_power_status(&result.value),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Got a response we didn't expect: {}", result.value),
+                )),
+            },
             Err(e) => Err(e),
         }
     }
@@ -282,7 +985,7 @@ impl PjlinkDevice {
             Ok(result) => {
                 match result.action {
                     CommandType::Power => {
-                        match &result.value[0..2] {
+                        match result.value.get(0..2).unwrap_or("") {
                             "OK" => match self.get_power_status() {
                                 Ok(status) => Ok(status),
                                 Err(e) => Err(e),
@@ -311,7 +1014,7 @@ impl PjlinkDevice {
             Ok(result) => {
                 match result.action {
                     CommandType::Power => {
-                        match &result.value[0..2] {
+                        match result.value.get(0..2).unwrap_or("") {
                             "OK" => match self.get_power_status() {
                                 Ok(status) => Ok(status),
                                 Err(e) => Err(e),
@@ -413,27 +1116,14 @@ impl PjlinkDevice {
 
     /// Get the current input (INPT ?) from the device
     /// Returns a Result enum with an Ok type of [pjlink::InputType](enum.InputType.html) example would be:
-    /// ```
+    /// ```text
     /// pjlink::InputType::RGB(input_num) //with input_num being the number of the input with a type of u8
     ///
     /// ```
     ///
     pub fn get_input(&self) -> Result<InputType, Error> {
         match self.send("INPT ?") {
-            Ok(result) => {
-                let input = result.value.parse::<u8>().unwrap();
-                match input {
-                    11...19 => Ok(InputType::RGB(input - 10)),
-                    21...29 => Ok(InputType::Video(input - 20)),
-                    31...39 => Ok(InputType::Digital(input - 30)),
-                    41...49 => Ok(InputType::Storage(input - 40)),
-                    51...59 => Ok(InputType::Network(input - 50)),
-                    _ => Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        format!("Invalid input:: {}", input),
-                    )),
-                }
-            }
+            Ok(result) => parse_input(&result.value, self.quirks().extended_inputs),
             Err(e) => Err(e),
         }
     }
@@ -442,9 +1132,10 @@ impl PjlinkDevice {
     /// Returns a result enum with Ok type of [pjlink::InputType](enum.InputType.html) with a value associated
     ///  of the input number or an std::io::Error
     ///
-    /// ```
-    /// let result = pjlink::PjlinkDevice::set_input(&self, input: InputType).?
-    /// match device.get_input() {
+    /// ```no_run
+    /// # use pjlink::{InputType, PjlinkDevice};
+    /// # let device = PjlinkDevice::new("192.168.1.1").unwrap();
+    /// match device.set_input(InputType::Digital(1)) {
     ///    Ok(input) => {
     ///        match input {
     ///            InputType::RGB(input_number) => println!("Input: RGB {}", input_number),
@@ -459,20 +1150,16 @@ impl PjlinkDevice {
     /// ```
     ///
     pub fn set_input(&self, input: InputType) -> Result<InputType, Error> {
-        let input_number: u8 = match input {
-            InputType::RGB(i_num) => i_num + 10,
-            InputType::Video(i_num) => i_num + 20,
-            InputType::Digital(i_num) => i_num + 30,
-            InputType::Storage(i_num) => i_num + 40,
-            InputType::Network(i_num) => i_num + 50,
+        let command = if self.quirks().extended_inputs {
+            format!("INPT {}", input.extended_code())
+        } else {
+            format!("INPT {}", input.code())
         };
-
-        let command = format!("INPT {}", input_number);
         match self.send(&command) {
             Ok(result) => {
                 match result.action {
                     CommandType::Input => {
-                        match &result.value[0..2] {
+                        match result.value.get(0..2).unwrap_or("") {
                             "OK" => match self.get_input() {
                                 Ok(status) => Ok(status),
                                 Err(e) => Err(e),
@@ -495,49 +1182,28 @@ impl PjlinkDevice {
 
     /// Get the current Av Mute (AVMT ?) from the device
     /// Returns a Result enum with an Ok type of [pjlink::AvMute](struct.AvMute.html) example would be:
-    /// ```
+    /// ```text
     /// pjlink::AvMute::Audio or Video //with Audio and Video being a bool with the status.
     ///
     /// ```
     ///
     pub fn get_avmute(&self) -> Result<AvMute, Error> {
         match self.send("AVMT ?") {
-            Ok(result) => {
-                let status = result.value.parse::<u8>().unwrap();
-                match status {
-                    11 => Ok(AvMute {
-                        audio: false,
-                        video: true,
-                    }),
-                    21 => Ok(AvMute {
-                        audio: true,
-                        video: false,
-                    }),
-                    31 => Ok(AvMute {
-                        audio: true,
-                        video: true,
-                    }),
-                    30 => Ok(AvMute {
-                        audio: false,
-                        video: false,
-                    }),
-                    _ => Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        format!("Invalid result:: {}", status),
-                    )),
-                }
-            }
+            Ok(result) => parse_avmute(&result.value),
             Err(e) => Err(e),
         }
     }
 
     /// Set the AV Mute (AVMT 30) on the current device
     /// Returns a Result enum with an Ok type of [pjlink::AvMute](struct.AvMute.html) example would be:
-    /// ```
+    /// ```no_run
+    /// # use pjlink::{AvMute, PjlinkDevice};
+    /// # let host = "192.168.1.1";
+    /// # let device = PjlinkDevice::new(host).unwrap();
     /// let mutes = AvMute {
     ///     video: true,
     ///     audio: true,
-    /// }
+    /// };
     ///
     /// match device.set_avmute(mutes) {
     ///     Ok(mutes) => println!(
@@ -550,28 +1216,12 @@ impl PjlinkDevice {
     /// ```
     ///
     pub fn set_avmute(&self, mute_status: AvMute) -> Result<AvMute, Error> {
-        let mutes: u8 = match mute_status {
-            AvMute {
-                video: true,
-                audio: false,
-            } => 11,
-            AvMute {
-                video: false,
-                audio: true,
-            } => 21,
-            AvMute {
-                video: true,
-                audio: true,
-            } => 31,
-            _ => 30,
-        };
-
-        let command = format!("AVMT {}", mutes);
+        let command = format!("AVMT {}", mute_status.code());
         match self.send(&command) {
             Ok(result) => {
                 match result.action {
                     CommandType::AvMute => {
-                        match &result.value[0..2] {
+                        match result.value.get(0..2).unwrap_or("") {
                             "OK" => match self.get_avmute() {
                                 Ok(status) => Ok(status),
                                 Err(e) => Err(e),
@@ -594,7 +1244,7 @@ impl PjlinkDevice {
 
     /// Get the current lamp status (LAMP ?) from the device
     /// Returns a Result enum with an Ok vector of [pjlink::Lamp](struct.Lamp.html) example would be:
-    /// ```
+    /// ```text
     /// pjlink::Lamp::hours and on  //with hours being the total hours on that lamp
     /// and "on" being a bool with the status of the lamp.
     ///
@@ -602,30 +1252,17 @@ impl PjlinkDevice {
     ///
     pub fn get_lamp(&self) -> Result<Vec<Lamp>, Error> {
         match self.send("LAMP ?") {
-            Ok(result) => {
-                let mut status = result.value.split_whitespace();
-                let mut lamps = Vec::new();
-                while let Some(l) = status.next() {
-                    let hours = l.parse::<u16>().unwrap();
-
-                    let on = match status.next() {
-                        Some(x) => x == "1",
-                        None => false,
-                    };
-                    lamps.push(Lamp {
-                        hours: hours,
-                        on: on,
-                    });
-                }
-                Ok(lamps)
-            }
+            Ok(result) => parse_lamps(&result.value),
             Err(e) => Err(e),
         }
     }
 
     /// Get the current error status of the device (ERST ?)
     /// Returns a Result enum with an Ok being a [pjlink::ErrorStatus](struct.Lamp.html) example would be:
-    /// ```
+    /// ```no_run
+    /// # use pjlink::{ErrorType, PjlinkDevice};
+    /// # let host = "192.168.1.1";
+    /// # let device = PjlinkDevice::new(host).unwrap();
     /// match device.get_error_status() {
     ///    Ok(error_status) => {
     ///        match error_status.fan_error {
@@ -666,66 +1303,7 @@ impl PjlinkDevice {
     ///
     pub fn get_error_status(&self) -> Result<ErrorStatus, Error> {
         match self.send("ERST ?") {
-            Ok(result) => {
-                let mut status = result.value.chars();
-
-                Ok(ErrorStatus {
-                    fan_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                    lamp_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                    temperature_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                    cover_open_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                    filter_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                    other_error: match status.next() {
-                        Some(e) => match e {
-                            '0' => ErrorType::NoError,
-                            '1' => ErrorType::Warning,
-                            '2' => ErrorType::Error,
-                            _ => ErrorType::NoError,
-                        },
-                        None => ErrorType::NoError,
-                    },
-                })
-            }
+            Ok(result) => Ok(parse_error_status(&result.value)),
             Err(e) => Err(e),
         }
     }
@@ -0,0 +1,128 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many consecutive authorization failures (PJLINK ERRA) are allowed before the
+/// client stops talking to the device, and for how long.
+///
+/// Some projectors lock out their control port after repeated failures, so by default
+/// three failures in a row block further attempts for one minute. A `max_failures` of 0
+/// turns the lockout off.
+#[derive(Clone, Copy, Debug)]
+pub struct AuthLockout {
+    pub max_failures: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for AuthLockout {
+    fn default() -> AuthLockout {
+        AuthLockout {
+            max_failures: 3,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The error carried by a `PermissionDenied` std::io::Error when a command was refused
+/// locally because of earlier authorization failures. Nothing was sent to the device.
+///
+/// ```
+/// # use std::io::Error;
+/// use pjlink::AuthLockedOut;
+///
+/// fn locked_out(err: &Error) -> bool {
+///     err.get_ref()
+///         .map_or(false, |inner| inner.downcast_ref::<AuthLockedOut>().is_some())
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct AuthLockedOut {
+    /// The number of consecutive authorization failures
+    pub failures: u32,
+    /// How long until the device will be tried again
+    pub remaining: Duration,
+}
+
+impl fmt::Display for AuthLockedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Authorization locked out locally after {} failures, retry in {}s",
+            self.failures,
+            self.remaining.as_secs() + 1
+        )
+    }
+}
+
+impl error::Error for AuthLockedOut {}
+
+struct State {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+// Tracks the consecutive authorization failures of a single device.
+pub(crate) struct AuthTracker {
+    policy: AuthLockout,
+    state: Mutex<State>,
+}
+
+impl AuthTracker {
+    pub(crate) fn new(policy: AuthLockout) -> AuthTracker {
+        AuthTracker {
+            policy,
+            state: Mutex::new(State {
+                failures: 0,
+                locked_until: None,
+            }),
+        }
+    }
+
+    // Returns an error if we are still cooling down from too many failures.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        let state = self.state.lock().unwrap();
+        match state.locked_until {
+            Some(until) if until > Instant::now() => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                AuthLockedOut {
+                    failures: state.failures,
+                    remaining: until - Instant::now(),
+                },
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // The device answered PJLINK ERRA. Failures keep counting after a cooldown expires,
+    // so a single further failure locks the device out again.
+    pub(crate) fn failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if self.policy.max_failures > 0 && state.failures >= self.policy.max_failures {
+            state.locked_until = Some(Instant::now() + self.policy.cooldown);
+        }
+    }
+
+    // The device accepted our password, or the password was changed.
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.locked_until = None;
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use worker::{Worker, STOP_CHECK_INTERVAL};
+
+use {
+    parse_avmute, parse_error_status, parse_input, parse_power_status, parse_response, AvMute,
+    CommandType, DeviceStatus, ErrorStatus, InputType, Lamp, PjlinkDevice, PowerStatus,
+    StatusField,
+};
+
+type Callback<T> = Box<dyn Fn(T) + Send>;
+type LampCallback = Box<dyn Fn(&[Lamp]) + Send>;
+type PollErrorCallback = Box<dyn Fn(&Error) + Send>;
+
+#[derive(Default)]
+struct Callbacks {
+    power: Vec<Callback<PowerStatus>>,
+    input: Vec<Callback<InputType>>,
+    avmute: Vec<Callback<AvMute>>,
+    lamps: Vec<LampCallback>,
+    error_status: Vec<Callback<ErrorStatus>>,
+    poll_error: Vec<PollErrorCallback>,
+}
+
+impl Callbacks {
+    // Put callbacks that were taken out to run back in front of the ones added meanwhile
+    fn restore(&mut self, mut taken: Callbacks) {
+        taken.power.append(&mut self.power);
+        taken.input.append(&mut self.input);
+        taken.avmute.append(&mut self.avmute);
+        taken.lamps.append(&mut self.lamps);
+        taken.error_status.append(&mut self.error_status);
+        taken.poll_error.append(&mut self.poll_error);
+        *self = taken;
+    }
+}
+
+struct Shared {
+    device: Arc<PjlinkDevice>,
+    status: Mutex<Option<DeviceStatus>>,
+    callbacks: Mutex<Callbacks>,
+}
+
+/// Keeps track of a device on a background thread and calls back when its status changes.
+///
+/// The device is polled with `get_status_snapshot` and the latest snapshot is kept in
+/// `status`. A class 2 device that sends notifications to this controller can be heard
+/// immediately by also listening for them with `notifications`, polling then only catches
+/// what the notifications missed. Callbacks are called from the background thread for
+/// every change after the first snapshot, which only fills the cache.
+///
+/// ```
+/// use std::sync::mpsc::channel;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{ManagedDevice, PjlinkDevice, PowerStatus};
+///
+/// let emulator = Emulator::new(EmulatorConfig {
+///     warmup: Duration::from_millis(200),
+///     ..EmulatorConfig::default()
+/// });
+/// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+///
+/// let mut managed = ManagedDevice::new(Arc::new(device)).poll_interval(Duration::from_millis(50));
+/// let (sender, changes) = channel();
+/// managed.on_power_change(move |power| {
+///     let _ = sender.send(power);
+/// });
+/// managed.start().unwrap();
+///
+/// while managed.status().is_none() {
+///     std::thread::sleep(Duration::from_millis(10));
+/// }
+/// managed.device().power_on().unwrap();
+/// while changes.recv_timeout(Duration::from_secs(5)).unwrap() != PowerStatus::On {}
+///
+/// managed.stop();
+/// ```
+pub struct ManagedDevice {
+    shared: Arc<Shared>,
+    poll_interval: Duration,
+    notifications: Option<SocketAddr>,
+    running: Option<Worker>,
+}
+
+impl ManagedDevice {
+    /// Manage a device, polling it every 5 seconds once started
+    pub fn new(device: Arc<PjlinkDevice>) -> ManagedDevice {
+        ManagedDevice {
+            shared: Arc::new(Shared {
+                device,
+                status: Mutex::new(None),
+                callbacks: Mutex::new(Callbacks::default()),
+            }),
+            poll_interval: Duration::from_secs(5),
+            notifications: None,
+            running: None,
+        }
+    }
+
+    /// How often the device is polled
+    pub fn poll_interval(mut self, poll_interval: Duration) -> ManagedDevice {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Listen for class 2 status notifications on this address, usually port 4352 on
+    /// every interface. Only notifications sent from the device's address are used.
+    pub fn notifications(mut self, addr: SocketAddr) -> ManagedDevice {
+        self.notifications = Some(addr);
+        self
+    }
+
+    /// The device being managed, commands can still be sent to it while it is managed
+    pub fn device(&self) -> &PjlinkDevice {
+        &self.shared.device
+    }
+
+    /// The latest status, None until the first snapshot has been taken
+    pub fn status(&self) -> Option<DeviceStatus> {
+        self.shared.status.lock().unwrap().clone()
+    }
+
+    /// Called with the new power status when it changes
+    pub fn on_power_change<F: Fn(PowerStatus) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.power.push(Box::new(callback));
+    }
+
+    /// Called with the new input when it changes or becomes available after warming up
+    pub fn on_input_change<F: Fn(InputType) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.input.push(Box::new(callback));
+    }
+
+    /// Called with the new AV mute when it changes
+    pub fn on_avmute_change<F: Fn(AvMute) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.avmute.push(Box::new(callback));
+    }
+
+    /// Called with the lamps when a lamp is turned on or off or its hours change
+    pub fn on_lamp_change<F: Fn(&[Lamp]) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.lamps.push(Box::new(callback));
+    }
+
+    /// Called with the new error status when it changes
+    pub fn on_error_change<F: Fn(ErrorStatus) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.error_status.push(Box::new(callback));
+    }
+
+    /// Called with the error when polling the device fails, e.g. because it can't be
+    /// reached or the password is wrong. Once too many authorization failures have locked
+    /// the device out the error carries a [pjlink::AuthLockedOut](struct.AuthLockedOut.html).
+    /// The cached status is kept and polling goes on.
+    pub fn on_poll_error<F: Fn(&Error) + Send + 'static>(&self, callback: F) {
+        let mut callbacks = self.shared.callbacks.lock().unwrap();
+        callbacks.poll_error.push(Box::new(callback));
+    }
+
+    /// Is the background thread running
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start polling and listening for notifications on a background thread.
+    /// Starting a device that is already running does nothing.
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+
+        let listener = match self.notifications {
+            Some(addr) => {
+                let socket = UdpSocket::bind(addr)?;
+                socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+                Some(Listener {
+                    socket,
+                    sources: device_addresses(&self.shared.device),
+                })
+            }
+            None => None,
+        };
+
+        let shared = Arc::clone(&self.shared);
+        let poll_interval = self.poll_interval;
+        self.running = Some(Worker::spawn(
+            format!("pjlink managed {}", shared.device.host),
+            move |stop| run(&shared, listener, poll_interval, stop),
+        )?);
+        Ok(())
+    }
+
+    /// Stop the background thread and wait for it to finish. The cached status is kept.
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+}
+
+struct Listener {
+    socket: UdpSocket,
+    // The addresses the device's host name resolves to, empty if it didn't resolve
+    sources: Vec<IpAddr>,
+}
+
+fn device_addresses(device: &PjlinkDevice) -> Vec<IpAddr> {
+    match (device.host.as_str(), device.port).to_socket_addrs() {
+        Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn run(shared: &Shared, listener: Option<Listener>, poll_interval: Duration, stop: &AtomicBool) {
+    let mut next_poll = Instant::now();
+    let mut buffer = [0u8; 256];
+
+    while !stop.load(Ordering::SeqCst) {
+        if Instant::now() >= next_poll {
+            match shared.device.get_status_snapshot() {
+                Ok(status) => update(shared, status),
+                Err(e) => call_back(shared, |callbacks| {
+                    callbacks
+                        .poll_error
+                        .iter()
+                        .for_each(|callback| callback(&e))
+                }),
+            }
+            next_poll = Instant::now() + poll_interval;
+        }
+
+        match listener {
+            Some(ref listener) => match listener.socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    if listener.sources.is_empty() || listener.sources.contains(&from.ip()) {
+                        let text = String::from_utf8_lossy(&buffer[..len]);
+                        notify(shared, text.trim_end());
+                    }
+                }
+                Err(ref e)
+                    if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(_) => thread::sleep(STOP_CHECK_INTERVAL),
+            },
+            None => {
+                let wait = next_poll.saturating_duration_since(Instant::now());
+                thread::sleep(wait.min(STOP_CHECK_INTERVAL));
+            }
+        }
+    }
+}
+
+// The value if it is new, i.e. the device answered and it wasn't answering the same before
+fn changed<'a, T: PartialEq>(old: &StatusField<T>, new: &'a StatusField<T>) -> Option<&'a T> {
+    match new.value() {
+        Some(value) if old.value() != Some(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Replace the cached status with a new snapshot and call back for what changed
+fn update(shared: &Shared, status: DeviceStatus) {
+    let old = shared.status.lock().unwrap().replace(status.clone());
+    let old = match old {
+        Some(old) => old,
+        None => return,
+    };
+
+    call_back(shared, |callbacks| {
+        call_back_changes(callbacks, &old, &status)
+    });
+}
+
+// Run callbacks outside the lock so that they can add callbacks of their own.
+// Only the background thread runs them, so nothing else takes them out meanwhile.
+fn call_back<F: FnOnce(&Callbacks)>(shared: &Shared, run: F) {
+    let callbacks = mem::take(&mut *shared.callbacks.lock().unwrap());
+    run(&callbacks);
+    shared.callbacks.lock().unwrap().restore(callbacks);
+}
+
+fn call_back_changes(callbacks: &Callbacks, old: &DeviceStatus, status: &DeviceStatus) {
+    if let Some(&power) = changed(&old.power, &status.power) {
+        callbacks.power.iter().for_each(|callback| callback(power));
+    }
+    if let Some(&input) = changed(&old.input, &status.input) {
+        callbacks.input.iter().for_each(|callback| callback(input));
+    }
+    if let Some(&avmute) = changed(&old.avmute, &status.avmute) {
+        callbacks
+            .avmute
+            .iter()
+            .for_each(|callback| callback(avmute));
+    }
+    if let Some(lamps) = changed(&old.lamps, &status.lamps) {
+        callbacks.lamps.iter().for_each(|callback| callback(lamps));
+    }
+    if let Some(&error_status) = changed(&old.error_status, &status.error_status) {
+        callbacks
+            .error_status
+            .iter()
+            .for_each(|callback| callback(error_status));
+    }
+}
+
+// Apply a class 2 notification such as %2POWR=1 to the cached status
+fn notify(shared: &Shared, notification: &str) {
+    let response = match parse_response(notification) {
+        Ok(response) => response,
+        Err(_) => return,
+    };
+
+    let mut status = match *shared.status.lock().unwrap() {
+        Some(ref status) => status.clone(),
+        // Nothing to compare with until the first snapshot
+        None => return,
+    };
+    match response.action {
+        CommandType::Power => match parse_power_status(&response.value) {
+            Ok(power) => status.power = StatusField::Value(power),
+            Err(_) => return,
+        },
+        CommandType::Input => {
+            let extended = shared.device.quirks().extended_inputs;
+            match parse_input(&response.value, extended) {
+                Ok(input) => status.input = StatusField::Value(input),
+                Err(_) => return,
+            }
+        }
+        CommandType::AvMute => match parse_avmute(&response.value) {
+            Ok(avmute) => status.avmute = StatusField::Value(avmute),
+            Err(_) => return,
+        },
+        CommandType::ErrorStatus => {
+            status.error_status = StatusField::Value(parse_error_status(&response.value))
+        }
+        _ => return,
+    }
+    update(shared, status);
+}
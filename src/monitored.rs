@@ -0,0 +1,150 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error;
+use std::sync::Arc;
+
+use {AvMute, DeviceStatus, ErrorStatus, InputType, IntoPassword, Lamp, PjlinkDevice, PowerStatus};
+
+/// A handle to a device that can only be queried.
+///
+/// It has the query methods of [pjlink::PjlinkDevice](struct.PjlinkDevice.html) and none
+/// of the methods that change the device, so code given a MonitoredDevice can observe a
+/// projector without any way to turn it off or switch its input. Clones share the device.
+///
+/// ```
+/// use std::sync::Arc;
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{MonitoredDevice, PjlinkDevice, PowerStatus};
+///
+/// let server = Server::bind("127.0.0.1:0", Emulator::new(EmulatorConfig::default()))
+///     .unwrap()
+///     .spawn()
+///     .unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+///
+/// // Keep control of the device and hand out a read-only view of it
+/// let device = Arc::new(device);
+/// let dashboard = MonitoredDevice::from(Arc::clone(&device));
+///
+/// device.power_on().unwrap();
+/// assert_eq!(dashboard.get_power_status().unwrap(), PowerStatus::Warmup);
+/// ```
+///
+/// ```compile_fail
+/// # use pjlink::MonitoredDevice;
+/// let dashboard = MonitoredDevice::new("192.168.1.1").unwrap();
+/// dashboard.power_off();
+/// ```
+#[derive(Clone)]
+pub struct MonitoredDevice {
+    device: Arc<PjlinkDevice>,
+}
+
+impl From<PjlinkDevice> for MonitoredDevice {
+    fn from(device: PjlinkDevice) -> MonitoredDevice {
+        MonitoredDevice {
+            device: Arc::new(device),
+        }
+    }
+}
+
+impl From<Arc<PjlinkDevice>> for MonitoredDevice {
+    fn from(device: Arc<PjlinkDevice>) -> MonitoredDevice {
+        MonitoredDevice { device }
+    }
+}
+
+impl MonitoredDevice {
+    /// Constructs a new MonitoredDevice.
+    pub fn new(host: &str) -> Result<MonitoredDevice, Error> {
+        PjlinkDevice::new(host).map(MonitoredDevice::from)
+    }
+
+    /// Contructs a new MonitoredDevice that has a password, see `PjlinkDevice::new_with_password`
+    pub fn new_with_password<P: IntoPassword>(
+        host: &str,
+        password: P,
+    ) -> Result<MonitoredDevice, Error> {
+        PjlinkDevice::new_with_password(host, password).map(MonitoredDevice::from)
+    }
+
+    /// The host of the device
+    pub fn host(&self) -> &str {
+        &self.device.host
+    }
+
+    /// The port of the device
+    pub fn port(&self) -> u16 {
+        self.device.port
+    }
+
+    /// See `PjlinkDevice::get_power_status`
+    pub fn get_power_status(&self) -> Result<PowerStatus, Error> {
+        self.device.get_power_status()
+    }
+
+    /// See `PjlinkDevice::get_input`
+    pub fn get_input(&self) -> Result<InputType, Error> {
+        self.device.get_input()
+    }
+
+    /// See `PjlinkDevice::get_avmute`
+    pub fn get_avmute(&self) -> Result<AvMute, Error> {
+        self.device.get_avmute()
+    }
+
+    /// See `PjlinkDevice::get_lamp`
+    pub fn get_lamp(&self) -> Result<Vec<Lamp>, Error> {
+        self.device.get_lamp()
+    }
+
+    /// See `PjlinkDevice::get_error_status`
+    pub fn get_error_status(&self) -> Result<ErrorStatus, Error> {
+        self.device.get_error_status()
+    }
+
+    /// See `PjlinkDevice::get_info`
+    pub fn get_info(&self) -> Result<String, Error> {
+        self.device.get_info()
+    }
+
+    /// See `PjlinkDevice::get_manufacturer`
+    pub fn get_manufacturer(&self) -> Result<String, Error> {
+        self.device.get_manufacturer()
+    }
+
+    /// See `PjlinkDevice::get_product_name`
+    pub fn get_product_name(&self) -> Result<String, Error> {
+        self.device.get_product_name()
+    }
+
+    /// See `PjlinkDevice::get_class`
+    pub fn get_class(&self) -> Result<String, Error> {
+        self.device.get_class()
+    }
+
+    /// See `PjlinkDevice::get_device_name`
+    pub fn get_device_name(&self) -> Result<String, Error> {
+        self.device.get_device_name()
+    }
+
+    /// See `PjlinkDevice::get_status_snapshot`
+    pub fn get_status_snapshot(&self) -> Result<DeviceStatus, Error> {
+        self.device.get_status_snapshot()
+    }
+}
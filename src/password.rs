@@ -0,0 +1,183 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// The PJLink specification limits passwords to 32 bytes.
+pub const MAX_PASSWORD_LEN: usize = 32;
+
+/// A PJLink password.
+///
+/// The password is validated when it is constructed (1 to 32 bytes of printable ASCII),
+/// is never printed by `Debug` and the memory holding it is zeroed when it is dropped.
+///
+/// ```
+/// use pjlink::Password;
+///
+/// let password = Password::new("JBMIAProjectorLink").unwrap();
+/// assert_eq!(format!("{:?}", password), "Password(<redacted>)");
+///
+/// assert!(Password::new("").is_err());
+/// assert!(Password::new("this password is much longer than 32 bytes").is_err());
+/// ```
+pub struct Password {
+    secret: Vec<u8>,
+}
+
+impl Password {
+    /// Validates and wraps a password. Owned strings are taken over without being copied.
+    pub fn new<S: Into<String>>(password: S) -> Result<Password, Error> {
+        Password::from_bytes(password.into().into_bytes())
+    }
+
+    // Validation happens after the bytes are wrapped so they are zeroed even when rejected.
+    fn from_bytes(secret: Vec<u8>) -> Result<Password, Error> {
+        let password = Password { secret };
+
+        if password.secret.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The password must not be empty.",
+            ));
+        }
+        if password.secret.len() > MAX_PASSWORD_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The password must be no longer than {} bytes.",
+                    MAX_PASSWORD_LEN
+                ),
+            ));
+        }
+        if !password.secret.iter().all(|b| (0x20..=0x7e).contains(b)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The password may only contain printable ASCII characters.",
+            ));
+        }
+
+        Ok(password)
+    }
+
+    /// Reads the password from an environment variable.
+    pub fn from_env(name: &str) -> Result<Password, Error> {
+        match env::var(name) {
+            Ok(password) => Password::new(password),
+            Err(_) => Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "The environment variable {} is not set or is not valid unicode.",
+                    name
+                ),
+            )),
+        }
+    }
+
+    /// Reads the password from the first line of a file, ignoring the line ending.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Password, Error> {
+        let mut file = File::open(path)?;
+
+        // A buffer that is never reallocated, with room for the longest password and a
+        // CRLF, so no copy of the secret is left behind. Anything longer is too long anyway.
+        let mut password = Password {
+            secret: vec![0; MAX_PASSWORD_LEN + 2],
+        };
+        let mut len = 0;
+        while len < password.secret.len() {
+            match file.read(&mut password.secret[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let end = password.secret[..len]
+            .iter()
+            .position(|&b| b == b'\r' || b == b'\n')
+            .unwrap_or(len);
+        password.zero_from(end);
+        password.secret.truncate(end);
+
+        Password::from_bytes(::std::mem::take(&mut password.secret))
+    }
+
+    // The password as it is sent to the device. Only used to compute the digest.
+    pub(crate) fn expose(&self) -> &[u8] {
+        &self.secret
+    }
+
+    fn zero_from(&mut self, start: usize) {
+        for byte in self.secret[start..].iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.zero_from(0);
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Password(<redacted>)")
+    }
+}
+
+/// Anything that can be turned into a validated [Password](struct.Password.html).
+/// This lets `new_with_password` and the builder accept either a string or a `Password`.
+/// An empty string means no password, as it did before passwords were validated.
+pub trait IntoPassword {
+    fn into_password(self) -> Result<Option<Password>, Error>;
+}
+
+impl IntoPassword for Password {
+    fn into_password(self) -> Result<Option<Password>, Error> {
+        Ok(Some(self))
+    }
+}
+
+impl IntoPassword for &str {
+    fn into_password(self) -> Result<Option<Password>, Error> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        Password::new(self).map(Some)
+    }
+}
+
+impl IntoPassword for &String {
+    fn into_password(self) -> Result<Option<Password>, Error> {
+        self.as_str().into_password()
+    }
+}
+
+impl IntoPassword for String {
+    fn into_password(self) -> Result<Option<Password>, Error> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        Password::new(self).map(Some)
+    }
+}
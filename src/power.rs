@@ -0,0 +1,243 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use retry;
+use {PjlinkDevice, PowerStatus};
+
+/// How often the power status is polled by `power_on_and_wait` and `power_off_and_wait`
+pub const POWER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The error carried by a `TimedOut` std::io::Error when the device did not reach
+/// the requested power status in time.
+#[derive(Clone, Copy, Debug)]
+pub struct PowerTimeout {
+    /// The power status we were waiting for
+    pub target: PowerStatus,
+    /// The last power status reported by the device, if it answered at all
+    pub last: Option<PowerStatus>,
+}
+
+impl fmt::Display for PowerTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.last {
+            Some(last) => write!(
+                f,
+                "Timed out waiting for power {:?}, last status was {:?}",
+                self.target, last
+            ),
+            None => write!(
+                f,
+                "Timed out waiting for power {:?}, the device never reported a status",
+                self.target
+            ),
+        }
+    }
+}
+
+impl error::Error for PowerTimeout {}
+
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+impl PjlinkDevice {
+    /// Turn on the device and wait until it reports that it is on.
+    /// A device that is still cooling down is allowed to finish before it is turned on.
+    /// Returns a `TimedOut` error carrying a [pjlink::PowerTimeout](struct.PowerTimeout.html)
+    /// if the device is not on within the timeout.
+    pub fn power_on_and_wait(&self, timeout: Duration) -> Result<PowerStatus, Error> {
+        self.power_on_and_wait_with_progress(timeout, |_| ())
+    }
+
+    /// Same as `power_on_and_wait` with a callback for every power status change seen
+    pub fn power_on_and_wait_with_progress<F>(
+        &self,
+        timeout: Duration,
+        progress: F,
+    ) -> Result<PowerStatus, Error>
+    where
+        F: FnMut(PowerStatus),
+    {
+        self.change_power_and_wait(PowerStatus::On, timeout, progress)
+    }
+
+    /// Turn off the device and wait until it reports that it is off.
+    /// A device that is still warming up is allowed to finish before it is turned off.
+    /// Returns a `TimedOut` error carrying a [pjlink::PowerTimeout](struct.PowerTimeout.html)
+    /// if the device is not off within the timeout.
+    pub fn power_off_and_wait(&self, timeout: Duration) -> Result<PowerStatus, Error> {
+        self.power_off_and_wait_with_progress(timeout, |_| ())
+    }
+
+    /// Same as `power_off_and_wait` with a callback for every power status change seen
+    pub fn power_off_and_wait_with_progress<F>(
+        &self,
+        timeout: Duration,
+        progress: F,
+    ) -> Result<PowerStatus, Error>
+    where
+        F: FnMut(PowerStatus),
+    {
+        self.change_power_and_wait(PowerStatus::Off, timeout, progress)
+    }
+
+    /// Poll the power status (POWR ?) until the device reports `target`.
+    ///
+    /// ERR3 replies and transient connection errors are treated as "not there yet" since
+    /// many devices refuse connections while warming up or cooling down.
+    /// Returns a `TimedOut` error carrying a [pjlink::PowerTimeout](struct.PowerTimeout.html)
+    /// with the last observed status if the timeout expires first.
+    /// A poll that is under way when the timeout expires can take up to the device's
+    /// I/O timeout to give up, see `PjlinkDeviceBuilder::io_timeout`.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use pjlink::{PjlinkDevice, PowerStatus};
+    ///
+    /// let device = PjlinkDevice::new("192.168.1.1").unwrap();
+    /// device.power_on().unwrap();
+    /// match device.wait_for_power(PowerStatus::On, Duration::from_secs(90), Duration::from_secs(2)) {
+    ///     Ok(_) => println!("Device is on"),
+    ///     Err(err) => println!("An error occurred: {}", err),
+    /// }
+    /// ```
+    pub fn wait_for_power(
+        &self,
+        target: PowerStatus,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<PowerStatus, Error> {
+        self.wait_for_power_with_progress(target, timeout, poll_interval, |_| ())
+    }
+
+    /// Same as `wait_for_power` with a callback for every power status change seen
+    pub fn wait_for_power_with_progress<F>(
+        &self,
+        target: PowerStatus,
+        timeout: Duration,
+        poll_interval: Duration,
+        mut progress: F,
+    ) -> Result<PowerStatus, Error>
+    where
+        F: FnMut(PowerStatus),
+    {
+        let deadline = Instant::now() + timeout;
+        let mut last = None;
+
+        loop {
+            match self.get_power_status() {
+                Ok(status) => {
+                    if last != Some(status) {
+                        progress(status);
+                        last = Some(status);
+                    }
+                    if status == target {
+                        return Ok(status);
+                    }
+                }
+                Err(e) => {
+                    if !retry::is_retryable(&e) {
+                        return Err(e);
+                    }
+                }
+            }
+
+            let left = remaining(deadline);
+            if left == Duration::from_secs(0) {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    PowerTimeout { target, last },
+                ));
+            }
+            thread::sleep(poll_interval.min(left));
+        }
+    }
+
+    // Turn the device on or off and follow it through warmup or cooling.
+    // A device already warming up or cooling down towards the target is only waited for,
+    // since devices answer ERR3 to a power command in the middle of a transition.
+    // ERR3 and transient errors are retried until the deadline, like in wait_for_power.
+    fn change_power_and_wait<F>(
+        &self,
+        target: PowerStatus,
+        timeout: Duration,
+        mut progress: F,
+    ) -> Result<PowerStatus, Error>
+    where
+        F: FnMut(PowerStatus),
+    {
+        let deadline = Instant::now() + timeout;
+
+        // Each status is reported once even though it is seen by several polls
+        let mut last = None;
+        let mut report = |status: PowerStatus, last: &mut Option<PowerStatus>| {
+            if *last != Some(status) {
+                progress(status);
+                *last = Some(status);
+            }
+        };
+
+        // Most devices refuse to change direction half way through a transition
+        let (heading, busy) = match target {
+            PowerStatus::On => (PowerStatus::Warmup, PowerStatus::Cooling),
+            _ => (PowerStatus::Cooling, PowerStatus::Warmup),
+        };
+        loop {
+            // Ok(true) once the device is on its way to the target
+            let under_way = self.get_power_status().and_then(|current| {
+                report(current, &mut last);
+                if current == heading || current == target {
+                    Ok(true)
+                } else if current == busy {
+                    // Wait for the other transition to end first
+                    Ok(false)
+                } else {
+                    match target {
+                        PowerStatus::On => self.power_on(),
+                        _ => self.power_off(),
+                    }
+                    .map(|_| true)
+                }
+            });
+            match under_way {
+                Ok(true) => {
+                    return self.wait_for_power_with_progress(
+                        target,
+                        remaining(deadline),
+                        POWER_POLL_INTERVAL,
+                        |status| report(status, &mut last),
+                    )
+                }
+                Ok(false) => (),
+                Err(ref e) if retry::is_retryable(e) => (),
+                Err(e) => return Err(e),
+            }
+
+            let left = remaining(deadline);
+            if left == Duration::from_secs(0) {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    PowerTimeout { target, last },
+                ));
+            }
+            thread::sleep(POWER_POLL_INTERVAL.min(left));
+        }
+    }
+}
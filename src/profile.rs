@@ -0,0 +1,221 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use {pjlink_error, PjlinkDevice, PjlinkErrorCode};
+
+// The queries every device gets, as (class, command)
+static CLASS1_QUERIES: &[(u8, &str)] = &[
+    (1, "CLSS ?"),
+    (1, "INF1 ?"),
+    (1, "INF2 ?"),
+    (1, "INFO ?"),
+    (1, "NAME ?"),
+    (1, "INST ?"),
+    (1, "LAMP ?"),
+    (1, "ERST ?"),
+];
+
+// The identity queries added by class 2
+static CLASS2_QUERIES: &[(u8, &str)] = &[
+    (2, "SNUM ?"),
+    (2, "SVER ?"),
+    (2, "INST ?"),
+    (2, "RRES ?"),
+    (2, "FILT ?"),
+    (2, "RLMP ?"),
+    (2, "RFIL ?"),
+];
+
+/// The exact responses a device gave to its identity and status queries.
+///
+/// A profile is captured from a real device with `capture` and saved to a text file
+/// with one `request<TAB>response` pair per line, for example `%1INF1 ?` and `%1INF1=EPSON`.
+/// The emulator can replay it with `Server::replay` to answer exactly like the device did,
+/// error codes included.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceProfile {
+    responses: Vec<(String, String)>,
+}
+
+impl DeviceProfile {
+    /// An empty profile
+    pub fn new() -> DeviceProfile {
+        DeviceProfile::default()
+    }
+
+    /// Run every supported query against the device and record the raw responses.
+    /// The class 2 identity queries are only sent to class 2 devices.
+    /// Fails on the first query the device could not be asked, see `capture_partial`
+    /// to keep going.
+    pub fn capture(device: &PjlinkDevice) -> Result<DeviceProfile, Error> {
+        let (profile, failures) = DeviceProfile::capture_partial(device);
+        match failures.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(profile),
+        }
+    }
+
+    /// Same as `capture`, recording what the device answered and returning the requests
+    /// that failed, e.g. because the connection dropped, with their errors. Capturing
+    /// stops at an authorization failure since every further query would fail too and
+    /// count towards the device's lockout.
+    pub fn capture_partial(device: &PjlinkDevice) -> (DeviceProfile, Vec<(String, Error)>) {
+        let mut capture = Capture {
+            device,
+            profile: DeviceProfile::new(),
+            failures: Vec::new(),
+            stopped: false,
+        };
+        for &(class, command) in CLASS1_QUERIES {
+            capture.record(class, command);
+        }
+
+        if capture
+            .profile
+            .value("%1CLSS ?")
+            .and_then(|class| class.trim().parse::<u8>().ok())
+            .is_some_and(|class| class >= 2)
+        {
+            for &(class, command) in CLASS2_QUERIES {
+                capture.record(class, command);
+            }
+            let inputs: Vec<String> = capture
+                .profile
+                .value("%2INST ?")
+                .map(|inputs| inputs.split_whitespace().map(String::from).collect())
+                .unwrap_or_default();
+            for input in inputs {
+                capture.record(2, &format!("INNM ?{}", input));
+            }
+        }
+
+        (capture.profile, capture.failures)
+    }
+
+    /// Set the response to a request such as "%1NAME ?"
+    pub fn insert(&mut self, request: &str, response: &str) {
+        match self.responses.iter_mut().find(|entry| entry.0 == request) {
+            Some(entry) => entry.1 = response.to_string(),
+            None => self
+                .responses
+                .push((request.to_string(), response.to_string())),
+        }
+    }
+
+    /// The recorded response to a request such as "%1NAME ?"
+    pub fn response(&self, request: &str) -> Option<&str> {
+        self.responses
+            .iter()
+            .find(|entry| entry.0 == request)
+            .map(|entry| entry.1.as_str())
+    }
+
+    /// The value part of a recorded response, after the '=', unless it is an error code
+    pub fn value(&self, request: &str) -> Option<&str> {
+        self.response(request)
+            .and_then(|response| response.find('=').map(|i| &response[i + 1..]))
+            .filter(|value| !(value.len() == 4 && value.starts_with("ERR")))
+    }
+
+    /// The recorded requests and responses in the order they were recorded
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.responses
+            .iter()
+            .map(|entry| (entry.0.as_str(), entry.1.as_str()))
+    }
+
+    /// Parse a profile from the text format, blank lines and lines starting with # are ignored
+    pub fn parse(text: &str) -> Result<DeviceProfile, Error> {
+        let mut profile = DeviceProfile::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find('\t') {
+                Some(tab) => profile.insert(&line[..tab], &line[tab + 1..]),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Line {} of the profile has no tab: {}", number + 1, line),
+                    ))
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Load a profile saved with `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DeviceProfile, Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        DeviceProfile::parse(&text)
+    }
+
+    /// Save the profile to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        File::create(path)?.write_all(self.to_string().as_bytes())
+    }
+}
+
+impl fmt::Display for DeviceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# PJLink device profile: request<TAB>response")?;
+        for (request, response) in self.iter() {
+            writeln!(f, "{}\t{}", request, response)?;
+        }
+        Ok(())
+    }
+}
+
+struct Capture<'a> {
+    device: &'a PjlinkDevice,
+    profile: DeviceProfile,
+    failures: Vec<(String, Error)>,
+    stopped: bool,
+}
+
+impl<'a> Capture<'a> {
+    fn record(&mut self, class: u8, command: &str) {
+        if self.stopped {
+            return;
+        }
+        let request = format!("%{}{}", class, command);
+        match self.device.send_class_command(class, command) {
+            Ok(response) => {
+                if response.starts_with("PJLINK ERRA") {
+                    self.stopped = true;
+                    self.failures.push((request, pjlink_error("ERRA")));
+                } else {
+                    self.profile.insert(&request, &response);
+                }
+            }
+            Err(e) => {
+                // Locked out, or a password problem found before sending
+                if e.kind() == ErrorKind::PermissionDenied
+                    || PjlinkErrorCode::from_error(&e) == Some(PjlinkErrorCode::Authorization)
+                {
+                    self.stopped = true;
+                }
+                self.failures.push((request, e));
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use {AvMute, InputType, PjlinkDevice, PowerStatus, POWER_POLL_INTERVAL};
+
+/// A command that can be queued on a [pjlink::CommandQueue](struct.CommandQueue.html)
+#[derive(Clone, Debug)]
+pub enum QueuedCommand {
+    PowerOn,
+    PowerOff,
+    /// Held until the device is on
+    SetInput(InputType),
+    /// Held until the device is on
+    SetAvMute(AvMute),
+    /// A raw command sent with `send_command`, it is never held
+    Raw(String),
+}
+
+impl QueuedCommand {
+    /// Does the device have to be on before this command can be sent
+    pub fn needs_power(&self) -> bool {
+        matches!(
+            *self,
+            QueuedCommand::SetInput(_) | QueuedCommand::SetAvMute(_)
+        )
+    }
+}
+
+/// The result of a queued command that succeeded
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandOutcome {
+    Power(PowerStatus),
+    Input(InputType),
+    AvMute(AvMute),
+    Raw(String),
+}
+
+/// A handle to the result of a queued command
+pub struct PendingCommand {
+    receiver: Receiver<Result<CommandOutcome, Error>>,
+}
+
+impl PendingCommand {
+    /// Block until the command has been executed
+    pub fn wait(self) -> Result<CommandOutcome, Error> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(queue_closed()),
+        }
+    }
+
+    /// The result of the command if it has been executed
+    pub fn try_result(&self) -> Option<Result<CommandOutcome, Error>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(queue_closed())),
+        }
+    }
+}
+
+fn queue_closed() -> Error {
+    Error::other("The command queue stopped before the command was executed.")
+}
+
+struct Job {
+    command: QueuedCommand,
+    reply: Sender<Result<CommandOutcome, Error>>,
+}
+
+/// Executes commands for one device in order on a background thread.
+///
+/// Commands can be queued at any time. Commands that only work while the device is on
+/// (changing the input or the AV mute) are held until `POWR ?` reports that it is on,
+/// and everything queued after them waits its turn. A held command fails with a
+/// [pjlink::PowerTimeout](struct.PowerTimeout.html) if the device is not on within the
+/// hold timeout, and the queue moves on to the next command.
+///
+/// Power commands wait for a warmup or cooling in the other direction to end before
+/// they are sent, since devices refuse them half way through a transition.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use pjlink::{AvMute, CommandQueue, InputType, PjlinkDevice, QueuedCommand};
+///
+/// let device = Arc::new(PjlinkDevice::new("192.168.1.1").unwrap());
+/// let queue = CommandQueue::new(device);
+///
+/// let power = queue.push(QueuedCommand::PowerOn);
+/// let input = queue.push(QueuedCommand::SetInput(InputType::Digital(1)));
+/// let mute = queue.push(QueuedCommand::SetAvMute(AvMute { audio: false, video: false }));
+///
+/// for pending in vec![power, input, mute] {
+///     match pending.wait() {
+///         Ok(outcome) => println!("Done: {:?}", outcome),
+///         Err(err) => println!("An error occurred: {}", err),
+///     }
+/// }
+/// ```
+pub struct CommandQueue {
+    sender: Option<Sender<Job>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl CommandQueue {
+    /// Start a queue that holds commands for up to two minutes
+    pub fn new(device: Arc<PjlinkDevice>) -> CommandQueue {
+        CommandQueue::with_hold_timeout(device, Duration::from_secs(120), POWER_POLL_INTERVAL)
+    }
+
+    /// Start a queue that holds commands for up to `hold_timeout`, polling the
+    /// power status every `poll_interval` while it waits.
+    pub fn with_hold_timeout(
+        device: Arc<PjlinkDevice>,
+        hold_timeout: Duration,
+        poll_interval: Duration,
+    ) -> CommandQueue {
+        let (sender, receiver) = channel::<Job>();
+        let worker = thread::spawn(move || {
+            for job in receiver.iter() {
+                let result = execute(&device, job.command, hold_timeout, poll_interval);
+                // Nobody may be waiting for the result which is fine.
+                let _ = job.reply.send(result);
+            }
+        });
+
+        CommandQueue {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Add a command to the end of the queue
+    pub fn push(&self, command: QueuedCommand) -> PendingCommand {
+        let (reply, receiver) = channel();
+        if let Some(ref sender) = self.sender {
+            // If the worker is gone the reply sender is dropped and
+            // the PendingCommand reports that the queue stopped.
+            let _ = sender.send(Job { command, reply });
+        }
+        PendingCommand { receiver }
+    }
+
+    /// Stop accepting commands and wait for the queued ones to be executed.
+    /// Dropping the queue instead lets them finish in the background.
+    pub fn finish(mut self) {
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn execute(
+    device: &PjlinkDevice,
+    command: QueuedCommand,
+    hold_timeout: Duration,
+    poll_interval: Duration,
+) -> Result<CommandOutcome, Error> {
+    if command.needs_power() {
+        match device.get_power_status() {
+            Ok(PowerStatus::On) => (),
+            _ => {
+                device.wait_for_power(PowerStatus::On, hold_timeout, poll_interval)?;
+            }
+        }
+    }
+
+    match command {
+        QueuedCommand::PowerOn => {
+            change_power(device, PowerStatus::On, hold_timeout, poll_interval)
+                .map(CommandOutcome::Power)
+        }
+        QueuedCommand::PowerOff => {
+            change_power(device, PowerStatus::Off, hold_timeout, poll_interval)
+                .map(CommandOutcome::Power)
+        }
+        QueuedCommand::SetInput(input) => device.set_input(input).map(CommandOutcome::Input),
+        QueuedCommand::SetAvMute(mute) => device.set_avmute(mute).map(CommandOutcome::AvMute),
+        QueuedCommand::Raw(raw) => device.send_command(&raw).map(CommandOutcome::Raw),
+    }
+}
+
+// Devices answer ERR3 to a power command in the middle of a transition, so a device
+// cooling down is allowed to reach Off before it is turned on (and a device warming up
+// to reach On before it is turned off). A device already heading for the target is left alone.
+fn change_power(
+    device: &PjlinkDevice,
+    target: PowerStatus,
+    hold_timeout: Duration,
+    poll_interval: Duration,
+) -> Result<PowerStatus, Error> {
+    let (heading, busy, settled) = match target {
+        PowerStatus::On => (PowerStatus::Warmup, PowerStatus::Cooling, PowerStatus::Off),
+        _ => (PowerStatus::Cooling, PowerStatus::Warmup, PowerStatus::On),
+    };
+    match device.get_power_status()? {
+        status if status == target || status == heading => return Ok(status),
+        status if status == busy => {
+            device.wait_for_power(settled, hold_timeout, poll_interval)?;
+        }
+        _ => (),
+    }
+    match target {
+        PowerStatus::On => device.power_on(),
+        _ => device.power_off(),
+    }
+}
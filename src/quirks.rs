@@ -0,0 +1,165 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+/// Ways a device deviates from the specification that the client can work around.
+///
+/// Quirks are set with `PjlinkDeviceBuilder::quirks` or picked by manufacturer and
+/// product name with `PjlinkDevice::detect_quirks`, from the devices added with
+/// `PjlinkDeviceBuilder::known_quirks` and then from the built-in table, see `Quirks::lookup`.
+///
+/// ```
+/// use std::time::Duration;
+/// use pjlink::{PjlinkDevice, Quirks};
+///
+/// let device = PjlinkDevice::builder("192.168.1.1")
+///     .quirks(Quirks {
+///         greeting_delay: Duration::from_millis(200),
+///         lenient_replies: true,
+///         ..Quirks::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Wait this long after the greeting before sending the command
+    pub greeting_delay: Duration,
+    /// The device closes the connection after every reply and needs
+    /// `reconnect_delay` before it accepts the next one
+    pub close_after_reply: bool,
+    /// How long to wait between connections when `close_after_reply` is set
+    pub reconnect_delay: Duration,
+    /// Accept lowercase `ok` and `errN` and whitespace around the reply
+    pub lenient_replies: bool,
+    /// Accept input numbers outside 1-9, as a letter (`3A` for Digital 10) or
+    /// as several digits (`310`). Inputs above 9 are sent as letters.
+    pub extended_inputs: bool,
+}
+
+// A device that follows the specification, the base of the built-in entries
+const NONE: Quirks = Quirks {
+    greeting_delay: Duration::from_millis(0),
+    close_after_reply: false,
+    reconnect_delay: Duration::from_millis(0),
+    lenient_replies: false,
+    extended_inputs: false,
+};
+
+// The built-in table as (INF1 ?, start of INF2 ?, quirks), in uppercase.
+// An empty product matches every product of the manufacturer, the first match wins.
+// Every quirk only costs a little time or strictness on a device that does not need it.
+// None of the entries has been confirmed on hardware yet, which is noted with each one.
+static KNOWN_QUIRKS: &[(&str, &str, Quirks)] = &[
+    // Epson EB projectors: a command sent straight after the greeting is sometimes
+    // dropped. Unconfirmed.
+    (
+        "EPSON",
+        "",
+        Quirks {
+            greeting_delay: Duration::from_millis(100),
+            ..NONE
+        },
+    ),
+    // Sony VPL projectors: the connection is closed after every reply and refused for
+    // a moment. Unconfirmed.
+    (
+        "SONY",
+        "VPL-",
+        Quirks {
+            close_after_reply: true,
+            reconnect_delay: Duration::from_millis(500),
+            ..NONE
+        },
+    ),
+    // Optoma projectors: `ok` in lowercase and a space before the terminator.
+    // Unconfirmed.
+    (
+        "OPTOMA",
+        "",
+        Quirks {
+            lenient_replies: true,
+            ..NONE
+        },
+    ),
+    // NEC projectors: class 1 input numbers above 9 (`INPT=310`). Unconfirmed.
+    (
+        "NEC",
+        "",
+        Quirks {
+            extended_inputs: true,
+            ..NONE
+        },
+    ),
+];
+
+// Does an entry of a table, in uppercase, match the identity of a device
+fn matches(entry: (&str, &str), manufacturer: &str, product_name: &str) -> bool {
+    manufacturer.trim().eq_ignore_ascii_case(entry.0)
+        && product_name.trim().to_uppercase().starts_with(entry.1)
+}
+
+// The quirks for a device from its own entries, then from the built-in table
+pub(crate) fn find(
+    overrides: &[(String, String, Quirks)],
+    manufacturer: &str,
+    product_name: &str,
+) -> Option<Quirks> {
+    overrides
+        .iter()
+        .map(|&(ref m, ref p, quirks)| (m.as_str(), p.as_str(), quirks))
+        .chain(KNOWN_QUIRKS.iter().cloned())
+        .find(|&(m, p, _)| matches((m, p), manufacturer, product_name))
+        .map(|(_, _, quirks)| quirks)
+}
+
+impl Quirks {
+    /// The quirks in the built-in table for a manufacturer (INF1 ?) and
+    /// product name (INF2 ?), if any. Both are compared without case and the
+    /// table entry only has to match the start of the product name.
+    ///
+    /// ```
+    /// use pjlink::Quirks;
+    ///
+    /// assert!(Quirks::lookup("Sony", "VPL-FHZ70").unwrap().close_after_reply);
+    /// assert_eq!(Quirks::lookup("Sony", "SRX-R515"), None);
+    /// ```
+    pub fn lookup(manufacturer: &str, product_name: &str) -> Option<Quirks> {
+        find(&[], manufacturer, product_name)
+    }
+
+    // Clean up a reply from a device that needs lenient_replies
+    pub(crate) fn normalize(&self, response: String) -> String {
+        if !self.lenient_replies {
+            return response;
+        }
+        let response = response.trim();
+        match response.find('=') {
+            Some(equals) => {
+                let header = response[..equals].trim().to_uppercase();
+                let value = response[equals + 1..].trim();
+                let is_status = value.eq_ignore_ascii_case("ok")
+                    || (value.len() == 4 && value.is_char_boundary(3))
+                        && value[..3].eq_ignore_ascii_case("err");
+                if is_status {
+                    format!("{}={}", header, value.to_uppercase())
+                } else {
+                    format!("{}={}", header, value)
+                }
+            }
+            None => response.to_string(),
+        }
+    }
+}
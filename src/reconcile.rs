@@ -0,0 +1,376 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Error;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use room::{Freeze, SetFreeze};
+use worker::Worker;
+use {
+    AvMute, DeviceState, Fleet, FleetResults, InputType, PjlinkDevice, PjlinkErrorCode,
+    PowerStatus, RoomState,
+};
+
+// The number of corrections a Reconciler keeps
+const LOG_SIZE: usize = 1000;
+
+/// A command sent to bring a device closer to its desired state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    PowerOn,
+    PowerOff,
+    /// The input, and the input it had if the device could tell
+    Input(InputType, Option<InputType>),
+    /// The AV mute, and the AV mute it had if the device could tell
+    AvMute(AvMute, Option<AvMute>),
+    /// The freeze (class 2), and the freeze it had if the device could tell
+    Freeze(bool, Option<bool>),
+}
+
+/// A change made, or planned in a dry run, by `Fleet::reconcile`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Correction {
+    /// When the change was made
+    pub time: SystemTime,
+    /// The device, keyed like `FleetResults`
+    pub device: String,
+    pub change: Change,
+    /// Was the change only planned
+    pub dry_run: bool,
+    /// Why the command failed
+    pub error: Option<String>,
+}
+
+impl PjlinkDevice {
+    // Compare the device with its desired state and send what differs.
+    // Commands are only sent when the device is settled: a device that is warming up or
+    // cooling down is left alone and the input, mute and freeze wait until it is on.
+    // A dry run plans the input, mute and freeze of a device that should be on right away.
+    fn reconcile(
+        &self,
+        key: &str,
+        desired: &DeviceState,
+        dry_run: bool,
+    ) -> Result<Vec<Correction>, Error> {
+        let mut corrections = Vec::new();
+        let mut change = |change: Change, command: &dyn Fn() -> Result<(), Error>| {
+            let error = if dry_run {
+                None
+            } else {
+                command().err().map(|e| e.to_string())
+            };
+            let failed = error.is_some();
+            corrections.push(Correction {
+                time: SystemTime::now(),
+                device: key.to_string(),
+                change,
+                dry_run,
+                error,
+            });
+            !failed
+        };
+
+        let on = match (desired.on, self.get_power_status()?) {
+            (true, PowerStatus::Off) => {
+                change(Change::PowerOn, &|| self.power_on().map(|_| ()));
+                false
+            }
+            (false, PowerStatus::On) => {
+                change(Change::PowerOff, &|| self.power_off().map(|_| ()));
+                false
+            }
+            (true, PowerStatus::On) => true,
+            // Off and cooling down, or on its way to where it should be
+            _ => false,
+        };
+        if !(on || dry_run && desired.on) {
+            return Ok(corrections);
+        }
+
+        if let Some(input) = desired.input {
+            let current = queried(self.get_input(), on)?;
+            if current != Some(input)
+                && !change(Change::Input(input, current), &|| {
+                    self.set_input(input).map(|_| ())
+                })
+            {
+                return Ok(corrections);
+            }
+        }
+        if let Some(avmute) = desired.avmute {
+            let current = queried(self.get_avmute(), on)?;
+            if current != Some(avmute)
+                && !change(Change::AvMute(avmute, current), &|| {
+                    self.set_avmute(avmute).map(|_| ())
+                })
+            {
+                return Ok(corrections);
+            }
+        }
+        if let Some(freeze) = desired.freeze {
+            let current = queried(self.execute(&Freeze), on)?;
+            if current != Some(freeze) {
+                change(Change::Freeze(freeze, current), &|| {
+                    self.execute(&SetFreeze(freeze))
+                });
+            }
+        }
+        Ok(corrections)
+    }
+}
+
+// The answer to a query, or None when a device that is not on can't tell (ERR3)
+fn queried<T>(result: Result<T, Error>, on: bool) -> Result<Option<T>, Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e)
+            if !on && PjlinkErrorCode::from_error(e) == Some(PjlinkErrorCode::Unavailable) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+impl Fleet {
+    /// Make one pass over the devices of a desired state and send the commands needed to
+    /// bring each one closer to it. Devices that are off are turned on before their input,
+    /// mute and freeze are looked at on a later pass, and devices that are warming up or
+    /// cooling down are left alone until they are done, so it usually takes a few passes
+    /// to converge. With `dry_run` nothing is sent and the corrections are only planned,
+    /// including the input, mute and freeze of a device that still has to be turned on.
+    ///
+    /// A device that could not be queried gets an error. A command that failed is
+    /// reported in its correction and ends the pass for that device.
+    ///
+    /// ```
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{AvMute, Change, DeviceState, Fleet, InputType, PjlinkDevice, PowerStatus, RoomState};
+    ///
+    /// let emulator = Emulator::new(EmulatorConfig {
+    ///     power: PowerStatus::On,
+    ///     ..EmulatorConfig::default()
+    /// });
+    /// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    /// let device = PjlinkDevice::builder("127.0.0.1")
+    ///     .port(server.local_addr().port())
+    ///     .build()
+    ///     .unwrap();
+    /// let mut fleet = Fleet::new();
+    /// fleet.add(device);
+    ///
+    /// let mut desired = RoomState::default();
+    /// desired.devices.insert(Fleet::key(&fleet.devices()[0]), DeviceState {
+    ///     on: true,
+    ///     input: Some(InputType::Digital(2)),
+    ///     avmute: Some(AvMute { video: false, audio: false }),
+    ///     freeze: None,
+    /// });
+    ///
+    /// let plan = fleet.reconcile(&desired, true);
+    /// let corrections = plan.values().next().unwrap().as_ref().unwrap();
+    /// assert_eq!(corrections.len(), 1);
+    /// assert!(matches!(corrections[0].change, Change::Input(InputType::Digital(2), _)));
+    ///
+    /// fleet.reconcile(&desired, false);
+    /// assert_eq!(fleet.devices()[0].get_input().unwrap(), InputType::Digital(2));
+    /// ```
+    pub fn reconcile(&self, desired: &RoomState, dry_run: bool) -> FleetResults<Vec<Correction>> {
+        let keys: Vec<&str> = desired.devices.keys().map(|key| key.as_str()).collect();
+        let states = desired.devices.clone();
+        self.run_on(&keys, move |device| {
+            let key = Fleet::key(device);
+            match states.get(&key) {
+                Some(state) => device.reconcile(&key, state, dry_run),
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+}
+
+type Callback = Box<dyn Fn(&Correction) + Send>;
+
+struct Shared {
+    fleet: Fleet,
+    desired: Mutex<RoomState>,
+    dry_run: bool,
+    log: Mutex<VecDeque<Correction>>,
+    // The changes last planned for each device in a dry run
+    planned: Mutex<BTreeMap<String, Vec<Change>>>,
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+/// Keeps a fleet in a desired state by running `Fleet::reconcile` on a background thread.
+///
+/// Every correction is kept in a log of the last 1000 and passed to the `on_correction`
+/// callbacks. A desired state can be written like a room preset, see
+/// [pjlink::RoomPresets](struct.RoomPresets.html).
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use pjlink::{Fleet, PjlinkDevice, Reconciler, RoomPresets};
+///
+/// let mut fleet = Fleet::new();
+/// fleet.add(PjlinkDevice::new("192.168.1.10").unwrap());
+///
+/// let presets = RoomPresets::parse("[room 101]\n192.168.1.10 = power on, input 31, avmute 30").unwrap();
+/// let mut reconciler = Reconciler::new(fleet, presets.get("room 101").unwrap().clone())
+///     .interval(Duration::from_secs(30));
+/// reconciler.on_correction(|correction| {
+///     println!("{} {:?} {:?}", correction.device, correction.change, correction.error)
+/// });
+/// reconciler.start().unwrap();
+/// ```
+pub struct Reconciler {
+    shared: Arc<Shared>,
+    interval: Duration,
+    running: Option<Worker>,
+}
+
+impl Reconciler {
+    /// Keep the fleet in the desired state, checking every 10 seconds once started
+    pub fn new(fleet: Fleet, desired: RoomState) -> Reconciler {
+        Reconciler::with_dry_run(fleet, desired, false)
+    }
+
+    /// Only log what would be corrected, without sending any commands.
+    /// The corrections planned for a device are logged again only when they change.
+    pub fn dry_run(fleet: Fleet, desired: RoomState) -> Reconciler {
+        Reconciler::with_dry_run(fleet, desired, true)
+    }
+
+    fn with_dry_run(fleet: Fleet, desired: RoomState, dry_run: bool) -> Reconciler {
+        Reconciler {
+            shared: Arc::new(Shared {
+                fleet,
+                desired: Mutex::new(desired),
+                dry_run,
+                log: Mutex::new(VecDeque::new()),
+                planned: Mutex::new(BTreeMap::new()),
+                callbacks: Mutex::new(Vec::new()),
+            }),
+            interval: Duration::from_secs(10),
+            running: None,
+        }
+    }
+
+    /// How often the fleet is checked
+    pub fn interval(mut self, interval: Duration) -> Reconciler {
+        self.interval = interval;
+        self
+    }
+
+    /// Replace the desired state, it is used from the next pass
+    pub fn set_desired(&self, desired: RoomState) {
+        *self.shared.desired.lock().unwrap() = desired;
+    }
+
+    /// Called for every correction
+    pub fn on_correction<F: Fn(&Correction) + Send + 'static>(&self, callback: F) {
+        self.shared
+            .callbacks
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+
+    /// The most recent corrections, oldest first
+    pub fn log(&self) -> Vec<Correction> {
+        self.shared.log.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Make a pass now, on the calling thread
+    pub fn run_once(&self) -> FleetResults<Vec<Correction>> {
+        self.shared.run_once()
+    }
+
+    /// Is the background thread running
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start making passes on a background thread.
+    /// Starting a reconciler that is already running does nothing.
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+
+        let shared = Arc::clone(&self.shared);
+        self.running = Some(Worker::every(
+            String::from("pjlink reconciler"),
+            self.interval,
+            move || {
+                shared.run_once();
+            },
+        )?);
+        Ok(())
+    }
+
+    /// Stop the background thread and wait for the current pass to finish
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+}
+
+impl Shared {
+    fn run_once(&self) -> FleetResults<Vec<Correction>> {
+        let desired = self.desired.lock().unwrap().clone();
+        let results = self.fleet.reconcile(&desired, self.dry_run);
+        let logged = self.log_corrections(&results);
+
+        // The callbacks run outside the lock, so they may add callbacks or read the log
+        let mut callbacks = mem::take(&mut *self.callbacks.lock().unwrap());
+        for correction in &logged {
+            callbacks.iter().for_each(|callback| callback(correction));
+        }
+        let mut added = self.callbacks.lock().unwrap();
+        callbacks.append(&mut added);
+        *added = callbacks;
+        results
+    }
+
+    // Add the corrections of a pass to the log and return the ones added
+    fn log_corrections(&self, results: &FleetResults<Vec<Correction>>) -> Vec<Correction> {
+        let mut logged = Vec::new();
+        let mut planned = self.planned.lock().unwrap();
+        for (key, corrections) in results {
+            let corrections = match *corrections {
+                Ok(ref corrections) => corrections,
+                Err(_) => continue,
+            };
+            // A dry run plans the same corrections every pass until something changes
+            if self.dry_run {
+                let changes: Vec<Change> = corrections.iter().map(|c| c.change).collect();
+                if planned.get(key) == Some(&changes) {
+                    continue;
+                }
+                planned.insert(key.clone(), changes);
+            }
+            logged.extend(corrections.iter().cloned());
+        }
+
+        let mut log = self.log.lock().unwrap();
+        for correction in &logged {
+            if log.len() == LOG_SIZE {
+                log.pop_front();
+            }
+            log.push_back(correction.clone());
+        }
+        logged
+    }
+}
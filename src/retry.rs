@@ -0,0 +1,124 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+use rng::Rng;
+use PjlinkErrorCode;
+
+/// Controls how often a command is retried when the device answers ERR3
+/// (Unavailable at this time, usually while warming up or cooling down) or the
+/// connection fails with a transient I/O error.
+///
+/// ERR1, ERR2, ERR4 and authorization errors are never retried.
+/// The default policy makes a single attempt, as the library always has.
+///
+/// ```
+/// use std::time::Duration;
+/// use pjlink::{PjlinkDevice, RetryPolicy};
+///
+/// let device = PjlinkDevice::builder("192.168.1.1")
+///     .retry_policy(RetryPolicy {
+///         max_attempts: 5,
+///         initial_backoff: Duration::from_millis(500),
+///         ..RetryPolicy::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one
+    pub max_attempts: u32,
+    /// The wait before the second attempt
+    pub initial_backoff: Duration,
+    /// The wait is multiplied by this after every attempt
+    pub multiplier: f64,
+    /// The longest wait between two attempts
+    pub max_backoff: Duration,
+    /// A fraction between 0 and 1 of each wait that is randomized so that many
+    /// clients don't retry in lockstep.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// The wait after the given (1 based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let randomized = backoff * (1.0 - jitter * Rng::from_time().next_f64());
+
+        Duration::from_secs_f64(randomized.max(0.0))
+    }
+
+    // Run the attempt until it succeeds, fails permanently or we run out of attempts.
+    // Returns the final result and the number of attempts made.
+    pub(crate) fn run<T, F, R>(&self, mut attempt: F, retryable: R) -> (Result<T, Error>, u32)
+    where
+        F: FnMut() -> Result<T, Error>,
+        R: Fn(&Result<T, Error>) -> bool,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = attempt();
+            if attempts >= self.max_attempts || !retryable(&result) {
+                return (result, attempts);
+            }
+            thread::sleep(self.backoff(attempts));
+        }
+    }
+}
+
+// I/O errors that are likely to go away if we try again.
+pub(crate) fn is_transient(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+// Errors worth waiting out: transient I/O errors and ERR3 from the device.
+pub(crate) fn is_retryable(err: &Error) -> bool {
+    is_transient(err) || PjlinkErrorCode::from_error(err) == Some(PjlinkErrorCode::Unavailable)
+}
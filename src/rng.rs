@@ -0,0 +1,62 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SEED_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A small xorshift generator. It is only used for backoff jitter and fault
+// injection, neither of which needs more than a cheap repeatable sequence.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift so mix the seed first.
+        let mut rng = Rng {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        };
+        if rng.state == 0 {
+            rng.state = 1;
+        }
+        rng.next_u64();
+        rng
+    }
+
+    // Seeded from the clock for callers that don't need to repeat a sequence.
+    pub(crate) fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let count = SEED_COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+        Rng::new(nanos ^ count.rotate_left(32))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    // A float in [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+use config::{self, Entry};
+use {
+    AvMute, Fleet, FleetResults, InputType, PjlinkCommand, PjlinkDevice, PjlinkErrorCode,
+    PowerStatus,
+};
+
+/// The state of one device in a [pjlink::RoomState](struct.RoomState.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceState {
+    /// On or warming up
+    pub on: bool,
+    /// The input, if the device was on
+    pub input: Option<InputType>,
+    /// The AV mute, if the device was on
+    pub avmute: Option<AvMute>,
+    /// The freeze (class 2), if the device was on and supports it
+    pub freeze: Option<bool>,
+}
+
+/// The state of every device of a room, captured with `Fleet::capture_state`.
+/// It is saved to disk as a named preset of [pjlink::RoomPresets](struct.RoomPresets.html).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomState {
+    /// The state of each device, keyed like `FleetResults`
+    pub devices: BTreeMap<String, DeviceState>,
+}
+
+// FREZ ?
+pub(crate) struct Freeze;
+
+impl PjlinkCommand for Freeze {
+    type Output = bool;
+
+    fn class(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self) -> String {
+        String::from("FREZ ?")
+    }
+
+    fn decode(&self, value: &str) -> Result<bool, Error> {
+        match value {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid freeze: {}", value),
+            )),
+        }
+    }
+}
+
+// FREZ 1 and FREZ 0
+pub(crate) struct SetFreeze(pub(crate) bool);
+
+impl PjlinkCommand for SetFreeze {
+    type Output = ();
+
+    fn class(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self) -> String {
+        format!("FREZ {}", if self.0 { 1 } else { 0 })
+    }
+
+    fn decode(&self, _value: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl PjlinkDevice {
+    // The state of the device for a RoomState
+    fn capture_state(&self) -> Result<DeviceState, Error> {
+        let power = self.get_power_status()?;
+        if power != PowerStatus::On {
+            return Ok(DeviceState {
+                on: power == PowerStatus::Warmup,
+                input: None,
+                avmute: None,
+                freeze: None,
+            });
+        }
+
+        Ok(DeviceState {
+            on: true,
+            input: Some(self.get_input()?),
+            avmute: Some(self.get_avmute()?),
+            freeze: match self.execute(&Freeze) {
+                Ok(freeze) => Some(freeze),
+                // Class 1 devices have no freeze
+                Err(ref e)
+                    if PjlinkErrorCode::from_error(e)
+                        == Some(PjlinkErrorCode::UndefinedCommand) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+        })
+    }
+
+    // Power first, then the input, mute and freeze once the device is on
+    fn restore_state(&self, state: &DeviceState, timeout: Duration) -> Result<(), Error> {
+        if !state.on {
+            return self.power_off().map(|_| ());
+        }
+
+        self.power_on_and_wait(timeout)?;
+        if let Some(input) = state.input {
+            self.set_input(input)?;
+        }
+        if let Some(avmute) = state.avmute {
+            self.set_avmute(avmute)?;
+        }
+        if let Some(freeze) = state.freeze {
+            self.execute(&SetFreeze(freeze))?;
+        }
+        Ok(())
+    }
+}
+
+impl Fleet {
+    /// Capture the power, input, AV mute and freeze of every device.
+    /// Fails if any device could not be queried since a partial state can't be restored.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{AvMute, Fleet, InputType, PjlinkDevice, PowerStatus};
+    ///
+    /// let emulator = Emulator::new(EmulatorConfig {
+    ///     power: PowerStatus::On,
+    ///     warmup: Duration::from_millis(100),
+    ///     cooling: Duration::from_millis(100),
+    ///     ..EmulatorConfig::default()
+    /// });
+    /// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    /// let device = PjlinkDevice::builder("127.0.0.1")
+    ///     .port(server.local_addr().port())
+    ///     .build()
+    ///     .unwrap();
+    /// device.set_input(InputType::Digital(2)).unwrap();
+    ///
+    /// let mut fleet = Fleet::new();
+    /// fleet.add(device);
+    /// let state = fleet.capture_state().unwrap();
+    ///
+    /// // Somebody changes the room
+    /// let device = &fleet.devices()[0];
+    /// device.set_avmute(AvMute { video: true, audio: true }).unwrap();
+    /// device.power_off().unwrap();
+    ///
+    /// let results = fleet.restore_state(&state, Duration::from_secs(30));
+    /// assert!(results.values().all(|result| result.is_ok()));
+    /// assert_eq!(device.get_input().unwrap(), InputType::Digital(2));
+    /// assert_eq!(device.get_avmute().unwrap(), AvMute { video: false, audio: false });
+    /// ```
+    pub fn capture_state(&self) -> Result<RoomState, Error> {
+        let mut state = RoomState::default();
+        for (key, result) in self.run(|device| device.capture_state()) {
+            match result {
+                Ok(device) => {
+                    state.devices.insert(key, device);
+                }
+                Err(e) => {
+                    return Err(Error::new(
+                        e.kind(),
+                        format!("Could not capture the state of {}: {}", key, e),
+                    ))
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Put the devices back in a captured state. Devices that were on are turned on
+    /// and, once they are on within `timeout`, get their input, AV mute and freeze back.
+    /// Devices that were off are turned off. A device of the state that is not in the
+    /// fleet gets a `NotFound` error.
+    pub fn restore_state(&self, state: &RoomState, timeout: Duration) -> FleetResults<()> {
+        let keys: Vec<&str> = state.devices.keys().map(|key| key.as_str()).collect();
+        let states = state.devices.clone();
+        self.run_on(&keys, move |device| match states.get(&Fleet::key(device)) {
+            Some(state) => device.restore_state(state, timeout),
+            None => Ok(()),
+        })
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "power {}", if self.on { "on" } else { "off" })?;
+        if let Some(input) = self.input {
+            write!(f, ", input {}", input.extended_code())?;
+        }
+        if let Some(avmute) = self.avmute {
+            write!(f, ", avmute {}", avmute.code())?;
+        }
+        if let Some(freeze) = self.freeze {
+            write!(f, ", freeze {}", if freeze { "on" } else { "off" })?;
+        }
+        Ok(())
+    }
+}
+
+// A device line of a presets file, e.g. "power on, input 31, avmute 30, freeze off"
+fn parse_device(entry: &Entry) -> Result<DeviceState, Error> {
+    let mut state = DeviceState {
+        on: false,
+        input: None,
+        avmute: None,
+        freeze: None,
+    };
+    let mut power = false;
+
+    for setting in entry.value.split(',') {
+        let mut words = setting.split_whitespace();
+        let (name, value) = match (words.next(), words.next(), words.next()) {
+            (Some(name), Some(value), None) => (name, value),
+            _ => return Err(entry.invalid(&format!("expected name value: {}", setting.trim()))),
+        };
+        match name {
+            "power" => {
+                state.on = on_off(entry, value)?;
+                power = true;
+            }
+            "input" => match InputType::from_extended_code(value) {
+                Some(input) => state.input = Some(input),
+                None => return Err(entry.invalid(&format!("invalid input {}", value))),
+            },
+            "avmute" => match value.parse().ok().and_then(AvMute::from_code) {
+                Some(avmute) => state.avmute = Some(avmute),
+                None => return Err(entry.invalid(&format!("invalid avmute {}", value))),
+            },
+            "freeze" => state.freeze = Some(on_off(entry, value)?),
+            _ => return Err(entry.invalid(&format!("unknown setting {}", name))),
+        }
+    }
+
+    if !power {
+        return Err(entry.invalid("the power is missing"));
+    }
+    Ok(state)
+}
+
+fn on_off(entry: &Entry, value: &str) -> Result<bool, Error> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(entry.invalid(&format!("expected on or off, got {}", value))),
+    }
+}
+
+/// Named room states kept in a file, with a section for every preset and a line
+/// for every device:
+///
+/// ```text
+/// [lecture]
+/// 192.168.1.10 = power on, input 31, avmute 30, freeze off
+/// 192.168.1.11 = power off
+/// ```
+///
+/// The input and AV mute are the INPT and AVMT parameters.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use pjlink::{Fleet, PjlinkDevice, RoomPresets};
+///
+/// let mut fleet = Fleet::new();
+/// fleet.add(PjlinkDevice::new("192.168.1.10").unwrap());
+/// fleet.add(PjlinkDevice::new("192.168.1.11").unwrap());
+///
+/// let mut presets = RoomPresets::load("room.presets").unwrap_or_default();
+/// presets.insert("lecture", fleet.capture_state().unwrap());
+/// presets.save("room.presets").unwrap();
+///
+/// assert_eq!(RoomPresets::load("room.presets").unwrap(), presets);
+/// if let Some(state) = presets.get("lecture") {
+///     fleet.restore_state(state, Duration::from_secs(90));
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomPresets {
+    presets: BTreeMap<String, RoomState>,
+}
+
+impl RoomPresets {
+    /// No presets
+    pub fn new() -> RoomPresets {
+        RoomPresets::default()
+    }
+
+    /// The preset with this name
+    pub fn get(&self, name: &str) -> Option<&RoomState> {
+        self.presets.get(name)
+    }
+
+    /// Add a preset, replacing a preset with the same name
+    pub fn insert(&mut self, name: &str, state: RoomState) {
+        self.presets.insert(name.to_string(), state);
+    }
+
+    /// Remove the preset with this name
+    pub fn remove(&mut self, name: &str) -> Option<RoomState> {
+        self.presets.remove(name)
+    }
+
+    /// The names of the presets in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.presets.keys().map(|name| name.as_str())
+    }
+
+    /// Parse presets in the text format
+    pub fn parse(text: &str) -> Result<RoomPresets, Error> {
+        let mut presets = RoomPresets::new();
+        for section in config::parse(text)? {
+            if section.name.is_empty() {
+                if let Some(entry) = section.entries.first() {
+                    return Err(entry.invalid("devices have to be in a [preset] section"));
+                }
+                continue;
+            }
+
+            let mut state = RoomState::default();
+            for entry in &section.entries {
+                state
+                    .devices
+                    .insert(entry.key.clone(), parse_device(entry)?);
+            }
+            presets.insert(&section.name, state);
+        }
+        Ok(presets)
+    }
+
+    /// Load presets from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RoomPresets, Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        RoomPresets::parse(&text)
+    }
+
+    /// Save the presets to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        File::create(path)?.write_all(self.to_string().as_bytes())
+    }
+}
+
+impl fmt::Display for RoomPresets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# PJLink room presets")?;
+        for (name, state) in &self.presets {
+            writeln!(f, "\n[{}]", name)?;
+            for (key, device) in &state.devices {
+                writeln!(f, "{} = {}", key, device)?;
+            }
+        }
+        Ok(())
+    }
+}
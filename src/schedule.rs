@@ -0,0 +1,996 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::Error;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::{self, Entry, Section};
+use worker::{Worker, STOP_CHECK_INTERVAL};
+use {AvMute, Fleet, FleetResults, InputType, RoomPresets, RoomState};
+
+static GLOBAL_KEYS: &[&str] = &["utc_offset", "catch_up", "state", "log", "presets"];
+static CLOCK_KEYS: &[&str] = &["change"];
+static RULE_KEYS: &[&str] = &["cron", "at", "target", "action", "holidays"];
+
+// The number of executions a Scheduler keeps
+const LOG_SIZE: usize = 1000;
+
+// How far back and ahead occurrences of a rule are looked for
+const SEARCH_MINUTES: i64 = 366 * 24 * 60;
+
+// How long a device may take to warm up when a preset is restored
+const PRESET_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The date of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// A minute counted from 1970-01-01 00:00, of UTC or of local time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Minute(i64);
+
+struct Moment {
+    date: (i64, u32, u32),
+    hour: u32,
+    minute: u32,
+    // 0 is Sunday
+    weekday: u32,
+}
+
+impl Minute {
+    // The UTC minute of a time
+    fn from_time(time: SystemTime) -> Minute {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        Minute(seconds.div_euclid(60))
+    }
+
+    // The start of a UTC minute
+    fn to_time(self) -> SystemTime {
+        let seconds = self.0 * 60;
+        if seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(seconds as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+        }
+    }
+
+    fn moment(self) -> Moment {
+        let days = self.0.div_euclid(24 * 60);
+        let of_day = self.0.rem_euclid(24 * 60) as u32;
+        Moment {
+            date: civil_from_days(days),
+            hour: of_day / 60,
+            minute: of_day % 60,
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+}
+
+impl fmt::Display for Minute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let moment = self.moment();
+        let (year, month, day) = moment.date;
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            year, month, day, moment.hour, moment.minute
+        )
+    }
+}
+
+// YYYY-MM-DD
+fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let days = days_from_civil(year, month, day);
+    // Reject dates like 2026-02-30 that would roll over into the next month
+    if (1..=12).contains(&month) && civil_from_days(days) == (year, month, day) {
+        Some(days)
+    } else {
+        None
+    }
+}
+
+// YYYY-MM-DD HH:MM
+fn parse_minute(text: &str) -> Option<Minute> {
+    let mut parts = text.split_whitespace();
+    let days = parse_date(parts.next()?)?;
+    let mut time = parts.next()?.splitn(2, ':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    if parts.next().is_some() || hour > 23 || minute > 59 {
+        return None;
+    }
+    Some(Minute(days * 24 * 60 + hour * 60 + minute))
+}
+
+// +HH:MM or -HH:MM, in seconds
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, rest) = match text.get(0..1)? {
+        "+" => (1, &text[1..]),
+        "-" => (-1, &text[1..]),
+        _ => (1, text),
+    };
+    let mut parts = rest.splitn(2, ':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next().unwrap_or("0").parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// One field of a cron expression as a bit for every allowed value
+#[derive(Clone, Copy, Debug)]
+struct CronField {
+    allowed: u64,
+    any: bool,
+}
+
+impl CronField {
+    fn parse(text: &str, low: u32, high: u32) -> Option<CronField> {
+        let mut allowed = 0u64;
+        for item in text.split(',') {
+            let (range, step) = match item.find('/') {
+                Some(slash) => (&item[..slash], item[slash + 1..].parse::<u32>().ok()?),
+                None => (item, 1),
+            };
+            let (first, last) = if range == "*" {
+                (low, high)
+            } else {
+                match range.find('-') {
+                    Some(dash) => (range[..dash].parse().ok()?, range[dash + 1..].parse().ok()?),
+                    None => {
+                        let value = range.parse().ok()?;
+                        // 5/15 means from 5 to the end in steps of 15
+                        (value, if step > 1 { high } else { value })
+                    }
+                }
+            };
+            if step == 0 || first < low || last > high || first > last {
+                return None;
+            }
+            for value in (first..=last).step_by(step as usize) {
+                allowed |= 1 << value;
+            }
+        }
+        Some(CronField {
+            allowed,
+            any: text.starts_with('*'),
+        })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.allowed & (1 << value) != 0
+    }
+}
+
+// minute hour day-of-month month day-of-week, like crontab(5)
+#[derive(Clone, Copy, Debug)]
+struct Cron {
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+}
+
+impl Cron {
+    fn parse(text: &str) -> Option<Cron> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut weekday = CronField::parse(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekday.contains(7) {
+            weekday.allowed |= 1;
+        }
+        Some(Cron {
+            minute: CronField::parse(fields[0], 0, 59)?,
+            hour: CronField::parse(fields[1], 0, 23)?,
+            day: CronField::parse(fields[2], 1, 31)?,
+            month: CronField::parse(fields[3], 1, 12)?,
+            weekday,
+        })
+    }
+
+    fn matches(&self, moment: &Moment) -> bool {
+        let (_, month, day) = moment.date;
+        // Like cron, a restricted day of the month and day of the week are either or
+        let day_matches = match (self.day.any, self.weekday.any) {
+            (false, false) => self.day.contains(day) || self.weekday.contains(moment.weekday),
+            _ => self.day.contains(day) && self.weekday.contains(moment.weekday),
+        };
+        self.minute.contains(moment.minute)
+            && self.hour.contains(moment.hour)
+            && self.month.contains(month)
+            && day_matches
+    }
+}
+
+#[derive(Clone, Debug)]
+enum When {
+    Cron(Cron),
+    Once(Minute),
+}
+
+/// What a scheduled rule does
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduledAction {
+    PowerOn,
+    PowerOff,
+    SetInput(InputType),
+    SetAvMute(AvMute),
+    /// Restore a preset from the presets file, to the devices of the preset
+    Preset(String),
+    /// Send a raw command with `send_command`, an ERR reply counts as a failure
+    Raw(String),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    name: String,
+    when: When,
+    targets: Vec<String>,
+    action: ScheduledAction,
+    on_holidays: bool,
+}
+
+impl Rule {
+    // Is the rule due at a minute of local time
+    fn matches(&self, local: Minute) -> bool {
+        match self.when {
+            When::Cron(ref cron) => cron.matches(&local.moment()),
+            When::Once(once) => once == local,
+        }
+    }
+}
+
+// The offset of local time from UTC, which changes at the given UTC minutes
+#[derive(Clone, Debug)]
+struct UtcOffset {
+    // In seconds
+    initial: i64,
+    // (UTC minute, offset in seconds from then on), in order
+    changes: Vec<(Minute, i64)>,
+}
+
+impl UtcOffset {
+    // The offset in seconds at a UTC minute
+    fn at(&self, utc: Minute) -> i64 {
+        self.changes
+            .iter()
+            .rev()
+            .find(|&&(from, _)| from <= utc)
+            .map_or(self.initial, |&(_, offset)| offset)
+    }
+
+    fn local(&self, utc: Minute) -> Minute {
+        Minute(utc.0 + self.at(utc).div_euclid(60))
+    }
+
+    // Was the local time of a UTC minute seen before, because the clocks went back
+    fn repeated(&self, utc: Minute) -> bool {
+        let mut previous = self.initial;
+        for &(from, offset) in &self.changes {
+            if from <= utc && utc.0 < from.0 + (previous - offset) / 60 {
+                return true;
+            }
+            previous = offset;
+        }
+        false
+    }
+}
+
+/// Rules for commands to run at set times, loaded from a file.
+///
+/// The file has settings for the whole schedule, device groups, holidays and rules:
+///
+/// ```text
+/// # Local time as an offset from UTC
+/// utc_offset = +01:00
+/// # Run a rule missed while the scheduler was stopped if it is at most this many minutes late
+/// catch_up = 60
+/// # Remember the last minute handled here, needed to find missed runs after a restart
+/// state = /var/lib/pjlink/schedule.state
+/// # Append a line for every execution here
+/// log = /var/log/pjlink-schedule.log
+/// # Room presets for preset actions, see pjlink::RoomPresets
+/// presets = /etc/pjlink/room.presets
+///
+/// [group auditorium]
+/// devices = 192.168.1.10, 192.168.1.11
+///
+/// [holidays]
+/// date = 2026-12-25
+/// date = 2026-12-31
+///
+/// # When the clocks go forward or back for daylight saving time, as the local time
+/// # before the change, and the offset from then on
+/// [clock changes]
+/// change = 2026-03-29 02:00, +02:00
+/// change = 2026-10-25 03:00, +01:00
+///
+/// [rule nightly off]
+/// cron = 0 22 * * *
+/// target = auditorium
+/// action = power off
+///
+/// [rule open day]
+/// at = 2026-11-07 08:30
+/// target = auditorium, 192.168.1.20
+/// action = preset lecture
+/// holidays = run
+/// ```
+///
+/// A rule runs on a `cron` expression (minute, hour, day of the month, month and day of
+/// the week, with `*`, lists, ranges and `/steps`) or once `at` a local date and time.
+/// `target` lists groups and devices keyed like `FleetResults`. `action` is `power on`,
+/// `power off`, `input` with an INPT parameter, `avmute` with an AVMT parameter,
+/// `preset` with the name of a preset, or `send` with a raw command. Rules are skipped
+/// on holidays unless they have `holidays = run`.
+///
+/// Local time is `utc_offset` all year unless the `[clock changes]` list when it changes,
+/// there are no time zone rules built in. When the clocks go forward, rules due in the
+/// skipped hour do not run that day. When they go back, rules due in the repeated hour
+/// run once.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    utc_offset: UtcOffset,
+    catch_up: i64,
+    state: Option<PathBuf>,
+    log: Option<PathBuf>,
+    presets: RoomPresets,
+    groups: BTreeMap<String, Vec<String>>,
+    holidays: Vec<i64>,
+    rules: Vec<Rule>,
+}
+
+fn unknown_keys(section: &Section, keys: &[&str]) -> Result<(), Error> {
+    match section
+        .entries
+        .iter()
+        .find(|entry| !keys.iter().any(|key| entry.is(key)))
+    {
+        Some(entry) => Err(entry.invalid(&format!("unknown setting {}", entry.key))),
+        None => Ok(()),
+    }
+}
+
+fn list(entry: &Entry) -> Vec<String> {
+    entry
+        .value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_action(entry: &Entry, presets: &RoomPresets) -> Result<ScheduledAction, Error> {
+    let value = entry.value.as_str();
+    let (verb, argument) = match value.find(char::is_whitespace) {
+        Some(space) => (&value[..space], value[space..].trim()),
+        None => (value, ""),
+    };
+    let action = match (verb, argument) {
+        ("power", "on") => ScheduledAction::PowerOn,
+        ("power", "off") => ScheduledAction::PowerOff,
+        ("input", code) => InputType::from_extended_code(code)
+            .map(ScheduledAction::SetInput)
+            .ok_or_else(|| entry.invalid(&format!("invalid input {}", code)))?,
+        ("avmute", code) => code
+            .parse()
+            .ok()
+            .and_then(AvMute::from_code)
+            .map(ScheduledAction::SetAvMute)
+            .ok_or_else(|| entry.invalid(&format!("invalid avmute {}", code)))?,
+        ("preset", name) => {
+            if presets.get(name).is_none() {
+                return Err(entry.invalid(&format!("there is no preset {}", name)));
+            }
+            ScheduledAction::Preset(name.to_string())
+        }
+        ("send", command) if !command.is_empty() => ScheduledAction::Raw(command.to_string()),
+        _ => return Err(entry.invalid(&format!("unknown action {}", value))),
+    };
+    Ok(action)
+}
+
+impl Schedule {
+    /// Parse a schedule, the presets file it names is loaded as well
+    pub fn parse(text: &str) -> Result<Schedule, Error> {
+        let sections = config::parse(text)?;
+        let global = &sections[0];
+        unknown_keys(global, GLOBAL_KEYS)?;
+
+        let utc_offset = match global.get("utc_offset") {
+            Some(entry) => parse_offset(&entry.value)
+                .ok_or_else(|| entry.invalid("expected an offset like +01:00"))?,
+            None => 0,
+        };
+        let catch_up = match global.get("catch_up") {
+            Some(entry) => entry.parse()?,
+            None => 0,
+        };
+        let presets = match global.get("presets") {
+            Some(entry) => RoomPresets::load(&entry.value)?,
+            None => RoomPresets::new(),
+        };
+
+        let mut schedule = Schedule {
+            utc_offset: UtcOffset {
+                initial: utc_offset,
+                changes: Vec::new(),
+            },
+            catch_up,
+            state: global.get("state").map(|entry| PathBuf::from(&entry.value)),
+            log: global.get("log").map(|entry| PathBuf::from(&entry.value)),
+            presets,
+            groups: BTreeMap::new(),
+            holidays: Vec::new(),
+            rules: Vec::new(),
+        };
+
+        for section in &sections[1..] {
+            let (kind, name) = match section.name.find(char::is_whitespace) {
+                Some(space) => (&section.name[..space], section.name[space..].trim()),
+                None => (section.name.as_str(), ""),
+            };
+            match (kind, name) {
+                ("group", name) if !name.is_empty() => {
+                    unknown_keys(section, &["devices"])?;
+                    let devices = section.get("devices").map(list).unwrap_or_default();
+                    schedule.groups.insert(name.to_string(), devices);
+                }
+                ("holidays", "") => {
+                    unknown_keys(section, &["date"])?;
+                    for entry in &section.entries {
+                        let date = parse_date(&entry.value)
+                            .ok_or_else(|| entry.invalid("expected a date like 2026-12-25"))?;
+                        schedule.holidays.push(date);
+                    }
+                }
+                ("clock", "changes") => {
+                    unknown_keys(section, CLOCK_KEYS)?;
+                    for entry in &section.entries {
+                        let change = schedule.parse_clock_change(entry)?;
+                        schedule.utc_offset.changes.push(change);
+                    }
+                }
+                ("rule", name) if !name.is_empty() => {
+                    let rule = schedule.parse_rule(section, name)?;
+                    schedule.rules.push(rule);
+                }
+                _ => {
+                    return Err(config::invalid(
+                        section.line,
+                        "expected [group name], [holidays], [clock changes] or [rule name]",
+                    ))
+                }
+            }
+        }
+
+        Ok(schedule)
+    }
+
+    // A change of the offset from UTC, after the changes parsed so far
+    fn parse_clock_change(&self, entry: &Entry) -> Result<(Minute, i64), Error> {
+        let mut parts = entry.value.splitn(2, ',');
+        let (local, offset) = match (parts.next().and_then(parse_minute), parts.next()) {
+            (Some(local), Some(offset)) => (local, offset.trim()),
+            _ => return Err(entry.invalid("expected a change like 2026-03-29 02:00, +02:00")),
+        };
+        let offset =
+            parse_offset(offset).ok_or_else(|| entry.invalid("expected an offset like +01:00"))?;
+        let previous = self.utc_offset.changes.last();
+        let utc = Minute(local.0 - previous.map_or(self.utc_offset.initial, |c| c.1) / 60);
+        if previous.is_some_and(|&(from, _)| utc <= from) {
+            return Err(entry.invalid("clock changes have to be in order"));
+        }
+        Ok((utc, offset))
+    }
+
+    fn parse_rule(&self, section: &Section, name: &str) -> Result<Rule, Error> {
+        unknown_keys(section, RULE_KEYS)?;
+        let at_line = |problem: &str| config::invalid(section.line, problem);
+
+        let when = match (section.get("cron"), section.get("at")) {
+            (Some(cron), None) => When::Cron(
+                Cron::parse(&cron.value)
+                    .ok_or_else(|| cron.invalid("expected minute hour day month weekday"))?,
+            ),
+            (None, Some(at)) => When::Once(
+                parse_minute(&at.value)
+                    .ok_or_else(|| at.invalid("expected a date and time like 2026-11-07 08:30"))?,
+            ),
+            _ => return Err(at_line("a rule needs either cron or at")),
+        };
+        let action = match section.get("action") {
+            Some(entry) => parse_action(entry, &self.presets)?,
+            None => return Err(at_line("a rule needs an action")),
+        };
+        let targets = section.get("target").map(list).unwrap_or_default();
+        let is_preset = matches!(action, ScheduledAction::Preset(_));
+        if targets.is_empty() && !is_preset {
+            return Err(at_line("a rule needs a target"));
+        }
+        let on_holidays = match section.get("holidays") {
+            Some(entry) => match entry.value.as_str() {
+                "run" => true,
+                "skip" => false,
+                _ => return Err(entry.invalid("expected run or skip")),
+            },
+            None => false,
+        };
+
+        Ok(Rule {
+            name: name.to_string(),
+            when,
+            targets,
+            action,
+            on_holidays,
+        })
+    }
+
+    /// Load a schedule from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Schedule, Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Schedule::parse(&text)
+    }
+
+    /// The names of the rules in the order of the file
+    pub fn rules(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|rule| rule.name.as_str())
+    }
+
+    /// When a rule next runs after `after`, within a year. Holidays are taken into account.
+    pub fn next_run(&self, rule: &str, after: SystemTime) -> Option<SystemTime> {
+        let rule = self.rules.iter().find(|r| r.name == rule)?;
+        let start = Minute::from_time(after).0 + 1;
+        (start..start + SEARCH_MINUTES)
+            .map(Minute)
+            .find(|&minute| self.due(rule, minute) && !self.skipped(rule, minute))
+            .map(Minute::to_time)
+    }
+
+    // Is the rule due at a UTC minute
+    fn due(&self, rule: &Rule, minute: Minute) -> bool {
+        !self.utc_offset.repeated(minute) && rule.matches(self.utc_offset.local(minute))
+    }
+
+    fn is_holiday(&self, minute: Minute) -> bool {
+        let local = self.utc_offset.local(minute);
+        self.holidays.contains(&local.0.div_euclid(24 * 60))
+    }
+
+    fn skipped(&self, rule: &Rule, minute: Minute) -> bool {
+        !rule.on_holidays && self.is_holiday(minute)
+    }
+
+    // The devices of the rule's groups and the devices it names directly
+    fn devices(&self, rule: &Rule) -> Vec<String> {
+        let mut devices: Vec<String> = Vec::new();
+        for target in &rule.targets {
+            match self.groups.get(target) {
+                Some(group) => devices.extend(group.iter().cloned()),
+                None => devices.push(target.clone()),
+            }
+        }
+        devices.sort();
+        devices.dedup();
+        devices
+    }
+}
+
+/// What happened to a scheduled run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    /// The action ran, with the result of every device
+    Ran(BTreeMap<String, Result<(), String>>),
+    /// The run was skipped because it fell on a holiday
+    Holiday,
+    /// The scheduler was stopped at the time and it was too late to catch up
+    Missed,
+}
+
+/// An entry of the scheduler's execution log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Execution {
+    /// The name of the rule
+    pub rule: String,
+    /// When the rule was due
+    pub scheduled: SystemTime,
+    pub outcome: ExecutionOutcome,
+}
+
+impl Execution {
+    /// Did every device succeed, false for runs that were skipped or missed
+    pub fn succeeded(&self) -> bool {
+        match self.outcome {
+            ExecutionOutcome::Ran(ref results) => results.values().all(|result| result.is_ok()),
+            _ => false,
+        }
+    }
+}
+
+type Callback = Box<dyn Fn(&Execution) + Send>;
+
+struct Shared {
+    fleet: Fleet,
+    schedule: Schedule,
+    // The last minute handled, None until the first tick
+    last: Mutex<Option<Minute>>,
+    log: Mutex<VecDeque<Execution>>,
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+/// Runs the rules of a [pjlink::Schedule](struct.Schedule.html) on the devices of a fleet.
+///
+/// The scheduler checks the schedule every minute on a background thread, or whenever
+/// `tick` is called. Every run, including runs skipped for a holiday, is kept in a log of
+/// the last 1000, appended to the schedule's log file and passed to the `on_execution`
+/// callbacks.
+///
+/// When the schedule has a state file, the scheduler remembers the last minute it handled.
+/// After a restart, the most recent run of each rule that was missed while it was stopped
+/// is made if it is at most `catch_up` minutes late and logged as missed otherwise.
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{Fleet, PjlinkDevice, PowerStatus, Schedule, Scheduler};
+///
+/// let emulator = Emulator::new(EmulatorConfig {
+///     power: PowerStatus::On,
+///     ..EmulatorConfig::default()
+/// });
+/// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+/// let mut fleet = Fleet::new();
+/// fleet.add(device);
+///
+/// let schedule = Schedule::parse(&format!(
+///     "[group room]\ndevices = {}\n\n[rule nightly off]\ncron = 0 22 * * *\ntarget = room\naction = power off\n",
+///     Fleet::key(&fleet.devices()[0]),
+/// ))
+/// .unwrap();
+///
+/// // 2026-10-18 21:59 UTC
+/// let before = UNIX_EPOCH + Duration::from_secs(1_792_360_740);
+/// let due = schedule.next_run("nightly off", before).unwrap();
+/// assert_eq!(due, before + Duration::from_secs(60));
+///
+/// let scheduler = Scheduler::new(fleet.clone(), schedule);
+/// assert!(scheduler.tick(before).is_empty());
+/// let executions = scheduler.tick(due);
+/// assert!(executions[0].succeeded());
+/// assert_eq!(fleet.devices()[0].get_power_status().unwrap(), PowerStatus::Cooling);
+/// ```
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    running: Option<Worker>,
+}
+
+impl Scheduler {
+    /// A scheduler for the devices of a fleet, devices are named in the schedule by their key
+    pub fn new(fleet: Fleet, schedule: Schedule) -> Scheduler {
+        Scheduler {
+            shared: Arc::new(Shared {
+                fleet,
+                schedule,
+                last: Mutex::new(None),
+                log: Mutex::new(VecDeque::new()),
+                callbacks: Mutex::new(Vec::new()),
+            }),
+            running: None,
+        }
+    }
+
+    /// Called for every execution
+    pub fn on_execution<F: Fn(&Execution) + Send + 'static>(&self, callback: F) {
+        self.shared
+            .callbacks
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+
+    /// The most recent executions, oldest first
+    pub fn log(&self) -> Vec<Execution> {
+        self.shared.log.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Run the rules that were due since the last tick, up to `now`.
+    /// The first tick also catches up on runs missed while the scheduler was stopped.
+    pub fn tick(&self, now: SystemTime) -> Vec<Execution> {
+        self.shared.tick(now)
+    }
+
+    /// Is the background thread running
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start checking the schedule on a background thread.
+    /// Starting a scheduler that is already running does nothing.
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+
+        // A tick is cheap when nothing is due, so it is made as often as stopping is checked
+        let shared = Arc::clone(&self.shared);
+        self.running = Some(Worker::every(
+            String::from("pjlink scheduler"),
+            STOP_CHECK_INTERVAL,
+            move || {
+                shared.tick(SystemTime::now());
+            },
+        )?);
+        Ok(())
+    }
+
+    /// Stop the background thread and wait for a running action to finish
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+}
+
+impl Shared {
+    fn tick(&self, now: SystemTime) -> Vec<Execution> {
+        let schedule = &self.schedule;
+        let current = Minute::from_time(now);
+        let mut executions = Vec::new();
+
+        let mut last = self.last.lock().unwrap();
+        let previous = match *last {
+            Some(previous) => previous,
+            None => {
+                // Just started, catch up with what was missed while stopped
+                let stopped = self.load_state().unwrap_or(Minute(current.0 - 1));
+                if stopped < current {
+                    executions.extend(self.catch_up(stopped, current));
+                }
+                // Restarted within the minute that was handled last
+                Minute(stopped.0.min(current.0).max(current.0 - 1))
+            }
+        };
+        if current <= previous {
+            *last = Some(previous.max(current));
+            return executions;
+        }
+
+        // Normally a single minute, more if an action took longer than a minute
+        for minute in (previous.0 + 1..=current.0).map(Minute) {
+            for rule in schedule
+                .rules
+                .iter()
+                .filter(|rule| schedule.due(rule, minute))
+            {
+                executions.push(self.execute(rule, minute));
+            }
+        }
+        *last = Some(current);
+        self.save_state(current);
+        executions
+    }
+
+    // The most recent run of each rule between stopping and starting again
+    fn catch_up(&self, stopped: Minute, current: Minute) -> Vec<Execution> {
+        let schedule = &self.schedule;
+        let oldest = (stopped.0 + 1).max(current.0 - SEARCH_MINUTES);
+        let mut executions = Vec::new();
+
+        for rule in &schedule.rules {
+            let missed = (oldest..current.0)
+                .rev()
+                .map(Minute)
+                .find(|&minute| schedule.due(rule, minute));
+            if let Some(minute) = missed {
+                if current.0 - minute.0 <= schedule.catch_up || schedule.skipped(rule, minute) {
+                    executions.push(self.execute(rule, minute));
+                } else {
+                    executions.push(self.record(rule, minute, ExecutionOutcome::Missed));
+                }
+            }
+        }
+        executions
+    }
+
+    fn execute(&self, rule: &Rule, minute: Minute) -> Execution {
+        if self.schedule.skipped(rule, minute) {
+            return self.record(rule, minute, ExecutionOutcome::Holiday);
+        }
+
+        let devices = self.schedule.devices(rule);
+        let keys: Vec<&str> = devices.iter().map(|key| key.as_str()).collect();
+        let results: FleetResults<()> = match rule.action.clone() {
+            ScheduledAction::PowerOn => self
+                .fleet
+                .run_on(&keys, |device| device.power_on().map(|_| ())),
+            ScheduledAction::PowerOff => self
+                .fleet
+                .run_on(&keys, |device| device.power_off().map(|_| ())),
+            ScheduledAction::SetInput(input) => self
+                .fleet
+                .run_on(&keys, move |device| device.set_input(input).map(|_| ())),
+            ScheduledAction::SetAvMute(avmute) => self
+                .fleet
+                .run_on(&keys, move |device| device.set_avmute(avmute).map(|_| ())),
+            ScheduledAction::Preset(name) => {
+                let state = self.preset(&name, &devices);
+                self.fleet.restore_state(&state, PRESET_TIMEOUT)
+            }
+            ScheduledAction::Raw(command) => self.fleet.run_on(&keys, move |device| {
+                let response = device.send_command(&command)?;
+                match response.find('=') {
+                    Some(equals) if response[equals + 1..].starts_with("ERR") => {
+                        Err(Error::other(response))
+                    }
+                    _ => Ok(()),
+                }
+            }),
+        };
+
+        let results = results
+            .into_iter()
+            .map(|(key, result)| (key, result.map_err(|e| e.to_string())))
+            .collect();
+        self.record(rule, minute, ExecutionOutcome::Ran(results))
+    }
+
+    // The preset, limited to the rule's devices if it names any
+    fn preset(&self, name: &str, devices: &[String]) -> RoomState {
+        let mut state = self.schedule.presets.get(name).cloned().unwrap_or_default();
+        if !devices.is_empty() {
+            state.devices.retain(|key, _| devices.contains(key));
+        }
+        state
+    }
+
+    fn record(&self, rule: &Rule, minute: Minute, outcome: ExecutionOutcome) -> Execution {
+        let execution = Execution {
+            rule: rule.name.clone(),
+            scheduled: minute.to_time(),
+            outcome,
+        };
+
+        {
+            let mut log = self.log.lock().unwrap();
+            if log.len() == LOG_SIZE {
+                log.pop_front();
+            }
+            log.push_back(execution.clone());
+        }
+
+        // The callbacks run outside the lock, so they may add callbacks or read the log
+        let mut callbacks = mem::take(&mut *self.callbacks.lock().unwrap());
+        callbacks.iter().for_each(|callback| callback(&execution));
+        {
+            let mut added = self.callbacks.lock().unwrap();
+            callbacks.append(&mut added);
+            *added = callbacks;
+        }
+
+        if let Some(ref path) = self.schedule.log {
+            // The log file is a convenience, a full disk must not stop the schedule
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| {
+                    let local = self.schedule.utc_offset.local(minute);
+                    writeln!(file, "{} {}", local, describe(&execution))
+                });
+        }
+        execution
+    }
+
+    fn load_state(&self) -> Option<Minute> {
+        let path = self.schedule.state.as_ref()?;
+        let sections = config::load(path).ok()?;
+        let seconds: u64 = sections[0].get("last")?.parse().ok()?;
+        let time = UNIX_EPOCH + Duration::from_secs(seconds);
+        Some(Minute::from_time(time))
+    }
+
+    fn save_state(&self, minute: Minute) {
+        if let Some(ref path) = self.schedule.state {
+            let seconds = match minute.to_time().duration_since(UNIX_EPOCH) {
+                Ok(since) => since.as_secs(),
+                Err(_) => return,
+            };
+            // Written next to the state and renamed over it, so a crash leaves either the
+            // old state or the new one. Without the state a restart only misses the runs
+            // it can't catch up on.
+            let temporary = temporary_path(path);
+            let _ = File::create(&temporary)
+                .and_then(|mut file| {
+                    writeln!(file, "# The last minute handled by the pjlink scheduler")?;
+                    writeln!(file, "last = {}", seconds)?;
+                    file.sync_all()
+                })
+                .and_then(|_| fs::rename(&temporary, path));
+        }
+    }
+}
+
+// The state file with .tmp added to its name
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+// A line of the log file
+fn describe(execution: &Execution) -> String {
+    match execution.outcome {
+        ExecutionOutcome::Ran(ref results) => {
+            let results: Vec<String> = results
+                .iter()
+                .map(|(key, result)| match *result {
+                    Ok(()) => format!("{} ok", key),
+                    Err(ref e) => format!("{} failed: {}", key, e),
+                })
+                .collect();
+            format!("{}: {}", execution.rule, results.join(", "))
+        }
+        ExecutionOutcome::Holiday => format!("{}: skipped for a holiday", execution.rule),
+        ExecutionOutcome::Missed => format!("{}: missed", execution.rule),
+    }
+}
@@ -0,0 +1,644 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use config::invalid;
+use room::SetFreeze;
+use {AvMute, ErrorType, InputType, PjlinkDevice, PowerStatus, POWER_POLL_INTERVAL};
+
+// How long `wait until` waits when the script doesn't say
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A line printed by a script with `print` or `warn`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptMessage {
+    /// The line of the script
+    pub line: usize,
+    /// Was it a `warn`
+    pub warning: bool,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn parse(op: &str) -> Option<Comparison> {
+        match op {
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            "=" | "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            _ => None,
+        }
+    }
+
+    fn compare(self, left: i64, right: i64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Number {
+    // The highest hours of the lamps
+    LampHours,
+    Class,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Condition {
+    Not(Box<Condition>),
+    Power(PowerStatus),
+    Input(InputType),
+    // Video and audio muted, only video or only audio
+    Muted { video: bool, audio: bool },
+    // Any warning or error in ERST
+    Errors,
+    Compare(Number, Comparison, i64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Statement {
+    Power(bool),
+    WaitPower(PowerStatus, Duration),
+    Wait(Duration),
+    Input(InputType),
+    // Mutes to change, None leaves that mute as it is
+    Mute {
+        video: Option<bool>,
+        audio: Option<bool>,
+    },
+    Freeze(bool),
+    // The class and the command
+    Send(u8, String),
+    Print(String),
+    Warn(String),
+    Fail(String),
+    If(Condition, Vec<Step>, Vec<Step>),
+    Repeat(u32, Vec<Step>),
+    While(Condition, Vec<Step>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Step {
+    line: usize,
+    statement: Statement,
+}
+
+// How a block of statements ended
+enum Terminator {
+    End,
+    Else,
+    ElseIf(Condition, usize),
+    Eof,
+}
+
+/// A control macro for a device, written as one statement per line:
+///
+/// ```text
+/// # Morning startup
+/// power on; wait until on within 90s
+/// input digital 1
+/// unmute
+/// if lamp hours > 3000
+///     warn The lamp is due for replacement
+/// end
+/// ```
+///
+/// Statements can also be separated with `;` and everything after a `#` is a comment.
+///
+/// | Statement | |
+/// |---|---|
+/// | `power on`, `power off` | POWR 1 and POWR 0 |
+/// | `wait until on [within 90s]` | Wait for the power status `on`, `off`, `warmup` or `cooling`, 120 seconds at most by default |
+/// | `wait 5s` | Pause, the duration is a number followed by `ms`, `s`, `m` or `h`, seconds without one |
+/// | `input digital 1` | Switch to `rgb`, `video`, `digital`, `storage` or `network` N, or an INPT parameter like `31` |
+/// | `mute`, `unmute` | Mute or unmute the video and audio, `mute video` or `unmute audio` only change one |
+/// | `freeze on`, `freeze off` | FREZ 1 and FREZ 0 (class 2) |
+/// | `send LAMP ?` | Send a raw command, class 1 unless it starts with a prefix like `%2`, the reply is printed |
+/// | `print text`, `warn text` | Pass a message to the caller |
+/// | `fail text` | Stop the script with an error |
+/// | `if` *condition* ... `else if` *condition* ... `else` ... `end` | Run statements depending on the device |
+/// | `repeat 3` ... `end` | Run statements a number of times |
+/// | `while` *condition* ... `end` | Run statements as long as a condition holds, usually with a `wait` |
+///
+/// The conditions are `power is on`, `input is digital 1`, `muted` (video and audio),
+/// `video muted`, `audio muted`, `errors` (a warning or error in ERST),
+/// and comparisons of `lamp hours` or `class` with a number, using
+/// `<`, `<=`, `>`, `>=`, `==` or `!=`. Any condition can be negated with `not`.
+///
+/// Errors in the script and errors from the device name the line of the statement.
+///
+/// ```
+/// use pjlink::server::{Emulator, EmulatorConfig, Server};
+/// use pjlink::{InputType, PjlinkDevice, PowerStatus, Script};
+///
+/// let emulator = Emulator::new(EmulatorConfig {
+///     power: PowerStatus::On,
+///     ..EmulatorConfig::default()
+/// });
+/// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+///
+/// let script = Script::parse(
+///     "power on; wait until on\n\
+///      input digital 2\n\
+///      unmute\n\
+///      if lamp hours >= 0\n\
+///          warn Lamp check\n\
+///      end",
+/// ).unwrap();
+/// let mut warnings = Vec::new();
+/// script.run(&device, |message| warnings.push(message.line)).unwrap();
+/// assert_eq!(warnings, vec![5]);
+/// assert_eq!(device.get_input().unwrap(), InputType::Digital(2));
+///
+/// let err = Script::parse("power on\ninput digital\n").unwrap_err();
+/// assert_eq!(err.to_string(), "Line 2: expected an input like digital 1 or 31");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Parse a script
+    pub fn parse(text: &str) -> Result<Script, Error> {
+        let mut lines = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            for statement in line.split(';') {
+                let words: Vec<String> = statement
+                    .split_whitespace()
+                    .map(|word| word.to_string())
+                    .collect();
+                if !words.is_empty() {
+                    lines.push((index + 1, words));
+                }
+            }
+        }
+
+        let mut parser = Parser {
+            lines: &lines,
+            position: 0,
+        };
+        let (steps, terminator) = parser.block()?;
+        match terminator {
+            Terminator::Eof => Ok(Script { steps }),
+            _ => Err(invalid(
+                lines[parser.position - 1].0,
+                "else or end without if, repeat or while",
+            )),
+        }
+    }
+
+    /// Load a script from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Script, Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Script::parse(&text)
+    }
+
+    /// Run the script against a device, passing every `print` and `warn` to `output`.
+    /// Stops at the first statement that fails.
+    pub fn run<F: FnMut(&ScriptMessage)>(
+        &self,
+        device: &PjlinkDevice,
+        mut output: F,
+    ) -> Result<(), Error> {
+        run_steps(device, &self.steps, &mut output)
+    }
+}
+
+struct Parser<'a> {
+    lines: &'a [(usize, Vec<String>)],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Statements up to the end, else or else if that closes the block
+    fn block(&mut self) -> Result<(Vec<Step>, Terminator), Error> {
+        let mut steps = Vec::new();
+        while self.position < self.lines.len() {
+            let (line, ref words) = self.lines[self.position];
+            self.position += 1;
+            let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+
+            let statement = match words[0].to_lowercase().as_str() {
+                "end" => {
+                    no_arguments(line, &words)?;
+                    return Ok((steps, Terminator::End));
+                }
+                "else" => match words.get(1).map(|word| word.to_lowercase()) {
+                    None => return Ok((steps, Terminator::Else)),
+                    Some(ref word) if word == "if" => {
+                        return Ok((
+                            steps,
+                            Terminator::ElseIf(condition(line, &words[2..])?, line),
+                        ))
+                    }
+                    Some(_) => return Err(invalid(line, "expected else or else if")),
+                },
+                "if" => {
+                    let condition = condition(line, &words[1..])?;
+                    self.conditional(line, condition)?
+                }
+                "repeat" => {
+                    let times = match words.get(1..) {
+                        Some([times]) => times.parse().map_err(|_| {
+                            invalid(line, &format!("invalid number of times {}", times))
+                        })?,
+                        _ => return Err(invalid(line, "expected repeat and a number of times")),
+                    };
+                    Statement::Repeat(times, self.loop_body(line, "repeat")?)
+                }
+                "while" => {
+                    let condition = condition(line, &words[1..])?;
+                    Statement::While(condition, self.loop_body(line, "while")?)
+                }
+                _ => statement(line, &words)?,
+            };
+            steps.push(Step { line, statement });
+        }
+        Ok((steps, Terminator::Eof))
+    }
+
+    fn conditional(&mut self, line: usize, condition: Condition) -> Result<Statement, Error> {
+        let (then, terminator) = self.block()?;
+        let otherwise = match terminator {
+            Terminator::End => Vec::new(),
+            Terminator::Else => match self.block()? {
+                (otherwise, Terminator::End) => otherwise,
+                (_, Terminator::Eof) => return Err(invalid(line, "if without end")),
+                _ => {
+                    return Err(invalid(
+                        self.lines[self.position - 1].0,
+                        "expected end after else",
+                    ))
+                }
+            },
+            Terminator::ElseIf(condition, else_line) => vec![Step {
+                line: else_line,
+                statement: self.conditional(line, condition)?,
+            }],
+            Terminator::Eof => return Err(invalid(line, "if without end")),
+        };
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn loop_body(&mut self, line: usize, name: &str) -> Result<Vec<Step>, Error> {
+        match self.block()? {
+            (steps, Terminator::End) => Ok(steps),
+            (_, Terminator::Eof) => Err(invalid(line, &format!("{} without end", name))),
+            _ => Err(invalid(
+                self.lines[self.position - 1].0,
+                &format!("else in a {} loop", name),
+            )),
+        }
+    }
+}
+
+fn no_arguments(line: usize, words: &[&str]) -> Result<(), Error> {
+    match words.len() {
+        1 => Ok(()),
+        _ => Err(invalid(
+            line,
+            &format!("unexpected {} after {}", words[1], words[0]),
+        )),
+    }
+}
+
+// The text after the first word, as written
+fn text(words: &[&str]) -> String {
+    words[1..].join(" ")
+}
+
+fn statement(line: usize, words: &[&str]) -> Result<Statement, Error> {
+    let lowercase: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+    let lowercase: Vec<&str> = lowercase.iter().map(|word| word.as_str()).collect();
+
+    match lowercase[..] {
+        ["power", "on"] => Ok(Statement::Power(true)),
+        ["power", "off"] => Ok(Statement::Power(false)),
+        ["power", ..] => Err(invalid(line, "expected power on or power off")),
+        ["wait", "until", status] => Ok(Statement::WaitPower(
+            power_status(line, status)?,
+            DEFAULT_WAIT_TIMEOUT,
+        )),
+        ["wait", "until", status, "within", timeout] => Ok(Statement::WaitPower(
+            power_status(line, status)?,
+            duration(line, timeout)?,
+        )),
+        ["wait", "until", ..] => Err(invalid(line, "expected wait until on [within 90s]")),
+        ["wait", wait] => Ok(Statement::Wait(duration(line, wait)?)),
+        ["wait", ..] => Err(invalid(line, "expected wait 5s or wait until on")),
+        ["input", ref input @ ..] => Ok(Statement::Input(input_type(line, input)?)),
+        ["mute"] => Ok(Statement::Mute {
+            video: Some(true),
+            audio: Some(true),
+        }),
+        ["unmute"] => Ok(Statement::Mute {
+            video: Some(false),
+            audio: Some(false),
+        }),
+        [mute @ "mute", which] | [mute @ "unmute", which] => {
+            let on = Some(mute == "mute");
+            match which {
+                "video" => Ok(Statement::Mute {
+                    video: on,
+                    audio: None,
+                }),
+                "audio" => Ok(Statement::Mute {
+                    video: None,
+                    audio: on,
+                }),
+                _ => Err(invalid(
+                    line,
+                    &format!("expected video or audio, got {}", which),
+                )),
+            }
+        }
+        ["freeze", "on"] => Ok(Statement::Freeze(true)),
+        ["freeze", "off"] => Ok(Statement::Freeze(false)),
+        ["freeze", ..] => Err(invalid(line, "expected freeze on or freeze off")),
+        ["send", _, ..] => Ok(send(&text(words))),
+        ["print", ..] => Ok(Statement::Print(text(words))),
+        ["warn", ..] => Ok(Statement::Warn(text(words))),
+        ["fail", ..] => Ok(Statement::Fail(text(words))),
+        _ => Err(invalid(
+            line,
+            &format!("unknown statement {}", words.join(" ")),
+        )),
+    }
+}
+
+// "LAMP ?" or "%2SNUM ?"
+fn send(command: &str) -> Statement {
+    let mut chars = command.chars();
+    match (chars.next(), chars.next().and_then(|c| c.to_digit(10))) {
+        (Some('%'), Some(class)) => Statement::Send(class as u8, command[2..].to_string()),
+        _ => Statement::Send(1, command.to_string()),
+    }
+}
+
+fn condition(line: usize, words: &[&str]) -> Result<Condition, Error> {
+    let lowercase: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+    let lowercase: Vec<&str> = lowercase.iter().map(|word| word.as_str()).collect();
+
+    match lowercase[..] {
+        ["not", ..] => Ok(Condition::Not(Box::new(condition(line, &words[1..])?))),
+        ["power", "is", status] => Ok(Condition::Power(power_status(line, status)?)),
+        ["input", "is", ref input @ ..] => Ok(Condition::Input(input_type(line, input)?)),
+        ["muted"] => Ok(Condition::Muted {
+            video: true,
+            audio: true,
+        }),
+        ["video", "muted"] => Ok(Condition::Muted {
+            video: true,
+            audio: false,
+        }),
+        ["audio", "muted"] => Ok(Condition::Muted {
+            video: false,
+            audio: true,
+        }),
+        ["errors"] => Ok(Condition::Errors),
+        ["lamp", "hours", op, number] => compare(line, Number::LampHours, op, number),
+        ["class", op, number] => compare(line, Number::Class, op, number),
+        [] => Err(invalid(line, "missing condition")),
+        _ => Err(invalid(
+            line,
+            &format!("unknown condition {}", words.join(" ")),
+        )),
+    }
+}
+
+fn compare(line: usize, value: Number, op: &str, number: &str) -> Result<Condition, Error> {
+    let comparison = Comparison::parse(op)
+        .ok_or_else(|| invalid(line, &format!("unknown comparison {}", op)))?;
+    let number = number
+        .parse()
+        .map_err(|_| invalid(line, &format!("invalid number {}", number)))?;
+    Ok(Condition::Compare(value, comparison, number))
+}
+
+fn power_status(line: usize, status: &str) -> Result<PowerStatus, Error> {
+    match status {
+        "on" => Ok(PowerStatus::On),
+        "off" => Ok(PowerStatus::Off),
+        "warmup" => Ok(PowerStatus::Warmup),
+        "cooling" => Ok(PowerStatus::Cooling),
+        _ => Err(invalid(
+            line,
+            &format!("expected on, off, warmup or cooling, got {}", status),
+        )),
+    }
+}
+
+// "digital 1" or an INPT parameter like "31"
+fn input_type(line: usize, words: &[&str]) -> Result<InputType, Error> {
+    let input = match *words {
+        [code] => InputType::from_extended_code(code),
+        [kind, number] => number
+            .parse()
+            .ok()
+            .filter(|number| *number > 0)
+            .and_then(|number| match kind {
+                "rgb" => Some(InputType::RGB(number)),
+                "video" => Some(InputType::Video(number)),
+                "digital" => Some(InputType::Digital(number)),
+                "storage" => Some(InputType::Storage(number)),
+                "network" => Some(InputType::Network(number)),
+                _ => None,
+            }),
+        _ => None,
+    };
+    input.ok_or_else(|| invalid(line, "expected an input like digital 1 or 31"))
+}
+
+// 500ms, 5s, 2m, 1h, or 5 for seconds
+fn duration(line: usize, text: &str) -> Result<Duration, Error> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let invalid_duration = || invalid(line, &format!("invalid duration {}", text));
+    let number: u64 = text[..split].parse().map_err(|_| invalid_duration())?;
+    let unit = match &text[split..] {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(invalid_duration()),
+    };
+    // Too many hours or minutes for a Duration
+    number
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid_duration)
+}
+
+// Point an error from the device at the line of the statement
+fn at_line(line: usize, e: Error) -> Error {
+    Error::new(e.kind(), format!("Line {}: {}", line, e))
+}
+
+fn run_steps<F: FnMut(&ScriptMessage)>(
+    device: &PjlinkDevice,
+    steps: &[Step],
+    output: &mut F,
+) -> Result<(), Error> {
+    for step in steps {
+        let line = step.line;
+        let check =
+            |condition: &Condition| evaluate(device, condition).map_err(|e| at_line(line, e));
+        match step.statement {
+            Statement::If(ref condition, ref then, ref otherwise) => {
+                if check(condition)? {
+                    run_steps(device, then, output)?;
+                } else {
+                    run_steps(device, otherwise, output)?;
+                }
+            }
+            Statement::Repeat(times, ref body) => {
+                for _ in 0..times {
+                    run_steps(device, body, output)?;
+                }
+            }
+            Statement::While(ref condition, ref body) => {
+                while check(condition)? {
+                    run_steps(device, body, output)?;
+                }
+            }
+            Statement::Print(ref text) | Statement::Warn(ref text) => output(&ScriptMessage {
+                line,
+                warning: matches!(step.statement, Statement::Warn(_)),
+                text: text.clone(),
+            }),
+            Statement::Fail(ref text) => {
+                return Err(Error::other(format!("Line {}: {}", line, text)));
+            }
+            ref statement => {
+                let reply = execute(device, statement).map_err(|e| at_line(line, e))?;
+                if let Some(reply) = reply {
+                    output(&ScriptMessage {
+                        line,
+                        warning: false,
+                        text: reply,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Run a statement that talks to the device, with the reply of a raw command
+fn execute(device: &PjlinkDevice, statement: &Statement) -> Result<Option<String>, Error> {
+    match *statement {
+        Statement::Power(true) => device.power_on().map(|_| ())?,
+        Statement::Power(false) => device.power_off().map(|_| ())?,
+        Statement::WaitPower(status, timeout) => device
+            .wait_for_power(status, timeout, POWER_POLL_INTERVAL)
+            .map(|_| ())?,
+        Statement::Wait(wait) => thread::sleep(wait),
+        Statement::Input(input) => device.set_input(input).map(|_| ())?,
+        Statement::Mute { video, audio } => {
+            let mut avmute = match (video, audio) {
+                (Some(_), Some(_)) => AvMute {
+                    video: false,
+                    audio: false,
+                },
+                _ => device.get_avmute()?,
+            };
+            avmute.video = video.unwrap_or(avmute.video);
+            avmute.audio = audio.unwrap_or(avmute.audio);
+            device.set_avmute(avmute).map(|_| ())?
+        }
+        Statement::Freeze(freeze) => device.execute(&SetFreeze(freeze))?,
+        Statement::Send(class, ref command) => {
+            return device.send_class_command(class, command).map(Some)
+        }
+        _ => (),
+    }
+    Ok(None)
+}
+
+fn evaluate(device: &PjlinkDevice, condition: &Condition) -> Result<bool, Error> {
+    match *condition {
+        Condition::Not(ref condition) => evaluate(device, condition).map(|value| !value),
+        Condition::Power(status) => Ok(device.get_power_status()? == status),
+        Condition::Input(input) => Ok(device.get_input()? == input),
+        Condition::Muted { video, audio } => {
+            let avmute = device.get_avmute()?;
+            Ok((!video || avmute.video) && (!audio || avmute.audio))
+        }
+        Condition::Errors => {
+            let status = device.get_error_status()?;
+            Ok([
+                status.fan_error,
+                status.lamp_error,
+                status.temperature_error,
+                status.cover_open_error,
+                status.filter_error,
+                status.other_error,
+            ]
+            .iter()
+            .any(|error| *error != ErrorType::NoError))
+        }
+        Condition::Compare(value, comparison, number) => {
+            let value = match value {
+                Number::LampHours => device
+                    .get_lamp()?
+                    .iter()
+                    .map(|lamp| i64::from(lamp.hours))
+                    .max()
+                    .unwrap_or(0),
+                Number::Class => device.get_class()?.trim().parse().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        "The device reported an invalid class",
+                    )
+                })?,
+            };
+            Ok(comparison.compare(value, number))
+        }
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {AvMute, ErrorStatus, ErrorType, InputType, Lamp, PjlinkErrorCode, PowerStatus};
+
+use super::{input_name, Emulator};
+
+/// The equipment behind a [Server](struct.Server.html).
+///
+/// The server handles the greeting, authentication, framing and parameter checks, and
+/// calls into the backend for everything it needs to know or change. Returning an error
+/// code sends it to the controller, e.g. `PjlinkErrorCode::Unavailable` for ERR3 while the
+/// equipment is busy. Methods with a default answer like a device that does not support
+/// the command, so a PJLink front end for equipment without PJLink only needs power,
+/// input and AV mute:
+///
+/// ```
+/// use std::sync::Mutex;
+/// use pjlink::server::{PjlinkBackend, Server};
+/// use pjlink::{AvMute, InputType, PjlinkDevice, PjlinkErrorCode, PowerStatus};
+///
+/// // An HDMI matrix output that is always on and can switch between two sources
+/// struct MatrixOutput {
+///     source: Mutex<InputType>,
+/// }
+///
+/// impl PjlinkBackend for MatrixOutput {
+///     fn power_status(&self) -> Result<PowerStatus, PjlinkErrorCode> {
+///         Ok(PowerStatus::On)
+///     }
+///     fn set_power(&self, _on: bool) -> Result<(), PjlinkErrorCode> {
+///         Ok(())
+///     }
+///     fn input(&self) -> Result<InputType, PjlinkErrorCode> {
+///         Ok(*self.source.lock().unwrap())
+///     }
+///     fn set_input(&self, input: InputType) -> Result<(), PjlinkErrorCode> {
+///         *self.source.lock().unwrap() = input;
+///         Ok(())
+///     }
+///     fn inputs(&self) -> Result<Vec<InputType>, PjlinkErrorCode> {
+///         Ok(vec![InputType::Digital(1), InputType::Digital(2)])
+///     }
+///     fn avmute(&self) -> Result<AvMute, PjlinkErrorCode> {
+///         Ok(AvMute { video: false, audio: false })
+///     }
+///     fn set_avmute(&self, _video: Option<bool>, _audio: Option<bool>) -> Result<(), PjlinkErrorCode> {
+///         Err(PjlinkErrorCode::Unavailable)
+///     }
+/// }
+///
+/// let backend = MatrixOutput { source: Mutex::new(InputType::Digital(1)) };
+/// let server = Server::bind("127.0.0.1:0", backend).unwrap().spawn().unwrap();
+/// let device = PjlinkDevice::builder("127.0.0.1")
+///     .port(server.local_addr().port())
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(device.set_input(InputType::Digital(2)).unwrap(), InputType::Digital(2));
+/// assert!(device.get_lamp().is_err());
+/// ```
+pub trait PjlinkBackend: Send + Sync {
+    /// CLSS ?, class 2 commands are answered with ERR1 unless this is 2
+    fn class(&self) -> u8 {
+        1
+    }
+
+    /// POWR ?
+    fn power_status(&self) -> Result<PowerStatus, PjlinkErrorCode>;
+
+    /// POWR 1 and POWR 0
+    fn set_power(&self, on: bool) -> Result<(), PjlinkErrorCode>;
+
+    /// INPT ?
+    fn input(&self) -> Result<InputType, PjlinkErrorCode>;
+
+    /// INPT with an input, only called with one of the inputs from `inputs`
+    fn set_input(&self, input: InputType) -> Result<(), PjlinkErrorCode>;
+
+    /// INST ?
+    fn inputs(&self) -> Result<Vec<InputType>, PjlinkErrorCode>;
+
+    /// AVMT ?
+    fn avmute(&self) -> Result<AvMute, PjlinkErrorCode>;
+
+    /// AVMT with a setting, the audio or video flag is only given
+    /// when the setting addresses it (1x for video, 2x for audio, 3x for both).
+    fn set_avmute(&self, video: Option<bool>, audio: Option<bool>) -> Result<(), PjlinkErrorCode>;
+
+    /// LAMP ?, ERR1 by default for equipment without lamps
+    fn lamps(&self) -> Result<Vec<Lamp>, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// ERST ?, no errors by default
+    fn error_status(&self) -> Result<ErrorStatus, PjlinkErrorCode> {
+        Ok(ErrorStatus {
+            fan_error: ErrorType::NoError,
+            lamp_error: ErrorType::NoError,
+            temperature_error: ErrorType::NoError,
+            cover_open_error: ErrorType::NoError,
+            filter_error: ErrorType::NoError,
+            other_error: ErrorType::NoError,
+        })
+    }
+
+    /// NAME ?, empty by default
+    fn name(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(String::new())
+    }
+
+    /// INF1 ?, empty by default
+    fn manufacturer(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(String::new())
+    }
+
+    /// INF2 ?, empty by default
+    fn product_name(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(String::new())
+    }
+
+    /// INFO ?, empty by default
+    fn info(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(String::new())
+    }
+
+    /// SNUM ? (class 2)
+    fn serial_number(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// SVER ? (class 2)
+    fn software_version(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// INNM ? (class 2), only called with one of the inputs from `inputs`.
+    /// Names like "Digital 1" by default.
+    fn input_name(&self, input: InputType) -> Result<String, PjlinkErrorCode> {
+        Ok(input_name(input))
+    }
+
+    /// IRES ? (class 2), "-" when there is no signal
+    fn input_resolution(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// RRES ? (class 2)
+    fn recommended_resolution(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// FILT ? (class 2)
+    fn filter_hours(&self) -> Result<u32, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// RLMP ? (class 2)
+    fn lamp_model(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// RFIL ? (class 2)
+    fn filter_model(&self) -> Result<String, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// FREZ ? (class 2)
+    fn freeze(&self) -> Result<bool, PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+
+    /// FREZ 1 and FREZ 0 (class 2)
+    fn set_freeze(&self, _freeze: bool) -> Result<(), PjlinkErrorCode> {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    }
+}
+
+impl PjlinkBackend for Emulator {
+    fn class(&self) -> u8 {
+        Emulator::class(self)
+    }
+
+    fn power_status(&self) -> Result<PowerStatus, PjlinkErrorCode> {
+        Ok(Emulator::power_status(self))
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), PjlinkErrorCode> {
+        self.request_power(on)
+    }
+
+    fn input(&self) -> Result<InputType, PjlinkErrorCode> {
+        self.current_input()
+    }
+
+    fn set_input(&self, input: InputType) -> Result<(), PjlinkErrorCode> {
+        self.select_input(input)
+    }
+
+    fn inputs(&self) -> Result<Vec<InputType>, PjlinkErrorCode> {
+        Ok(Emulator::inputs(self))
+    }
+
+    fn avmute(&self) -> Result<AvMute, PjlinkErrorCode> {
+        Ok(Emulator::avmute(self))
+    }
+
+    fn set_avmute(&self, video: Option<bool>, audio: Option<bool>) -> Result<(), PjlinkErrorCode> {
+        self.set_mute(video, audio)
+    }
+
+    fn lamps(&self) -> Result<Vec<Lamp>, PjlinkErrorCode> {
+        Emulator::lamps(self)
+    }
+
+    fn error_status(&self) -> Result<ErrorStatus, PjlinkErrorCode> {
+        Ok(Emulator::error_status(self))
+    }
+
+    fn name(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().name)
+    }
+
+    fn manufacturer(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().manufacturer)
+    }
+
+    fn product_name(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().product_name)
+    }
+
+    fn info(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().info)
+    }
+
+    fn serial_number(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().serial_number)
+    }
+
+    fn software_version(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().software_version)
+    }
+
+    fn input_resolution(&self) -> Result<String, PjlinkErrorCode> {
+        Emulator::input_resolution(self)
+    }
+
+    fn recommended_resolution(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().recommended_resolution)
+    }
+
+    fn filter_hours(&self) -> Result<u32, PjlinkErrorCode> {
+        Ok(self.config().filter_hours)
+    }
+
+    fn lamp_model(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().lamp_model)
+    }
+
+    fn filter_model(&self) -> Result<String, PjlinkErrorCode> {
+        Ok(self.config().filter_model)
+    }
+
+    fn freeze(&self) -> Result<bool, PjlinkErrorCode> {
+        Ok(Emulator::freeze(self))
+    }
+
+    fn set_freeze(&self, freeze: bool) -> Result<(), PjlinkErrorCode> {
+        Emulator::set_freeze(self, freeze)
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use {
+    AvMute, DeviceProfile, ErrorStatus, ErrorType, InputType, Lamp, PjlinkErrorCode, PowerStatus,
+};
+
+/// Describes the device an [Emulator](struct.Emulator.html) pretends to be
+#[derive(Clone, Debug)]
+pub struct EmulatorConfig {
+    /// The PJLink class, 1 or 2. Class 2 commands are answered with ERR1 by a class 1 device.
+    pub class: u8,
+    /// NAME ?
+    pub name: String,
+    /// INF1 ?
+    pub manufacturer: String,
+    /// INF2 ?
+    pub product_name: String,
+    /// INFO ?
+    pub info: String,
+    /// SNUM ? (class 2)
+    pub serial_number: String,
+    /// SVER ? (class 2)
+    pub software_version: String,
+    /// The inputs reported by INST ?, the first one is selected at startup
+    pub inputs: Vec<InputType>,
+    /// The lamps reported by LAMP ?, a device without lamps answers ERR1
+    pub lamps: Vec<Lamp>,
+    /// ERST ?
+    pub error_status: ErrorStatus,
+    /// The power status at startup
+    pub power: PowerStatus,
+    /// How long the device stays in Warmup after POWR 1
+    pub warmup: Duration,
+    /// How long the device stays in Cooling after POWR 0
+    pub cooling: Duration,
+    /// IRES ? (class 2) while there is a signal on the input
+    pub resolution: String,
+    /// RRES ? (class 2)
+    pub recommended_resolution: String,
+    /// FILT ? (class 2)
+    pub filter_hours: u32,
+    /// RLMP ? (class 2)
+    pub lamp_model: String,
+    /// RFIL ? (class 2)
+    pub filter_model: String,
+    /// The MAC address reported in ACKN and LKUP over UDP (class 2), as "xx:xx:xx:xx:xx:xx"
+    pub mac_address: String,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> EmulatorConfig {
+        EmulatorConfig {
+            class: 1,
+            name: String::from("PJLink Emulator"),
+            manufacturer: String::from("pjlink"),
+            product_name: String::from("Emulator"),
+            info: String::from("Emulated PJLink device"),
+            serial_number: String::from("0000000001"),
+            software_version: String::from(env!("CARGO_PKG_VERSION")),
+            inputs: vec![
+                InputType::RGB(1),
+                InputType::Video(1),
+                InputType::Digital(1),
+                InputType::Digital(2),
+                InputType::Network(1),
+            ],
+            lamps: vec![Lamp {
+                hours: 0,
+                on: false,
+            }],
+            error_status: ErrorStatus {
+                fan_error: ErrorType::NoError,
+                lamp_error: ErrorType::NoError,
+                temperature_error: ErrorType::NoError,
+                cover_open_error: ErrorType::NoError,
+                filter_error: ErrorType::NoError,
+                other_error: ErrorType::NoError,
+            },
+            power: PowerStatus::Off,
+            warmup: Duration::from_secs(10),
+            cooling: Duration::from_secs(10),
+            resolution: String::from("1920x1080"),
+            recommended_resolution: String::from("1920x1080"),
+            filter_hours: 0,
+            lamp_model: String::new(),
+            filter_model: String::new(),
+            mac_address: String::from("00:00:5e:00:53:01"),
+        }
+    }
+}
+
+impl EmulatorConfig {
+    /// A configuration that looks like the device a profile was captured from.
+    /// Anything the profile does not have, or that the device answered with an error,
+    /// keeps its default value. The device starts in standby.
+    pub fn from_profile(profile: &DeviceProfile) -> EmulatorConfig {
+        let mut config = EmulatorConfig::default();
+        let text = |request: &str, field: &mut String| {
+            if let Some(value) = profile.value(request) {
+                *field = value.to_string();
+            }
+        };
+
+        if let Some(class) = profile.value("%1CLSS ?").and_then(|v| v.parse().ok()) {
+            config.class = class;
+        }
+        text("%1NAME ?", &mut config.name);
+        text("%1INF1 ?", &mut config.manufacturer);
+        text("%1INF2 ?", &mut config.product_name);
+        text("%1INFO ?", &mut config.info);
+        text("%2SNUM ?", &mut config.serial_number);
+        text("%2SVER ?", &mut config.software_version);
+        text("%2RRES ?", &mut config.recommended_resolution);
+        text("%2RLMP ?", &mut config.lamp_model);
+        text("%2RFIL ?", &mut config.filter_model);
+        if let Some(hours) = profile.value("%2FILT ?").and_then(|v| v.parse().ok()) {
+            config.filter_hours = hours;
+        }
+
+        if let Some(inputs) = profile.value("%1INST ?") {
+            config.inputs = inputs
+                .split_whitespace()
+                .filter_map(|code| code.parse().ok().and_then(InputType::from_code))
+                .collect();
+        }
+        if let Some(lamps) = profile.value("%1LAMP ?") {
+            let values: Vec<&str> = lamps.split_whitespace().collect();
+            config.lamps = values
+                .chunks(2)
+                .filter_map(|lamp| {
+                    Some(Lamp {
+                        hours: lamp[0].parse().ok()?,
+                        on: false,
+                    })
+                })
+                .collect();
+        } else if profile.response("%1LAMP ?").is_some() {
+            // The device answered with an error, so it has no lamps
+            config.lamps.clear();
+        }
+        if let Some(errors) = profile.value("%1ERST ?") {
+            let mut errors = errors.chars().map(|c| match c {
+                '1' => ErrorType::Warning,
+                '2' => ErrorType::Error,
+                _ => ErrorType::NoError,
+            });
+            let mut next = || errors.next().unwrap_or(ErrorType::NoError);
+            config.error_status = ErrorStatus {
+                fan_error: next(),
+                lamp_error: next(),
+                temperature_error: next(),
+                cover_open_error: next(),
+                filter_error: next(),
+                other_error: next(),
+            };
+        }
+
+        config
+    }
+}
+
+struct State {
+    config: EmulatorConfig,
+    power: PowerStatus,
+    // When the current power status was entered
+    power_since: Instant,
+    // Time spent with the lamps on, added to the configured lamp hours
+    lamp_time: Duration,
+    input: InputType,
+    mute: AvMute,
+    freeze: bool,
+    signal: bool,
+}
+
+impl State {
+    // Move through warmup and cooling based on the time that has passed.
+    fn advance(&mut self) {
+        let now = Instant::now();
+        if self.power == PowerStatus::Warmup
+            && now.duration_since(self.power_since) >= self.config.warmup
+        {
+            self.power_since += self.config.warmup;
+            self.power = PowerStatus::On;
+        }
+        if self.power == PowerStatus::Cooling
+            && now.duration_since(self.power_since) >= self.config.cooling
+        {
+            self.power_since += self.config.cooling;
+            self.power = PowerStatus::Off;
+        }
+    }
+
+    fn set_power(&mut self, power: PowerStatus) {
+        if self.power == PowerStatus::On && power != PowerStatus::On {
+            self.lamp_time += self.power_since.elapsed();
+        }
+        if power != PowerStatus::On {
+            self.mute = AvMute {
+                audio: false,
+                video: false,
+            };
+            self.freeze = false;
+        }
+        self.power = power;
+        self.power_since = Instant::now();
+    }
+
+    fn require_on(&self) -> Result<(), PjlinkErrorCode> {
+        if self.power == PowerStatus::On {
+            Ok(())
+        } else {
+            Err(PjlinkErrorCode::Unavailable)
+        }
+    }
+}
+
+/// A simulated PJLink device.
+///
+/// The emulator follows the Off, Warmup, On, Cooling, Off power cycle with the
+/// configured timings, refuses power commands while warming up or cooling down, only
+/// accepts input, AV mute and freeze changes while it is on (answering ERR3 otherwise)
+/// and counts lamp hours while it is on.
+///
+/// An Emulator is a cheap handle to shared state, so a clone can be kept by a test to
+/// inspect or change the device while a [Server](struct.Server.html) is serving it.
+#[derive(Clone)]
+pub struct Emulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Emulator {
+    pub fn new(config: EmulatorConfig) -> Emulator {
+        let input = config.inputs.first().cloned().unwrap_or(InputType::RGB(1));
+        Emulator {
+            state: Arc::new(Mutex::new(State {
+                power: config.power,
+                power_since: Instant::now(),
+                lamp_time: Duration::from_secs(0),
+                input,
+                mute: AvMute {
+                    audio: false,
+                    video: false,
+                },
+                freeze: false,
+                signal: true,
+                config,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state
+    }
+
+    /// The PJLink class of the device
+    pub fn class(&self) -> u8 {
+        self.lock().config.class
+    }
+
+    /// The current power status
+    pub fn power_status(&self) -> PowerStatus {
+        self.lock().power
+    }
+
+    /// Force the power status without going through warmup or cooling
+    pub fn set_power_status(&self, power: PowerStatus) {
+        self.lock().set_power(power);
+    }
+
+    /// The selected input
+    pub fn input(&self) -> InputType {
+        self.lock().input
+    }
+
+    /// The current AV mute
+    pub fn avmute(&self) -> AvMute {
+        self.lock().mute
+    }
+
+    /// Is the image frozen (class 2)
+    pub fn freeze(&self) -> bool {
+        self.lock().freeze
+    }
+
+    /// Change the reported error status
+    pub fn set_error_status(&self, error_status: ErrorStatus) {
+        self.lock().config.error_status = error_status;
+    }
+
+    /// Change the lamps
+    pub fn set_lamps(&self, lamps: Vec<Lamp>) {
+        self.lock().config.lamps = lamps;
+    }
+
+    /// Connect or disconnect the source on the selected input.
+    /// Without a signal IRES ? answers "-".
+    pub fn set_signal(&self, signal: bool) {
+        self.lock().signal = signal;
+    }
+
+    /// Handle POWR 1 and POWR 0
+    pub fn request_power(&self, on: bool) -> Result<(), PjlinkErrorCode> {
+        let mut state = self.lock();
+        match (on, state.power) {
+            (true, PowerStatus::Off) => {
+                state.set_power(PowerStatus::Warmup);
+                state.advance();
+                Ok(())
+            }
+            (false, PowerStatus::On) => {
+                state.set_power(PowerStatus::Cooling);
+                state.advance();
+                Ok(())
+            }
+            // Like most projectors, power commands are refused in the middle of a transition
+            (_, PowerStatus::Warmup) | (_, PowerStatus::Cooling) => {
+                Err(PjlinkErrorCode::Unavailable)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle INPT ?, which is only available while the device is on
+    pub fn current_input(&self) -> Result<InputType, PjlinkErrorCode> {
+        let state = self.lock();
+        state.require_on()?;
+        Ok(state.input)
+    }
+
+    /// Handle INPT with an input number
+    pub fn select_input(&self, input: InputType) -> Result<(), PjlinkErrorCode> {
+        let mut state = self.lock();
+        if !state.config.inputs.contains(&input) {
+            return Err(PjlinkErrorCode::InvalidParameter);
+        }
+        state.require_on()?;
+        state.input = input;
+        Ok(())
+    }
+
+    /// The inputs the device has
+    pub fn inputs(&self) -> Vec<InputType> {
+        self.lock().config.inputs.clone()
+    }
+
+    /// Handle AVMT with a setting, the audio or video flag is only changed
+    /// when the setting addresses it (1x for video, 2x for audio, 3x for both).
+    pub fn set_mute(
+        &self,
+        video: Option<bool>,
+        audio: Option<bool>,
+    ) -> Result<(), PjlinkErrorCode> {
+        let mut state = self.lock();
+        state.require_on()?;
+        if let Some(video) = video {
+            state.mute.video = video;
+        }
+        if let Some(audio) = audio {
+            state.mute.audio = audio;
+        }
+        Ok(())
+    }
+
+    /// Handle LAMP ?
+    pub fn lamps(&self) -> Result<Vec<Lamp>, PjlinkErrorCode> {
+        let state = self.lock();
+        if state.config.lamps.is_empty() {
+            return Err(PjlinkErrorCode::UndefinedCommand);
+        }
+
+        let mut on_time = state.lamp_time;
+        if state.power == PowerStatus::On {
+            on_time += state.power_since.elapsed();
+        }
+        let extra_hours = (on_time.as_secs() / 3600).min(u64::from(u16::MAX)) as u16;
+        let lit = state.power == PowerStatus::On;
+
+        Ok(state
+            .config
+            .lamps
+            .iter()
+            .map(|lamp| Lamp {
+                hours: lamp.hours.saturating_add(extra_hours),
+                on: lit,
+            })
+            .collect())
+    }
+
+    /// Handle ERST ?
+    pub fn error_status(&self) -> ErrorStatus {
+        self.lock().config.error_status
+    }
+
+    /// A copy of the configuration the emulator was started with,
+    /// including any changes to the error status and lamps.
+    pub fn config(&self) -> EmulatorConfig {
+        self.lock().config.clone()
+    }
+
+    /// Handle IRES ? (class 2), "-" when there is no signal
+    pub fn input_resolution(&self) -> Result<String, PjlinkErrorCode> {
+        let state = self.lock();
+        state.require_on()?;
+        if state.signal {
+            Ok(state.config.resolution.clone())
+        } else {
+            Ok(String::from("-"))
+        }
+    }
+
+    /// Handle FREZ with a setting (class 2)
+    pub fn set_freeze(&self, freeze: bool) -> Result<(), PjlinkErrorCode> {
+        let mut state = self.lock();
+        state.require_on()?;
+        state.freeze = freeze;
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use config::{self, Entry, Section};
+use rng::Rng;
+use {Lamp, PowerStatus, PORT};
+
+use super::{Emulator, EmulatorConfig, FaultProfile, Server, ServerHandle, UdpServer};
+
+static KEYS: &[&str] = &[
+    "count",
+    "bind",
+    "port",
+    "vary",
+    "name",
+    "class",
+    "manufacturer",
+    "product",
+    "info",
+    "serial",
+    "password",
+    "lamps",
+    "lamp_hours",
+    "filter_hours",
+    "warmup",
+    "cooling",
+    "power",
+    "faults",
+    "seed",
+    "mac",
+    "udp",
+    "controller",
+];
+
+/// One emulated device of a [Farm](struct.Farm.html)
+pub struct FarmDevice {
+    name: String,
+    emulator: Emulator,
+    handle: ServerHandle,
+    udp: Option<ServerHandle>,
+}
+
+impl FarmDevice {
+    /// The device name, also reported by NAME ?
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The address the device is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// The emulator behind the device, to inspect or change its state
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+}
+
+/// Many emulated devices served from one process, described by one config file.
+///
+/// Each section of the file describes a device, or with `count` a group of devices,
+/// and settings before the first section apply to every device. `{n}` in a value is
+/// replaced by the number of the device within its group, starting at 1. Numeric
+/// settings can be given as a range `low..high` to pick a different value for each
+/// device, repeatably for the same `seed`.
+///
+/// ```text
+/// bind = 127.0.0.1
+/// warmup = 2
+/// seed = 42
+///
+/// [Hall A]
+/// port = 4352
+/// class = 2
+/// password = JBMIAProjectorLink
+/// lamp_hours = 1200
+/// faults = busy, LAMP:delay=200
+///
+/// # Room 1 listens on 5000, Room 2 on 5001 and so on
+/// [Room {n}]
+/// count = 400
+/// port = 5000
+/// lamp_hours = 0..20000
+/// password = room{n}
+///
+/// # Lab 1 listens on 127.0.1.1:4352, Lab 2 on 127.0.1.2:4352 and so on
+/// [Lab {n}]
+/// count = 20
+/// bind = 127.0.1.1
+/// port = 4352
+/// vary = address
+/// ```
+///
+/// The settings are `count`, `bind` (127.0.0.1 unless set, use 0.0.0.0 to be reachable
+/// from other machines), `port` (0 for any free port), `vary` (`port` or
+/// `address`, which consecutive devices of a group differ in), `name` (defaults to the
+/// section name), `class`, `manufacturer`, `product`, `info`, `serial`, `password`,
+/// `lamps` (the number of lamps), `lamp_hours`, `filter_hours`, `warmup` and `cooling` in
+/// seconds, `power` (`on` or `off` at startup), `faults` (see
+/// [FaultProfile::parse](struct.FaultProfile.html#method.parse)), `seed`, and for the
+/// class 2 UDP side `udp` (`on` to answer searches on the same address and port),
+/// `controller` (the address notifications go to) and `mac`.
+///
+/// Binding to loopback addresses other than 127.0.0.1 works out of the box on Linux;
+/// other systems need the addresses added to the loopback interface first.
+pub struct Farm {
+    devices: Vec<FarmDevice>,
+}
+
+impl Farm {
+    /// Load a config file and start every device in it
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Farm, Error> {
+        Farm::from_sections(config::load(path)?)
+    }
+
+    /// Start every device described by the text of a config file
+    ///
+    /// ```
+    /// use pjlink::server::Farm;
+    /// use pjlink::PjlinkDevice;
+    ///
+    /// let farm = Farm::start("
+    ///     bind = 127.0.0.1
+    ///     port = 0
+    ///
+    ///     [Room {n}]
+    ///     count = 3
+    ///     class = 2
+    ///     lamp_hours = 100..900
+    /// ").unwrap();
+    ///
+    /// for emulated in farm.devices() {
+    ///     let device = PjlinkDevice::builder("127.0.0.1")
+    ///         .port(emulated.local_addr().port())
+    ///         .build()
+    ///         .unwrap();
+    ///     assert_eq!(device.get_device_name().unwrap(), emulated.name());
+    /// }
+    /// ```
+    pub fn start(config: &str) -> Result<Farm, Error> {
+        Farm::from_sections(config::parse(config)?)
+    }
+
+    fn from_sections(sections: Vec<Section>) -> Result<Farm, Error> {
+        let defaults = &sections[0];
+        for section in &sections {
+            for entry in &section.entries {
+                if !KEYS.iter().any(|key| entry.is(key)) {
+                    return Err(entry.invalid(&format!("unknown setting {}", entry.key)));
+                }
+            }
+        }
+
+        let seed: u64 = match defaults.get("seed") {
+            Some(entry) => entry.parse()?,
+            None => 0,
+        };
+
+        let mut devices = Vec::new();
+        for section in &sections[1..] {
+            let settings = Settings { section, defaults };
+            let count: u32 = settings.parse("count", 1)?;
+            let section_seed: u64 = settings.parse("seed", seed)?;
+            for n in 1..=count {
+                let device_seed = section_seed.wrapping_add(devices.len() as u64);
+                let device = settings
+                    .device(n, count, device_seed)
+                    .map_err(|err| Error::new(err.kind(), format!("[{}] {}", section.name, err)))?;
+                devices.push(device);
+            }
+        }
+
+        if devices.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The farm config does not describe any devices.",
+            ));
+        }
+        Ok(Farm { devices })
+    }
+
+    /// Every device in the order of the config file
+    pub fn devices(&self) -> &[FarmDevice] {
+        &self.devices
+    }
+
+    /// The device with this name
+    pub fn device(&self, name: &str) -> Option<&FarmDevice> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+
+    /// Stop every device
+    pub fn stop(self) {
+        for device in self.devices {
+            device.handle.stop();
+            if let Some(udp) = device.udp {
+                udp.stop();
+            }
+        }
+    }
+}
+
+// The settings of one section, falling back to the ones before the first section
+struct Settings<'a> {
+    section: &'a Section,
+    defaults: &'a Section,
+}
+
+impl<'a> Settings<'a> {
+    fn get(&self, key: &str) -> Option<&'a Entry> {
+        self.section.get(key).or_else(|| self.defaults.get(key))
+    }
+
+    fn parse<T: FromStr>(&self, key: &str, default: T) -> Result<T, Error> {
+        match self.get(key) {
+            Some(entry) => entry.parse(),
+            None => Ok(default),
+        }
+    }
+
+    // A text setting with {n} replaced
+    fn text(&self, key: &str, n: u32) -> Option<String> {
+        self.get(key)
+            .map(|entry| entry.value.replace("{n}", &n.to_string()))
+    }
+
+    // A number that may be a low..high range
+    fn number(&self, key: &str, n: u32, rng: &mut Rng, default: u64) -> Result<u64, Error> {
+        let entry = match self.get(key) {
+            Some(entry) => entry,
+            None => return Ok(default),
+        };
+        let value = entry.value.replace("{n}", &n.to_string());
+        let invalid = || entry.invalid(&format!("invalid value for {}", key));
+        match value.find("..") {
+            Some(dots) => {
+                let low: u64 = value[..dots].trim().parse().map_err(|_| invalid())?;
+                let high: u64 = value[dots + 2..].trim().parse().map_err(|_| invalid())?;
+                if high < low {
+                    return Err(invalid());
+                }
+                Ok(low + rng.next_u64() % (high - low + 1))
+            }
+            None => value.trim().parse().map_err(|_| invalid()),
+        }
+    }
+
+    fn device(&self, n: u32, count: u32, seed: u64) -> Result<FarmDevice, Error> {
+        let mut rng = Rng::new(seed);
+
+        let mut name = self
+            .section
+            .get("name")
+            .map_or(self.section.name.clone(), |entry| entry.value.clone());
+        if count > 1 && !name.contains("{n}") {
+            name.push_str(" {n}");
+        }
+        let name = name.replace("{n}", &n.to_string());
+
+        let mut config = EmulatorConfig {
+            name: name.clone(),
+            serial_number: format!("{:010}", seed),
+            mac_address: format!(
+                "02:00:00:{:02x}:{:02x}:{:02x}",
+                (seed >> 16) as u8,
+                (seed >> 8) as u8,
+                seed as u8
+            ),
+            ..EmulatorConfig::default()
+        };
+        config.class = match self.number("class", n, &mut rng, 1)? {
+            class @ 1..=2 => class as u8,
+            _ => return Err(config::invalid(self.section.line, "class must be 1 or 2")),
+        };
+        if let Some(value) = self.text("manufacturer", n) {
+            config.manufacturer = value;
+        }
+        if let Some(value) = self.text("product", n) {
+            config.product_name = value;
+        }
+        if let Some(value) = self.text("info", n) {
+            config.info = value;
+        }
+        if let Some(value) = self.text("serial", n) {
+            config.serial_number = value;
+        }
+        if let Some(value) = self.text("mac", n) {
+            config.mac_address = value;
+        }
+
+        let lamps = self.number("lamps", n, &mut rng, 1)?;
+        let hours = self.number("lamp_hours", n, &mut rng, 0)?;
+        config.lamps = (0..lamps)
+            .map(|_| Lamp {
+                hours: hours.min(u64::from(u16::MAX)) as u16,
+                on: false,
+            })
+            .collect();
+        config.filter_hours = self.number("filter_hours", n, &mut rng, 0)? as u32;
+        config.warmup = Duration::from_secs(self.number("warmup", n, &mut rng, 10)?);
+        config.cooling = Duration::from_secs(self.number("cooling", n, &mut rng, 10)?);
+        if let Some(entry) = self.get("power") {
+            config.power = match entry.value.as_str() {
+                "on" => PowerStatus::On,
+                "off" => PowerStatus::Off,
+                _ => return Err(entry.invalid("power must be on or off")),
+            };
+        }
+
+        let addr = self.address(n)?;
+        let emulator = Emulator::new(config);
+        let mut server = Server::bind(addr, emulator.clone())
+            .map_err(|err| Error::new(err.kind(), format!("{} on {}: {}", name, addr, err)))?;
+        if let Some(password) = self.text("password", n) {
+            server = server.password(password)?;
+        }
+        if let Some(faults) = self.text("faults", n) {
+            server = server.faults(FaultProfile::parse(seed, &faults)?);
+        }
+
+        let handle = server.spawn()?;
+
+        let udp = match self.get("udp").map(|entry| (entry, entry.value.as_str())) {
+            None | Some((_, "off")) => None,
+            Some((_, "on")) => {
+                let mut udp = UdpServer::bind(handle.local_addr(), emulator.clone())?;
+                if let Some(entry) = self.get("controller") {
+                    udp = udp.controller(entry.parse()?);
+                }
+                Some(udp.spawn()?)
+            }
+            Some((entry, _)) => return Err(entry.invalid("udp must be on or off")),
+        };
+
+        Ok(FarmDevice {
+            name,
+            emulator,
+            handle,
+            udp,
+        })
+    }
+
+    // Where device n of the group listens
+    fn address(&self, n: u32) -> Result<SocketAddr, Error> {
+        let offset = n - 1;
+        let ip: IpAddr = self.parse("bind", IpAddr::V4(Ipv4Addr::LOCALHOST))?;
+        let port: u16 = self.parse("port", PORT)?;
+
+        match self.get("vary").map(|entry| (entry, entry.value.as_str())) {
+            None | Some((_, "port")) => {
+                if port == 0 {
+                    return Ok(SocketAddr::new(ip, 0));
+                }
+                if u32::from(port) + offset > u32::from(u16::MAX) {
+                    return Err(config::invalid(
+                        self.section.line,
+                        "not enough ports for count",
+                    ));
+                }
+                Ok(SocketAddr::new(ip, port + offset as u16))
+            }
+            Some((_, "address")) => {
+                let ip = match ip {
+                    IpAddr::V4(ip) => u32::from(ip)
+                        .checked_add(offset)
+                        .map(|ip| IpAddr::V4(Ipv4Addr::from(ip))),
+                    IpAddr::V6(ip) => u128::from(ip)
+                        .checked_add(u128::from(offset))
+                        .map(|ip| IpAddr::V6(Ipv6Addr::from(ip))),
+                };
+                match ip {
+                    Some(ip) => Ok(SocketAddr::new(ip, port)),
+                    None => Err(config::invalid(
+                        self.section.line,
+                        "not enough addresses for count",
+                    )),
+                }
+            }
+            Some((entry, _)) => Err(entry.invalid("vary must be port or address")),
+        }
+    }
+}
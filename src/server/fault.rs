@@ -0,0 +1,275 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rng::Rng;
+
+/// A misbehavior the server can inject in place of a normal reply
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Send the reply in several TCP segments with a pause between them
+    SplitReply,
+    /// Wait before sending the reply
+    Delay(Duration),
+    /// Send part of the reply and close the connection
+    CloseMidReply,
+    /// Answer PJLINK ERRA to the first command even if the password is right
+    RejectAuth,
+    /// Answer ERR3 to every command while the device is warming up or cooling down.
+    /// At other times the rule does not fire and the rules after it are checked.
+    BusyWhileTransitioning,
+    /// Answer ERR4
+    DeviceFailure,
+    /// Answer with random bytes
+    Garbage,
+}
+
+/// When to inject a [Fault](enum.Fault.html)
+///
+/// A rule matches every command unless it is limited to one, and fires with the
+/// given probability, optionally only a limited number of times.
+///
+/// Rules can also be parsed from `[COMMAND:]fault[@probability][*times]` where fault is
+/// one of `split`, `delay=<milliseconds>`, `close`, `erra`, `busy`, `err4` or `garbage`.
+///
+/// ```
+/// use pjlink::server::FaultRule;
+///
+/// // ERR3 on half of the INPT commands during warmup and cooling, at most 3 times
+/// let rule: FaultRule = "INPT:busy@0.5*3".parse().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct FaultRule {
+    command: Option<String>,
+    fault: Fault,
+    probability: f64,
+    times: Option<u32>,
+}
+
+impl FaultRule {
+    /// A rule that injects the fault on every command
+    pub fn new(fault: Fault) -> FaultRule {
+        FaultRule {
+            command: None,
+            fault,
+            probability: 1.0,
+            times: None,
+        }
+    }
+
+    /// Only inject the fault on this command, e.g. "POWR"
+    pub fn command(mut self, command: &str) -> FaultRule {
+        self.command = Some(command.to_uppercase());
+        self
+    }
+
+    /// Inject the fault with this probability between 0 and 1
+    pub fn probability(mut self, probability: f64) -> FaultRule {
+        self.probability = probability;
+        self
+    }
+
+    /// Stop injecting the fault after it has fired this many times
+    pub fn times(mut self, times: u32) -> FaultRule {
+        self.times = Some(times);
+        self
+    }
+}
+
+impl FromStr for FaultRule {
+    type Err = Error;
+
+    fn from_str(rule: &str) -> Result<FaultRule, Error> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid fault rule: {}", rule),
+            )
+        };
+
+        let (command, rest) = match rule.find(':') {
+            Some(i) => (Some(&rule[..i]), &rule[i + 1..]),
+            None => (None, rule),
+        };
+        let (rest, times) = match rest.find('*') {
+            Some(i) => (
+                &rest[..i],
+                Some(rest[i + 1..].parse::<u32>().map_err(|_| invalid())?),
+            ),
+            None => (rest, None),
+        };
+        let (name, probability) = match rest.find('@') {
+            Some(i) => (
+                &rest[..i],
+                rest[i + 1..].parse::<f64>().map_err(|_| invalid())?,
+            ),
+            None => (rest, 1.0),
+        };
+
+        let fault = match name.trim() {
+            "split" => Fault::SplitReply,
+            "close" => Fault::CloseMidReply,
+            "erra" => Fault::RejectAuth,
+            "busy" => Fault::BusyWhileTransitioning,
+            "err4" => Fault::DeviceFailure,
+            "garbage" => Fault::Garbage,
+            delay if delay.starts_with("delay=") => Fault::Delay(Duration::from_millis(
+                delay[6..].parse::<u64>().map_err(|_| invalid())?,
+            )),
+            _ => return Err(invalid()),
+        };
+
+        let mut fault_rule = FaultRule::new(fault).probability(probability);
+        if let Some(command) = command {
+            fault_rule = fault_rule.command(command.trim());
+        }
+        if let Some(times) = times {
+            fault_rule = fault_rule.times(times);
+        }
+        Ok(fault_rule)
+    }
+}
+
+/// A set of fault rules and the seed that makes them repeatable.
+///
+/// Rules are checked in order and the first one that fires is used. Every connection
+/// draws from its own generator, seeded from the seed and the order the connection was
+/// accepted in, so with the same seed and the same commands on each connection the same
+/// faults are injected every time, however the connections interleave.
+///
+/// ```
+/// use std::time::Duration;
+/// use pjlink::server::{Fault, FaultProfile, FaultRule};
+///
+/// let profile = FaultProfile::new(42)
+///     .rule(FaultRule::new(Fault::BusyWhileTransitioning))
+///     .rule(FaultRule::new(Fault::Delay(Duration::from_millis(200))).command("LAMP"))
+///     .rule(FaultRule::new(Fault::DeviceFailure).probability(0.01));
+///
+/// let same = FaultProfile::parse(42, "busy, LAMP:delay=200, err4@0.01").unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct FaultProfile {
+    seed: u64,
+    rules: Vec<FaultRule>,
+}
+
+impl FaultProfile {
+    /// An empty profile
+    pub fn new(seed: u64) -> FaultProfile {
+        FaultProfile {
+            seed,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Parse a comma separated list of rules, see [FaultRule](struct.FaultRule.html)
+    pub fn parse(seed: u64, rules: &str) -> Result<FaultProfile, Error> {
+        let mut profile = FaultProfile::new(seed);
+        for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            profile = profile.rule(rule.parse()?);
+        }
+        Ok(profile)
+    }
+
+    /// Add a rule
+    pub fn rule(mut self, rule: FaultRule) -> FaultProfile {
+        self.rules.push(rule);
+        self
+    }
+}
+
+// The server side state of a fault profile.
+// The rules, and how often they may still fire, are shared by all connections.
+pub(crate) struct FaultInjector {
+    seed: u64,
+    rules: Mutex<Vec<FaultRule>>,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(profile: FaultProfile) -> FaultInjector {
+        FaultInjector {
+            seed: profile.seed,
+            rules: Mutex::new(profile.rules),
+        }
+    }
+
+    // The generator for the connection accepted as number `index`
+    pub(crate) fn connection_rng(&self, index: u64) -> Rng {
+        Rng::new(self.seed ^ index.wrapping_mul(0xbf58_476d_1ce4_e5b9))
+    }
+
+    // Should the first command on a connection be rejected with ERRA
+    pub(crate) fn reject_auth(&self, rng: &mut Rng, command: &str) -> bool {
+        self.pick(rng, command, true, &|| false).is_some()
+    }
+
+    // The fault to inject in the reply to this command, if any.
+    // A busy rule only fires while the device is warming up or cooling down,
+    // otherwise the rules after it get their turn.
+    pub(crate) fn reply_fault(
+        &self,
+        rng: &mut Rng,
+        command: &str,
+        transitioning: &dyn Fn() -> bool,
+    ) -> Option<Fault> {
+        self.pick(rng, command, false, transitioning)
+    }
+
+    fn pick(
+        &self,
+        rng: &mut Rng,
+        command: &str,
+        auth: bool,
+        transitioning: &dyn Fn() -> bool,
+    ) -> Option<Fault> {
+        let mut rules = self.rules.lock().unwrap();
+
+        for rule in rules.iter_mut() {
+            if (rule.fault == Fault::RejectAuth) != auth {
+                continue;
+            }
+            if rule.command.as_ref().is_some_and(|c| c != command) {
+                continue;
+            }
+            if rule.times == Some(0) {
+                continue;
+            }
+            // Always draw so the sequence only depends on the commands seen
+            let fires = rng.next_f64() < rule.probability;
+            if fires && (rule.fault != Fault::BusyWhileTransitioning || transitioning()) {
+                if let Some(ref mut times) = rule.times {
+                    *times -= 1;
+                }
+                return Some(rule.fault);
+            }
+        }
+        None
+    }
+
+    // Random bytes for a garbage reply, never containing the '\r' terminator
+    pub(crate) fn garbage(rng: &mut Rng) -> Vec<u8> {
+        let len = 1 + (rng.next_u64() % 32) as usize;
+        (0..len)
+            .map(|_| match rng.next_u64() as u8 {
+                b'\r' => b'?',
+                byte => byte,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,566 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The device side of PJLink.
+//!
+//! A [Server](struct.Server.html) accepts PJLink connections, handles the greeting,
+//! MD5 authentication and framing, and answers commands from an
+//! [Emulator](struct.Emulator.html). It is meant for testing code that uses
+//! [PjlinkDevice](../struct.PjlinkDevice.html) without a projector on the bench,
+//! or, with a [PjlinkBackend](trait.PjlinkBackend.html) of your own, for giving
+//! equipment without PJLink support a PJLink front end:
+//!
+//! ```
+//! use std::time::Duration;
+//! use pjlink::server::{Emulator, EmulatorConfig, Server};
+//! use pjlink::{InputType, PjlinkDevice, PowerStatus};
+//!
+//! let emulator = Emulator::new(EmulatorConfig {
+//!     warmup: Duration::from_millis(100),
+//!     ..EmulatorConfig::default()
+//! });
+//! let server = Server::bind("127.0.0.1:0", emulator.clone())
+//!     .unwrap()
+//!     .password("JBMIAProjectorLink")
+//!     .unwrap()
+//!     .spawn()
+//!     .unwrap();
+//!
+//! let device = PjlinkDevice::builder("127.0.0.1")
+//!     .port(server.local_addr().port())
+//!     .password("JBMIAProjectorLink")
+//!     .build()
+//!     .unwrap();
+//!
+//! let status = device.power_on_and_wait(Duration::from_secs(5)).unwrap();
+//! assert_eq!(status, PowerStatus::On);
+//! assert_eq!(device.set_input(InputType::Digital(1)).unwrap(), InputType::Digital(1));
+//! assert_eq!(emulator.input(), InputType::Digital(1));
+//!
+//! server.stop();
+//! ```
+
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use md5;
+
+use rng::Rng;
+use {
+    DeviceProfile, ErrorStatus, ErrorType, InputType, IntoPassword, Password, PjlinkErrorCode,
+    PowerStatus,
+};
+
+mod backend;
+mod emulator;
+mod farm;
+mod fault;
+mod udp;
+
+pub use self::backend::PjlinkBackend;
+pub use self::emulator::{Emulator, EmulatorConfig};
+pub use self::farm::{Farm, FarmDevice};
+use self::fault::FaultInjector;
+pub use self::fault::{Fault, FaultProfile, FaultRule};
+pub use self::udp::UdpServer;
+
+// Devices drop connections that have been idle this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The longest line the specification allows, including the digest
+const MAX_LINE: usize = 32 + 136;
+
+// Pause between the pieces of a split reply
+const SPLIT_PAUSE: Duration = Duration::from_millis(50);
+
+/// A PJLink server listening on a TCP port
+pub struct Server {
+    listener: TcpListener,
+    shared: Shared,
+}
+
+// Everything a connection needs
+struct Shared {
+    device: Box<dyn PjlinkBackend>,
+    password: Option<Password>,
+    faults: FaultInjector,
+    profile: Option<DeviceProfile>,
+}
+
+impl Server {
+    /// Listen on the given address, "0.0.0.0:4352" for the standard port or
+    /// "127.0.0.1:0" for a free port on the loopback interface, and answer
+    /// commands from an [Emulator](struct.Emulator.html) or any other
+    /// [PjlinkBackend](trait.PjlinkBackend.html).
+    pub fn bind<A: ToSocketAddrs, B: PjlinkBackend + 'static>(
+        addr: A,
+        backend: B,
+    ) -> Result<Server, Error> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            shared: Shared {
+                device: Box::new(backend),
+                password: None,
+                faults: FaultInjector::new(FaultProfile::new(0)),
+                profile: None,
+            },
+        })
+    }
+
+    /// Require MD5 authentication with this password, an empty string turns it off
+    pub fn password<P: IntoPassword>(mut self, password: P) -> Result<Server, Error> {
+        self.shared.password = password.into_password()?;
+        Ok(self)
+    }
+
+    /// Misbehave according to a fault profile
+    ///
+    /// ```
+    /// use pjlink::server::{Emulator, EmulatorConfig, FaultProfile, Server};
+    /// use pjlink::{PjlinkDevice, PjlinkErrorCode, RetryPolicy};
+    ///
+    /// let server = Server::bind("127.0.0.1:0", Emulator::new(EmulatorConfig::default()))
+    ///     .unwrap()
+    ///     .faults(FaultProfile::parse(7, "POWR:err4*1, split").unwrap())
+    ///     .spawn()
+    ///     .unwrap();
+    /// let device = PjlinkDevice::builder("127.0.0.1")
+    ///     .port(server.local_addr().port())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let err = device.get_power_status().unwrap_err();
+    /// assert_eq!(PjlinkErrorCode::from_error(&err), Some(PjlinkErrorCode::DeviceFailure));
+    /// // The next reply arrives in pieces
+    /// assert!(device.get_power_status().is_ok());
+    /// ```
+    pub fn faults(mut self, profile: FaultProfile) -> Server {
+        self.shared.faults = FaultInjector::new(profile);
+        self
+    }
+
+    /// Answer the requests recorded in a profile exactly like the captured device did.
+    /// Other requests, and every command that changes state, are handled by the emulator,
+    /// which can be set up to match with
+    /// [EmulatorConfig::from_profile](struct.EmulatorConfig.html#method.from_profile).
+    pub fn replay(mut self, profile: DeviceProfile) -> Server {
+        self.shared.profile = Some(profile);
+        self
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr()
+    }
+
+    /// Serve connections on the current thread until an error occurs
+    pub fn run(self) -> Result<(), Error> {
+        self.serve(&AtomicBool::new(false))
+    }
+
+    /// Serve connections on a background thread
+    pub fn spawn(self) -> Result<ServerHandle, Error> {
+        let addr = self.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let _ = self.serve(&thread_stop);
+        });
+
+        Ok(ServerHandle {
+            addr,
+            stop,
+            thread: Some(thread),
+            wake: true,
+        })
+    }
+
+    fn serve(self, stop: &AtomicBool) -> Result<(), Error> {
+        let shared = Arc::new(self.shared);
+        // Connections are numbered in the order they are accepted to seed their faults
+        for (index, stream) in self.listener.incoming().enumerate() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let shared = shared.clone();
+            let rng = shared.faults.connection_rng(index as u64);
+            thread::spawn(move || {
+                let _ = handle_connection(stream, &shared, rng);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Controls a [Server](struct.Server.html) running on a background thread.
+/// The server stops when the handle is dropped.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // Whether the thread blocks in accept and needs a connection to see the stop flag
+    wake: bool,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections and wait for the server thread to finish.
+    /// Connections that are already open are served until the client closes them.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            // Wake up the accept loop so it sees the stop flag
+            if self.wake {
+                let _ = TcpStream::connect(loopback(self.addr));
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// The address to reach a socket bound to an unspecified address from this host
+fn loopback(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    addr
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared, mut rng: Rng) -> Result<(), Error> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    // The digest the client has to send with its first command
+    let mut expected_digest = match shared.password {
+        Some(ref password) => {
+            let random = format!("{:08x}", Rng::from_time().next_u64() as u32);
+            writer.write_all(format!("PJLINK 1 {}\r", random).as_bytes())?;
+            let mut context = md5::Context::new();
+            context.consume(random.as_bytes());
+            context.consume(password.expose());
+            Some(format!("{:x}", context.compute()))
+        }
+        None => {
+            writer.write_all(b"PJLINK 0\r")?;
+            None
+        }
+    };
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader
+            .by_ref()
+            .take(MAX_LINE as u64)
+            .read_until(b'\r', &mut line)?
+            == 0
+        {
+            return Ok(());
+        }
+        // The rest of a line that is too long is thrown away rather than read as a new request
+        let too_long = line.len() == MAX_LINE && line.last() != Some(&b'\r');
+        if too_long {
+            let mut rest = Vec::new();
+            while reader
+                .by_ref()
+                .take(MAX_LINE as u64)
+                .read_until(b'\r', &mut rest)?
+                != 0
+                && rest.last() != Some(&b'\r')
+            {
+                rest.clear();
+            }
+        }
+        let text = String::from_utf8_lossy(&line);
+        let mut request = text.trim_matches(|c| c == '\r' || c == '\n');
+
+        if let Some(digest) = expected_digest.take() {
+            let command = split_request(request.get(digest.len()..).unwrap_or(""))
+                .map_or("", |(_, command, _)| command);
+            if !request.starts_with(&digest) || shared.faults.reject_auth(&mut rng, command) {
+                writer.write_all(b"PJLINK ERRA\r")?;
+                return Ok(());
+            }
+            request = &request[digest.len()..];
+        }
+
+        let (class, command, _) = match split_request(request) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let error_reply =
+            |code: PjlinkErrorCode| format!("%{}{}={}\r", class, command, code.code());
+        if too_long || !has_separator(request) {
+            writer.write_all(error_reply(PjlinkErrorCode::InvalidParameter).as_bytes())?;
+            continue;
+        }
+
+        let transitioning = || {
+            matches!(
+                shared.device.power_status(),
+                Ok(PowerStatus::Warmup) | Ok(PowerStatus::Cooling)
+            )
+        };
+        let reply = match shared.faults.reply_fault(&mut rng, command, &transitioning) {
+            Some(Fault::Delay(delay)) => {
+                thread::sleep(delay);
+                respond(shared, request)
+            }
+            Some(Fault::BusyWhileTransitioning) => error_reply(PjlinkErrorCode::Unavailable),
+            Some(Fault::DeviceFailure) => error_reply(PjlinkErrorCode::DeviceFailure),
+            Some(Fault::Garbage) => {
+                let mut garbage = FaultInjector::garbage(&mut rng);
+                garbage.push(b'\r');
+                writer.write_all(&garbage)?;
+                continue;
+            }
+            Some(Fault::SplitReply) => {
+                let reply = respond(shared, request);
+                for piece in reply.as_bytes().chunks(reply.len() / 3 + 1) {
+                    writer.write_all(piece)?;
+                    writer.flush()?;
+                    thread::sleep(SPLIT_PAUSE);
+                }
+                continue;
+            }
+            Some(Fault::CloseMidReply) => {
+                let reply = respond(shared, request);
+                writer.write_all(&reply.as_bytes()[..reply.len() / 2])?;
+                return Ok(());
+            }
+            Some(Fault::RejectAuth) | None => respond(shared, request),
+        };
+        writer.write_all(reply.as_bytes())?;
+    }
+}
+
+// Split a "%1POWR 1" style request into its class, command and parameter.
+// Requests that are not framed as PJLink commands are ignored, like most devices do.
+fn split_request(request: &str) -> Option<(&str, &str, &str)> {
+    if request.len() < 7 || !request.is_char_boundary(7) || !request.starts_with('%') {
+        return None;
+    }
+    Some((&request[1..2], &request[2..6], &request[7..]))
+}
+
+// The command and its parameter are separated by a single space
+fn has_separator(request: &str) -> bool {
+    request.as_bytes().get(6) == Some(&b' ')
+}
+
+// Build the reply to a single request that split_request accepted,
+// preferring the recorded response when replaying a profile
+fn respond(shared: &Shared, request: &str) -> String {
+    let (class, command, param) = match split_request(request) {
+        Some(parts) => parts,
+        None => return String::new(),
+    };
+    if let Some(response) = shared
+        .profile
+        .as_ref()
+        .and_then(|profile| profile.response(request))
+    {
+        return format!("{}\r", response);
+    }
+    let device = &*shared.device;
+
+    let result = if class != "1" && (class != "2" || device.class() < 2) {
+        Err(PjlinkErrorCode::UndefinedCommand)
+    } else {
+        answer(device, class == "2", command, param)
+    };
+
+    let value = match result {
+        Ok(value) => value,
+        Err(code) => code.code().to_string(),
+    };
+    format!("%{}{}={}\r", class, command, value)
+}
+
+fn answer(
+    device: &dyn PjlinkBackend,
+    class2: bool,
+    command: &str,
+    param: &str,
+) -> Result<String, PjlinkErrorCode> {
+    let query = param == "?";
+    let ok = || Ok(String::from("OK"));
+
+    match command {
+        "POWR" => match param {
+            "?" => device
+                .power_status()
+                .map(|power| power_code(power).to_string()),
+            "1" => device.set_power(true).and_then(|_| ok()),
+            "0" => device.set_power(false).and_then(|_| ok()),
+            _ => Err(PjlinkErrorCode::InvalidParameter),
+        },
+        "INPT" if query => device.input().map(|input| input.code().to_string()),
+        "INPT" => match param.parse::<u8>().ok().and_then(InputType::from_code) {
+            Some(input) if device.inputs()?.contains(&input) => {
+                device.set_input(input).and_then(|_| ok())
+            }
+            _ => Err(PjlinkErrorCode::InvalidParameter),
+        },
+        "AVMT" if query => device.avmute().map(|mute| mute.code().to_string()),
+        "AVMT" => {
+            let (video, audio) = match param {
+                "10" => (Some(false), None),
+                "11" => (Some(true), None),
+                "20" => (None, Some(false)),
+                "21" => (None, Some(true)),
+                "30" => (Some(false), Some(false)),
+                "31" => (Some(true), Some(true)),
+                _ => return Err(PjlinkErrorCode::InvalidParameter),
+            };
+            device.set_avmute(video, audio).and_then(|_| ok())
+        }
+        "FREZ" if class2 && query => device
+            .freeze()
+            .map(|freeze| if freeze { "1" } else { "0" }.to_string()),
+        "FREZ" if class2 => match param {
+            "1" => device.set_freeze(true).and_then(|_| ok()),
+            "0" => device.set_freeze(false).and_then(|_| ok()),
+            _ => Err(PjlinkErrorCode::InvalidParameter),
+        },
+        "INNM" if class2 => {
+            let input = param
+                .strip_prefix('?')
+                .and_then(|code| code.parse::<u8>().ok())
+                .and_then(InputType::from_code);
+            match input {
+                Some(input) if device.inputs()?.contains(&input) => device.input_name(input),
+                _ => Err(PjlinkErrorCode::InvalidParameter),
+            }
+        }
+        _ if !query => {
+            if is_query_command(command, class2) {
+                Err(PjlinkErrorCode::InvalidParameter)
+            } else {
+                Err(PjlinkErrorCode::UndefinedCommand)
+            }
+        }
+        _ => query_answer(device, class2, command),
+    }
+}
+
+// Commands that only take "?" as a parameter
+fn is_query_command(command: &str, class2: bool) -> bool {
+    match command {
+        "ERST" | "LAMP" | "INST" | "NAME" | "INF1" | "INF2" | "INFO" | "CLSS" => true,
+        "SNUM" | "SVER" | "IRES" | "RRES" | "FILT" | "RLMP" | "RFIL" => class2,
+        _ => false,
+    }
+}
+
+fn query_answer(
+    device: &dyn PjlinkBackend,
+    class2: bool,
+    command: &str,
+) -> Result<String, PjlinkErrorCode> {
+    match command {
+        "ERST" => device.error_status().map(error_status_code),
+        "LAMP" => Ok(device
+            .lamps()?
+            .iter()
+            .map(|lamp| format!("{} {}", lamp.hours, if lamp.on { 1 } else { 0 }))
+            .collect::<Vec<_>>()
+            .join(" ")),
+        "INST" => Ok(device
+            .inputs()?
+            .iter()
+            .map(|input| input.code().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")),
+        "NAME" => device.name(),
+        "INF1" => device.manufacturer(),
+        "INF2" => device.product_name(),
+        "INFO" => device.info(),
+        "CLSS" => Ok(device.class().to_string()),
+        "SNUM" if class2 => device.serial_number(),
+        "SVER" if class2 => device.software_version(),
+        "IRES" if class2 => device.input_resolution(),
+        "RRES" if class2 => device.recommended_resolution(),
+        "FILT" if class2 => device.filter_hours().map(|hours| hours.to_string()),
+        "RLMP" if class2 => device.lamp_model(),
+        "RFIL" if class2 => device.filter_model(),
+        _ => Err(PjlinkErrorCode::UndefinedCommand),
+    }
+}
+
+// The six digits of an ERST reply
+fn error_status_code(status: ErrorStatus) -> String {
+    [
+        status.fan_error,
+        status.lamp_error,
+        status.temperature_error,
+        status.cover_open_error,
+        status.filter_error,
+        status.other_error,
+    ]
+    .iter()
+    .map(|error| match *error {
+        ErrorType::NoError => '0',
+        ErrorType::Warning => '1',
+        ErrorType::Error => '2',
+    })
+    .collect()
+}
+
+fn power_code(power: PowerStatus) -> char {
+    match power {
+        PowerStatus::Off => '0',
+        PowerStatus::On => '1',
+        PowerStatus::Cooling => '2',
+        PowerStatus::Warmup => '3',
+    }
+}
+
+fn input_name(input: InputType) -> String {
+    match input {
+        InputType::RGB(n) => format!("RGB {}", n),
+        InputType::Video(n) => format!("Video {}", n),
+        InputType::Digital(n) => format!("Digital {}", n),
+        InputType::Storage(n) => format!("Storage {}", n),
+        InputType::Network(n) => format!("Network {}", n),
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use {AvMute, ErrorStatus, InputType, PowerStatus, PORT};
+
+use super::{error_status_code, Emulator, ServerHandle, MAX_LINE};
+
+// How often the emulator is checked for changes to notify
+const NOTIFY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The class 2 UDP side of an emulated device.
+///
+/// It answers `%2SRCH` search requests with `%2ACKN=<MAC>` to the address the search
+/// came from, sends `%2LKUP=<MAC>` once at startup, and sends `%2POWR`, `%2INPT`,
+/// `%2AVMT` and `%2ERST` notifications to the controller whenever the emulator reaches
+/// On or Off, or its input, AV mute or error status changes. A class 1 device stays silent.
+///
+/// ```
+/// use std::net::UdpSocket;
+/// use pjlink::server::{Emulator, EmulatorConfig, UdpServer};
+/// use pjlink::PowerStatus;
+///
+/// let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let emulator = Emulator::new(EmulatorConfig {
+///     class: 2,
+///     ..EmulatorConfig::default()
+/// });
+/// let server = UdpServer::bind("127.0.0.1:0", emulator.clone())
+///     .unwrap()
+///     .controller(controller.local_addr().unwrap())
+///     .spawn()
+///     .unwrap();
+///
+/// let mut buf = [0; 64];
+/// let (len, _) = controller.recv_from(&mut buf).unwrap();
+/// assert_eq!(&buf[..len], b"%2LKUP=00:00:5e:00:53:01\r");
+///
+/// controller.send_to(b"%2SRCH\r", server.local_addr()).unwrap();
+/// let (len, _) = controller.recv_from(&mut buf).unwrap();
+/// assert_eq!(&buf[..len], b"%2ACKN=00:00:5e:00:53:01\r");
+///
+/// emulator.set_power_status(PowerStatus::On);
+/// let (len, _) = controller.recv_from(&mut buf).unwrap();
+/// assert_eq!(&buf[..len], b"%2POWR=1\r");
+/// ```
+pub struct UdpServer {
+    socket: UdpSocket,
+    device: Emulator,
+    controller: Option<SocketAddr>,
+    lookup: Option<SocketAddr>,
+}
+
+impl UdpServer {
+    /// Listen on the given address, "0.0.0.0:4352" for the standard port
+    pub fn bind<A: ToSocketAddrs>(addr: A, device: Emulator) -> Result<UdpServer, Error> {
+        Ok(UdpServer {
+            socket: UdpSocket::bind(addr)?,
+            device,
+            controller: None,
+            lookup: None,
+        })
+    }
+
+    /// Send notifications, and the LKUP announcement, to this address
+    pub fn controller(mut self, addr: SocketAddr) -> UdpServer {
+        self.controller = Some(addr);
+        self
+    }
+
+    /// Send the LKUP announcement to this address instead. Without it LKUP goes to
+    /// the controller, or is broadcast on the PJLink port when there is none.
+    pub fn lookup(mut self, addr: SocketAddr) -> UdpServer {
+        self.lookup = Some(addr);
+        self
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr()
+    }
+
+    /// Serve requests and send notifications on the current thread until an error occurs
+    pub fn run(self) -> Result<(), Error> {
+        self.serve(&AtomicBool::new(false))
+    }
+
+    /// Serve requests and send notifications on a background thread
+    pub fn spawn(self) -> Result<ServerHandle, Error> {
+        let addr = self.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let _ = self.serve(&thread_stop);
+        });
+
+        Ok(ServerHandle {
+            addr,
+            stop,
+            thread: Some(thread),
+            wake: false,
+        })
+    }
+
+    fn serve(self, stop: &AtomicBool) -> Result<(), Error> {
+        self.socket.set_read_timeout(Some(NOTIFY_INTERVAL))?;
+        let class2 = self.device.class() >= 2;
+        let mac = self.device.config().mac_address;
+
+        if class2 {
+            let lookup = self.lookup.or(self.controller).unwrap_or_else(|| {
+                let _ = self.socket.set_broadcast(true);
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, PORT))
+            });
+            let _ = self
+                .socket
+                .send_to(format!("%2LKUP={}\r", mac).as_bytes(), lookup);
+        }
+
+        let mut last = Snapshot::take(&self.device);
+        let mut buf = [0; MAX_LINE];
+        while !stop.load(Ordering::SeqCst) {
+            // A read error is the timeout, or an ICMP error from an earlier send
+            if let Ok((len, from)) = self.socket.recv_from(&mut buf) {
+                let request = String::from_utf8_lossy(&buf[..len]);
+                if class2 && request.trim_end_matches(['\r', '\n']) == "%2SRCH" {
+                    let _ = self
+                        .socket
+                        .send_to(format!("%2ACKN={}\r", mac).as_bytes(), from);
+                }
+            }
+
+            let now = Snapshot::take(&self.device);
+            if let (true, Some(controller)) = (class2, self.controller) {
+                for notification in last.changes(&now) {
+                    let _ = self.socket.send_to(notification.as_bytes(), controller);
+                }
+            }
+            last = now;
+        }
+        Ok(())
+    }
+}
+
+// The state that notifications report
+struct Snapshot {
+    power: PowerStatus,
+    input: InputType,
+    mute: AvMute,
+    errors: ErrorStatus,
+}
+
+impl Snapshot {
+    fn take(device: &Emulator) -> Snapshot {
+        Snapshot {
+            power: device.power_status(),
+            input: device.input(),
+            mute: device.avmute(),
+            errors: device.error_status(),
+        }
+    }
+
+    // The notifications for the changes from this snapshot to the next one
+    fn changes(&self, next: &Snapshot) -> Vec<String> {
+        let mut notifications = Vec::new();
+        if next.power != self.power {
+            match next.power {
+                PowerStatus::On => notifications.push(String::from("%2POWR=1\r")),
+                PowerStatus::Off => notifications.push(String::from("%2POWR=0\r")),
+                PowerStatus::Warmup | PowerStatus::Cooling => (),
+            }
+        }
+        if next.input != self.input {
+            notifications.push(format!("%2INPT={}\r", next.input.code()));
+        }
+        if next.mute != self.mute {
+            notifications.push(format!("%2AVMT={}\r", next.mute.code()));
+        }
+        if next.errors != self.errors {
+            notifications.push(format!("%2ERST={}\r", error_status_code(next.errors)));
+        }
+        notifications
+    }
+}
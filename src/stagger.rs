@@ -0,0 +1,140 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {Fleet, PowerStatus};
+
+/// How `Fleet::staggered_power_on` spreads the devices over waves
+#[derive(Clone, Copy, Debug)]
+pub struct Stagger {
+    /// How many devices are turned on in each wave, at least 1
+    pub batch_size: usize,
+    /// The wait between the end of one wave and the start of the next
+    pub delay: Duration,
+    /// Wait for the devices of a wave to finish warming up before the delay starts
+    pub wait_for_warmup: bool,
+    /// How long a device may take to warm up when `wait_for_warmup` is set
+    pub warmup_timeout: Duration,
+}
+
+impl Default for Stagger {
+    fn default() -> Stagger {
+        Stagger {
+            batch_size: 4,
+            delay: Duration::from_secs(5),
+            wait_for_warmup: false,
+            warmup_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// What happened to each device of a group power on, keyed like `FleetResults`
+#[derive(Debug, Default)]
+pub struct GroupPowerReport {
+    /// The devices that accepted the command, or came on when waiting for warmup
+    pub succeeded: Vec<String>,
+    /// The devices that did not, with the reason
+    pub failed: BTreeMap<String, Error>,
+}
+
+impl GroupPowerReport {
+    /// Did every device come up
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl Fleet {
+    /// Turn on every device in waves of `stagger.batch_size` devices so they don't all draw
+    /// their inrush current at once. The devices of a wave are turned on in parallel like
+    /// `run`. The fleet deadline applies to the whole power on, including the delays between
+    /// waves, and devices whose wave would start after it fail with a `TimedOut` error.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{Fleet, PjlinkDevice, Stagger};
+    ///
+    /// let mut fleet = Fleet::new();
+    /// let mut servers = Vec::new();
+    /// for _ in 0..3 {
+    ///     let emulator = Emulator::new(EmulatorConfig {
+    ///         warmup: Duration::from_millis(100),
+    ///         ..EmulatorConfig::default()
+    ///     });
+    ///     let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    ///     let device = PjlinkDevice::builder("127.0.0.1")
+    ///         .port(server.local_addr().port())
+    ///         .build()
+    ///         .unwrap();
+    ///     fleet.add(device);
+    ///     servers.push(server);
+    /// }
+    ///
+    /// let report = fleet.staggered_power_on(Stagger {
+    ///     batch_size: 2,
+    ///     delay: Duration::from_millis(50),
+    ///     wait_for_warmup: true,
+    ///     warmup_timeout: Duration::from_secs(10),
+    /// });
+    /// assert!(report.is_success());
+    /// assert_eq!(report.succeeded.len(), 3);
+    /// ```
+    pub fn staggered_power_on(&self, stagger: Stagger) -> GroupPowerReport {
+        let mut report = GroupPowerReport::default();
+        let deadline = self.deadline_from_now();
+        let waves = self.devices().chunks(stagger.batch_size.max(1));
+        let count = waves.len();
+
+        for (index, wave) in waves.enumerate() {
+            let timeout = stagger.warmup_timeout;
+            let results = if stagger.wait_for_warmup {
+                self.run_devices_until(wave.to_vec(), deadline, move |device| {
+                    device.power_on_and_wait(timeout)
+                })
+            } else {
+                self.run_devices_until(wave.to_vec(), deadline, |device| device.power_on())
+            };
+
+            for (key, result) in results {
+                match result {
+                    Ok(PowerStatus::Off) | Ok(PowerStatus::Cooling) => {
+                        report
+                            .failed
+                            .insert(key, Error::other("The device did not turn on"));
+                    }
+                    Ok(_) => report.succeeded.push(key),
+                    Err(e) => {
+                        report.failed.insert(key, e);
+                    }
+                }
+            }
+
+            if index + 1 < count {
+                let delay = match deadline {
+                    Some(deadline) => stagger
+                        .delay
+                        .min(deadline.saturating_duration_since(Instant::now())),
+                    None => stagger.delay,
+                };
+                thread::sleep(delay);
+            }
+        }
+        report
+    }
+}
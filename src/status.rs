@@ -0,0 +1,169 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+
+use {
+    parse_avmute, parse_error_status, parse_input, parse_lamps, parse_power_status, parse_response,
+    AvMute, ErrorStatus, InputType, Lamp, PjlinkDevice, PjlinkErrorCode, PowerStatus, Session,
+};
+
+/// One field of a [pjlink::DeviceStatus](struct.DeviceStatus.html)
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatusField<T> {
+    /// The device answered
+    Value(T),
+    /// The device does not support the query (ERR1)
+    Unsupported,
+    /// The device can not answer right now (ERR3), e.g. the input while it is in standby
+    Unavailable,
+    /// The query failed, with the reason
+    Failed(String),
+}
+
+impl<T> StatusField<T> {
+    /// The value if the device answered
+    pub fn value(&self) -> Option<&T> {
+        match *self {
+            StatusField::Value(ref value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Everything `get_status_snapshot` found out about a device
+#[derive(Clone, Debug)]
+pub struct DeviceStatus {
+    /// When the snapshot was taken
+    pub timestamp: SystemTime,
+    /// POWR ?
+    pub power: StatusField<PowerStatus>,
+    /// INPT ?
+    pub input: StatusField<InputType>,
+    /// AVMT ?
+    pub avmute: StatusField<AvMute>,
+    /// LAMP ?
+    pub lamps: StatusField<Vec<Lamp>>,
+    /// ERST ?
+    pub error_status: StatusField<ErrorStatus>,
+    /// NAME ?
+    pub name: StatusField<String>,
+    /// INF1 ?
+    pub manufacturer: StatusField<String>,
+    /// INF2 ?
+    pub product_name: StatusField<String>,
+    /// INFO ?
+    pub info: StatusField<String>,
+    /// CLSS ?
+    pub class: StatusField<String>,
+}
+
+impl PjlinkDevice {
+    /// Query power, input, AV mute, lamps, error status and identity over a single
+    /// connection. A query that fails is marked in its field instead of failing the
+    /// whole snapshot; an error is only returned if the device can not be reached or
+    /// rejects the password. The snapshot stops at the first authorization failure so
+    /// that it counts only once towards the lockout.
+    ///
+    /// ```
+    /// use pjlink::server::{Emulator, EmulatorConfig, Server};
+    /// use pjlink::{PjlinkDevice, PowerStatus, StatusField};
+    ///
+    /// let emulator = Emulator::new(EmulatorConfig::default());
+    /// let server = Server::bind("127.0.0.1:0", emulator).unwrap().spawn().unwrap();
+    /// let device = PjlinkDevice::builder("127.0.0.1")
+    ///     .port(server.local_addr().port())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let status = device.get_status_snapshot().unwrap();
+    /// assert_eq!(status.power, StatusField::Value(PowerStatus::Off));
+    /// // There is no input to report while the device is in standby
+    /// assert_eq!(status.input, StatusField::Unavailable);
+    /// ```
+    pub fn get_status_snapshot(&self) -> Result<DeviceStatus, Error> {
+        let timestamp = SystemTime::now();
+        let mut session = Some(self.open_session()?);
+        let extended_inputs = self.quirks().extended_inputs;
+
+        Ok(DeviceStatus {
+            timestamp,
+            power: self.query(&mut session, "POWR ?", parse_power_status)?,
+            input: self.query(&mut session, "INPT ?", |value| {
+                parse_input(value, extended_inputs)
+            })?,
+            avmute: self.query(&mut session, "AVMT ?", parse_avmute)?,
+            lamps: self.query(&mut session, "LAMP ?", parse_lamps)?,
+            error_status: self.query(&mut session, "ERST ?", |value| {
+                Ok(parse_error_status(value))
+            })?,
+            name: self.query(&mut session, "NAME ?", |value| Ok(value.to_string()))?,
+            manufacturer: self.query(&mut session, "INF1 ?", |value| Ok(value.to_string()))?,
+            product_name: self.query(&mut session, "INF2 ?", |value| Ok(value.to_string()))?,
+            info: self.query(&mut session, "INFO ?", |value| Ok(value.to_string()))?,
+            class: self.query(&mut session, "CLSS ?", |value| Ok(value.to_string()))?,
+        })
+    }
+
+    // Send one query of a snapshot, reconnecting if the previous one lost the connection.
+    // Only an authorization failure or a lockout is returned as an error.
+    fn query<'a, T, F>(
+        &'a self,
+        session: &mut Option<Session<'a>>,
+        command: &str,
+        parse: F,
+    ) -> Result<StatusField<T>, Error>
+    where
+        F: Fn(&str) -> Result<T, Error>,
+    {
+        if session.is_none() {
+            match self.open_session() {
+                Ok(opened) => *session = Some(opened),
+                Err(e) => return failed(e),
+            }
+        }
+
+        let response = match session.as_mut().unwrap().send(1, command) {
+            Ok(response) => response,
+            Err(e) => {
+                *session = None;
+                return failed(e);
+            }
+        };
+        if self.quirks().close_after_reply {
+            *session = None;
+        }
+
+        match parse_response(&response).and_then(|result| parse(&result.value)) {
+            Ok(value) => Ok(StatusField::Value(value)),
+            Err(e) => match PjlinkErrorCode::from_error(&e) {
+                Some(PjlinkErrorCode::UndefinedCommand) => Ok(StatusField::Unsupported),
+                Some(PjlinkErrorCode::Unavailable) => Ok(StatusField::Unavailable),
+                _ => failed(e),
+            },
+        }
+    }
+}
+
+// A failed query, or the error ending the snapshot if the password was rejected
+fn failed<T>(e: Error) -> Result<StatusField<T>, Error> {
+    if e.kind() == ErrorKind::PermissionDenied
+        || PjlinkErrorCode::from_error(&e) == Some(PjlinkErrorCode::Authorization)
+    {
+        Err(e)
+    } else {
+        Ok(StatusField::Failed(e.to_string()))
+    }
+}
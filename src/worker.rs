@@ -0,0 +1,76 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long a background thread waits at most before checking if it has been stopped
+pub(crate) const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// A named background thread that runs until the worker is stopped or dropped
+pub(crate) struct Worker {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    // Run `body` on a new thread. It has to return soon after the flag it is given is set.
+    pub(crate) fn spawn<F>(name: String, body: F) -> Result<Worker, Error>
+    where
+        F: FnOnce(&AtomicBool) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(name)
+                .spawn(move || body(&stop))?
+        };
+        Ok(Worker {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    // Run `task` on a new thread right away and then `interval` after each run ends
+    pub(crate) fn every<F>(name: String, interval: Duration, mut task: F) -> Result<Worker, Error>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Worker::spawn(name, move |stop| {
+            let mut next_run = Instant::now();
+            while !stop.load(Ordering::SeqCst) {
+                if Instant::now() >= next_run {
+                    task();
+                    next_run = Instant::now() + interval;
+                }
+                let wait = next_run.saturating_duration_since(Instant::now());
+                thread::sleep(wait.min(STOP_CHECK_INTERVAL));
+            }
+        })
+    }
+}
+
+impl Drop for Worker {
+    // Stop the thread and wait for it to finish
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
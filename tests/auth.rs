@@ -0,0 +1,142 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use common::TestDevice;
+use pjlink::{AuthLockedOut, AuthLockout, Password, PjlinkDevice, PjlinkErrorCode, PowerStatus};
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn with_password(password: &str) -> TestDevice {
+    common::serve(common::config(PowerStatus::On), |server| {
+        server.password(password).unwrap()
+    })
+}
+
+fn locked_out(err: &Error) -> Option<AuthLockedOut> {
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<AuthLockedOut>())
+        .cloned()
+}
+
+#[test]
+fn the_right_password_is_accepted() {
+    let test = with_password("JBMIAProjectorLink");
+    let device = test
+        .builder()
+        .password("JBMIAProjectorLink")
+        .build()
+        .unwrap();
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn a_wrong_password_is_an_authorization_error() {
+    let test = with_password("secret");
+    let device = test.builder().password("wrong").build().unwrap();
+    let err = device.get_power_status().unwrap_err();
+    assert_eq!(
+        PjlinkErrorCode::from_error(&err),
+        Some(PjlinkErrorCode::Authorization)
+    );
+}
+
+#[test]
+fn a_missing_password_is_an_error() {
+    let test = with_password("secret");
+    assert!(test.device.get_power_status().is_err());
+}
+
+#[test]
+fn an_empty_password_means_none() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let device = test.builder().password("").build().unwrap();
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+    assert!(PjlinkDevice::new_with_password("127.0.0.1", "").is_ok());
+}
+
+#[test]
+fn repeated_failures_lock_the_device_out() {
+    let test = with_password("secret");
+    let device = test
+        .builder()
+        .password("wrong")
+        .auth_lockout(AuthLockout {
+            max_failures: 2,
+            cooldown: Duration::from_millis(300),
+        })
+        .build()
+        .unwrap();
+
+    for _ in 0..2 {
+        let err = device.get_power_status().unwrap_err();
+        assert!(locked_out(&err).is_none());
+    }
+
+    // Refused locally without contacting the device
+    let err = device.get_power_status().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(locked_out(&err).unwrap().failures, 2);
+
+    // Tried again once the cooldown is over
+    thread::sleep(Duration::from_millis(350));
+    let err = device.get_power_status().unwrap_err();
+    assert_eq!(
+        PjlinkErrorCode::from_error(&err),
+        Some(PjlinkErrorCode::Authorization)
+    );
+}
+
+#[test]
+fn a_new_password_clears_the_lockout() {
+    let test = with_password("secret");
+    // Shared like the devices of a Fleet or a ManagedDevice
+    let device = Arc::new(test.builder().password("wrong").build().unwrap());
+    for _ in 0..3 {
+        assert!(device.get_power_status().is_err());
+    }
+    assert!(locked_out(&device.get_power_status().unwrap_err()).is_some());
+
+    device.set_password("secret").unwrap();
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn a_password_file_gives_its_first_line() {
+    let path = env::temp_dir().join(format!("pjlink-password-{}", process::id()));
+    let read = |contents: &str| {
+        fs::write(&path, contents).unwrap();
+        Password::from_file(&path)
+    };
+
+    let test = with_password("secret");
+    let password = read("secret\r\nnot the password\n").unwrap();
+    let device = test.builder().password(password).build().unwrap();
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+
+    assert!(read(&"x".repeat(32)).is_ok());
+    assert!(read(&format!("{}\n", "x".repeat(32))).is_ok());
+    assert!(read(&"x".repeat(33)).is_err());
+    assert!(read(&"x".repeat(100)).is_err());
+    assert!(read("\nsecret\n").is_err());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,69 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::{EmulatorConfig, FaultProfile};
+use pjlink::{DeviceProfile, PjlinkErrorCode, PowerStatus};
+
+#[test]
+fn a_failed_command_does_not_stop_the_capture() {
+    let profile = FaultProfile::parse(1, "NAME:close").unwrap();
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.faults(profile)
+    });
+
+    let (profile, failures) = DeviceProfile::capture_partial(&test.device);
+    let failed: Vec<&str> = failures
+        .iter()
+        .map(|(request, _)| request.as_str())
+        .collect();
+    assert_eq!(failed, vec!["%1NAME ?"]);
+    assert!(profile.response("%1NAME ?").is_none());
+    assert!(profile.response("%1LAMP ?").is_some());
+    assert!(profile.response("%1ERST ?").is_some());
+
+    assert!(DeviceProfile::capture(&test.device).is_err());
+}
+
+#[test]
+fn an_authorization_failure_stops_the_capture() {
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.password("secret").unwrap()
+    });
+    let device = test.builder().password("wrong").build().unwrap();
+
+    let (profile, failures) = DeviceProfile::capture_partial(&device);
+    assert_eq!(profile.iter().count(), 0);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(
+        PjlinkErrorCode::from_error(&failures[0].1),
+        Some(PjlinkErrorCode::Authorization)
+    );
+}
+
+#[test]
+fn class_10_gets_the_class_2_queries() {
+    let test = common::spawn(EmulatorConfig {
+        class: 10,
+        ..common::config(PowerStatus::On)
+    });
+
+    let (profile, failures) = DeviceProfile::capture_partial(&test.device);
+    assert!(failures.is_empty());
+    assert_eq!(profile.value("%1CLSS ?"), Some("10"));
+    assert!(profile.value("%2SNUM ?").is_some());
+}
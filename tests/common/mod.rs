@@ -0,0 +1,66 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Helpers shared by the integration tests, which run the client against the emulator.
+
+#![allow(dead_code)]
+
+use pjlink::server::{Emulator, EmulatorConfig, Server, ServerHandle};
+use pjlink::{PjlinkDevice, PowerStatus};
+use std::time::Duration;
+
+// An emulated device with short warmup and cooling times, and a client for it.
+// The server stops when the handle is dropped, so keep it for the whole test.
+pub struct TestDevice {
+    pub emulator: Emulator,
+    pub server: ServerHandle,
+    pub device: PjlinkDevice,
+}
+
+pub fn config(power: PowerStatus) -> EmulatorConfig {
+    EmulatorConfig {
+        power,
+        warmup: Duration::from_millis(200),
+        cooling: Duration::from_millis(200),
+        ..EmulatorConfig::default()
+    }
+}
+
+pub fn spawn(config: EmulatorConfig) -> TestDevice {
+    serve(config, |server| server)
+}
+
+// Spawn a server after `setup` has added a password, faults or a profile to it
+pub fn serve<F: FnOnce(Server) -> Server>(config: EmulatorConfig, setup: F) -> TestDevice {
+    let emulator = Emulator::new(config);
+    let server = setup(Server::bind("127.0.0.1:0", emulator.clone()).unwrap())
+        .spawn()
+        .unwrap();
+    let device = PjlinkDevice::builder("127.0.0.1")
+        .port(server.local_addr().port())
+        .build()
+        .unwrap();
+    TestDevice {
+        emulator,
+        server,
+        device,
+    }
+}
+
+impl TestDevice {
+    // Another client for the same server, e.g. with a different password
+    pub fn builder(&self) -> pjlink::PjlinkDeviceBuilder {
+        PjlinkDevice::builder("127.0.0.1").port(self.server.local_addr().port())
+    }
+}
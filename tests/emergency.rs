@@ -0,0 +1,131 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::{EmulatorConfig, FaultProfile};
+use pjlink::{Emergency, EmergencyOptions, EmergencyReport, Fleet, PjlinkErrorCode, PowerStatus};
+use std::time::Duration;
+
+// Run an emergency once against a device in the given power state, which stays
+// in a transition long enough for the test
+fn emergency(power: PowerStatus, emergency: Emergency) -> EmergencyReport {
+    let test = common::spawn(EmulatorConfig {
+        warmup: Duration::from_secs(10),
+        cooling: Duration::from_secs(10),
+        ..common::config(power)
+    });
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+    fleet.emergency_with(
+        emergency,
+        EmergencyOptions {
+            attempts: 1,
+            ..EmergencyOptions::default()
+        },
+    )
+}
+
+#[test]
+fn muting_a_device_that_is_on_is_confirmed() {
+    assert!(emergency(PowerStatus::On, Emergency::AllMute).is_complete());
+}
+
+#[test]
+fn muting_a_device_in_standby_is_confirmed() {
+    let report = emergency(PowerStatus::Off, Emergency::AllMute);
+    assert!(report.is_complete());
+    assert_eq!(report.confirmed.len(), 1);
+}
+
+#[test]
+fn muting_a_cooling_device_is_confirmed() {
+    assert!(emergency(PowerStatus::Cooling, Emergency::AllMute).is_complete());
+}
+
+#[test]
+fn turning_off_a_cooling_device_is_confirmed() {
+    assert!(emergency(PowerStatus::Cooling, Emergency::AllOff).is_complete());
+}
+
+#[test]
+fn turning_off_a_warming_up_device_is_not_confirmed() {
+    let report = emergency(PowerStatus::Warmup, Emergency::AllOff);
+    assert!(report.confirmed.is_empty());
+    let err = report.unconfirmed.values().next().unwrap();
+    assert_eq!(
+        PjlinkErrorCode::from_error(err),
+        Some(PjlinkErrorCode::Unavailable)
+    );
+}
+
+#[test]
+fn a_dropped_connection_is_confirmed_by_the_next_round() {
+    let profile = FaultProfile::parse(1, "AVMT:close*1").unwrap();
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.faults(profile)
+    });
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+
+    let report = fleet.emergency_with(Emergency::AllMute, EmergencyOptions::default());
+    assert!(report.is_complete());
+    assert_eq!(report.confirmed, vec![Fleet::key(&test.device)]);
+}
+
+#[test]
+fn err3_while_warming_up_is_confirmed_by_the_next_round() {
+    let test = common::spawn(common::config(PowerStatus::Warmup));
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+
+    // The first round gets ERR3, the device is on by the second
+    let report = fleet.emergency_with(
+        Emergency::AllMute,
+        EmergencyOptions {
+            attempts: 2,
+            retry_delay: Duration::from_millis(300),
+            ..EmergencyOptions::default()
+        },
+    );
+    assert!(report.is_complete());
+    assert!(test.emulator.avmute().video);
+}
+
+#[test]
+fn the_power_status_check_shares_the_timeout() {
+    let profile = FaultProfile::parse(1, "AVMT:delay=300, POWR:delay=300").unwrap();
+    let test = common::serve(
+        EmulatorConfig {
+            cooling: Duration::from_secs(10),
+            ..common::config(PowerStatus::Cooling)
+        },
+        |server| server.faults(profile),
+    );
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+
+    let report = fleet.emergency_with(
+        Emergency::AllMute,
+        EmergencyOptions {
+            timeout: Duration::from_millis(500),
+            attempts: 1,
+            ..EmergencyOptions::default()
+        },
+    );
+    assert!(report.elapsed < Duration::from_millis(600));
+    assert!(!report.is_complete());
+}
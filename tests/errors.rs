@@ -0,0 +1,123 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::FaultProfile;
+use pjlink::{AvMute, InputType, PjlinkCommand, PjlinkErrorCode, PowerStatus};
+use std::io::{BufRead, BufReader, Error, Write};
+use std::net::TcpStream;
+
+fn code<T>(result: Result<T, Error>) -> Option<PjlinkErrorCode> {
+    match result {
+        Ok(_) => panic!("expected an error"),
+        Err(err) => PjlinkErrorCode::from_error(&err),
+    }
+}
+
+// A class 2 command, which a class 1 device does not know
+struct SerialNumber;
+
+impl PjlinkCommand for SerialNumber {
+    type Output = String;
+
+    fn class(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self) -> String {
+        String::from("SNUM ?")
+    }
+
+    fn decode(&self, value: &str) -> Result<String, Error> {
+        Ok(value.to_string())
+    }
+}
+
+#[test]
+fn err1_is_an_undefined_command() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    assert_eq!(test.device.send_command("XXXX ?").unwrap(), "%1XXXX=ERR1");
+    assert_eq!(
+        code(test.device.execute(&SerialNumber)),
+        Some(PjlinkErrorCode::UndefinedCommand)
+    );
+}
+
+#[test]
+fn err2_is_an_invalid_parameter() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    assert_eq!(
+        code(test.device.set_input(InputType::Storage(9))),
+        Some(PjlinkErrorCode::InvalidParameter)
+    );
+}
+
+#[test]
+fn err2_without_a_space_after_the_command() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    assert_eq!(test.device.send_command("POWR_?").unwrap(), "%1POWR=ERR2");
+}
+
+#[test]
+fn err2_for_a_line_that_is_too_long_and_the_rest_is_ignored() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let stream = TcpStream::connect(test.server.local_addr()).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut reply = Vec::new();
+    let mut next_reply = || {
+        reply.clear();
+        reader.read_until(b'\r', &mut reply).unwrap();
+        String::from_utf8_lossy(&reply).into_owned()
+    };
+    assert_eq!(next_reply(), "PJLINK 0\r");
+
+    // The part past the longest line the specification allows looks like another request
+    let mut request = format!("%1POWR {}", "1".repeat(161));
+    request.push_str("%1POWR ?\r%1INPT ?\r");
+    writer.write_all(request.as_bytes()).unwrap();
+    assert_eq!(next_reply(), "%1POWR=ERR2\r");
+    assert_eq!(next_reply(), "%1INPT=ERR3\r");
+}
+
+#[test]
+fn err3_is_unavailable() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    assert_eq!(
+        code(test.device.get_input()),
+        Some(PjlinkErrorCode::Unavailable)
+    );
+    assert_eq!(
+        code(test.device.set_avmute(AvMute {
+            video: true,
+            audio: true
+        })),
+        Some(PjlinkErrorCode::Unavailable)
+    );
+}
+
+#[test]
+fn err4_is_a_device_failure() {
+    let profile = FaultProfile::parse(1, "err4").unwrap();
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.faults(profile)
+    });
+    assert_eq!(
+        code(test.device.get_power_status()),
+        Some(PjlinkErrorCode::DeviceFailure)
+    );
+}
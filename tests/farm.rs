@@ -0,0 +1,146 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+use pjlink::server::Farm;
+use pjlink::PjlinkDevice;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+
+fn lamp_hours(farm: &Farm) -> Vec<u16> {
+    farm.devices()
+        .iter()
+        .map(|device| device.emulator().config().lamps[0].hours)
+        .collect()
+}
+
+fn error(config: &str) -> String {
+    match Farm::start(config) {
+        Ok(_) => panic!("expected an error"),
+        Err(err) => {
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            err.to_string()
+        }
+    }
+}
+
+#[test]
+fn devices_listen_on_loopback_unless_bind_is_set() {
+    let farm = Farm::start("port = 0\n[Room]").unwrap();
+    assert_eq!(
+        farm.devices()[0].local_addr().ip(),
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    );
+}
+
+#[test]
+fn n_is_the_number_within_the_group() {
+    let farm = Farm::start(
+        "
+        port = 0
+
+        [Hall]
+
+        [Room {n}]
+        count = 3
+        password = room{n}
+
+        [Lab]
+        count = 2
+        ",
+    )
+    .unwrap();
+
+    let names: Vec<&str> = farm.devices().iter().map(|device| device.name()).collect();
+    assert_eq!(
+        names,
+        vec!["Hall", "Room 1", "Room 2", "Room 3", "Lab 1", "Lab 2"]
+    );
+
+    let room = farm.device("Room 2").unwrap();
+    let device = PjlinkDevice::builder("127.0.0.1")
+        .port(room.local_addr().port())
+        .password("room2")
+        .build()
+        .unwrap();
+    assert_eq!(device.get_device_name().unwrap(), "Room 2");
+}
+
+#[test]
+fn ranges_are_repeatable_for_the_same_seed() {
+    let config = |seed| {
+        format!(
+            "port = 0\nseed = {}\n[Room {{n}}]\ncount = 10\nlamp_hours = 100..900",
+            seed
+        )
+    };
+    let first = lamp_hours(&Farm::start(&config(42)).unwrap());
+    assert!(first.iter().all(|hours| (100..=900).contains(hours)));
+    assert!(first.iter().any(|hours| *hours != first[0]));
+    assert_eq!(first, lamp_hours(&Farm::start(&config(42)).unwrap()));
+    assert_ne!(first, lamp_hours(&Farm::start(&config(7)).unwrap()));
+}
+
+#[test]
+fn vary_port_counts_up_from_the_port() {
+    // A free port to start from, the ones after it are very likely free as well
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let farm = Farm::start(&format!(
+        "[Room {{n}}]\ncount = 3\nport = {}\nvary = port",
+        port
+    ))
+    .unwrap();
+
+    let ports: Vec<u16> = farm
+        .devices()
+        .iter()
+        .map(|device| device.local_addr().port())
+        .collect();
+    assert_eq!(ports, vec![port, port + 1, port + 2]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn vary_address_counts_up_from_the_address() {
+    let farm =
+        Farm::start("[Lab {n}]\ncount = 3\nbind = 127.0.1.1\nport = 0\nvary = address").unwrap();
+
+    let addresses: Vec<String> = farm
+        .devices()
+        .iter()
+        .map(|device| device.local_addr().ip().to_string())
+        .collect();
+    assert_eq!(addresses, vec!["127.0.1.1", "127.0.1.2", "127.0.1.3"]);
+}
+
+#[test]
+fn unknown_and_invalid_settings_name_the_line() {
+    assert_eq!(
+        error("port = 0\n[Room]\ncolour = blue"),
+        "Line 3: unknown setting colour"
+    );
+    assert_eq!(
+        error("port = 0\n[Room]\nvary = name"),
+        "[Room] Line 3: vary must be port or address"
+    );
+    assert_eq!(
+        error("port = 0\n[Room]\nlamp_hours = 900..100"),
+        "[Room] Line 3: invalid value for lamp_hours"
+    );
+}
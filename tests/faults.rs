@@ -0,0 +1,175 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use common::TestDevice;
+use pjlink::server::FaultProfile;
+use pjlink::{Lamp, PjlinkErrorCode, PowerStatus};
+use std::io::{BufRead, BufReader, Error, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+fn with_faults(power: PowerStatus, rules: &str) -> TestDevice {
+    let profile = FaultProfile::parse(7, rules).unwrap();
+    common::serve(common::config(power), |server| server.faults(profile))
+}
+
+fn code(result: Result<impl Sized, Error>) -> Option<PjlinkErrorCode> {
+    PjlinkErrorCode::from_error(&result.err().expect("expected an error"))
+}
+
+#[test]
+fn split_replies_are_put_back_together() {
+    let test = with_faults(PowerStatus::On, "split");
+    assert_eq!(test.device.get_power_status().unwrap(), PowerStatus::On);
+    assert_eq!(test.device.get_class().unwrap(), "1");
+}
+
+#[test]
+fn delay_holds_the_reply_back() {
+    let test = with_faults(PowerStatus::On, "LAMP:delay=300");
+
+    let started = Instant::now();
+    test.device.get_power_status().unwrap();
+    assert!(started.elapsed() < Duration::from_millis(300));
+
+    let started = Instant::now();
+    test.device.get_lamp().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn close_mid_reply_is_an_error() {
+    let test = with_faults(PowerStatus::On, "close");
+    assert!(test.device.get_power_status().is_err());
+}
+
+#[test]
+fn erra_rejects_the_right_password() {
+    let profile = FaultProfile::parse(7, "erra*1").unwrap();
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.password("secret").unwrap().faults(profile)
+    });
+    let device = test.builder().password("secret").build().unwrap();
+
+    assert_eq!(
+        code(device.get_power_status()),
+        Some(PjlinkErrorCode::Authorization)
+    );
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn busy_answers_err3_while_warming_up() {
+    let test = with_faults(PowerStatus::Warmup, "busy");
+    assert_eq!(
+        code(test.device.get_lamp()),
+        Some(PjlinkErrorCode::Unavailable)
+    );
+}
+
+#[test]
+fn busy_does_not_hide_the_rules_after_it() {
+    let test = with_faults(PowerStatus::On, "busy, LAMP:delay=300, err4");
+
+    // The device is on, so busy does not fire and the later rules do
+    let started = Instant::now();
+    assert!(test.device.get_lamp().is_ok());
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(
+        code(test.device.get_power_status()),
+        Some(PjlinkErrorCode::DeviceFailure)
+    );
+}
+
+#[test]
+fn err4_is_a_device_failure() {
+    let test = with_faults(PowerStatus::On, "POWR:err4");
+    assert_eq!(
+        code(test.device.get_power_status()),
+        Some(PjlinkErrorCode::DeviceFailure)
+    );
+    assert_eq!(
+        test.device.get_lamp().unwrap(),
+        vec![Lamp { hours: 0, on: true }]
+    );
+}
+
+#[test]
+fn garbage_is_an_error() {
+    let test = with_faults(PowerStatus::On, "garbage");
+    assert!(test.device.get_power_status().is_err());
+}
+
+#[test]
+fn times_limits_how_often_a_rule_fires() {
+    let test = with_faults(PowerStatus::On, "POWR:err4*2");
+    assert!(test.device.get_power_status().is_err());
+    assert!(test.device.get_power_status().is_err());
+    assert_eq!(test.device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn the_same_seed_injects_the_same_faults() {
+    let outcomes = |seed| {
+        let profile = FaultProfile::parse(seed, "err4@0.5").unwrap();
+        let test = common::serve(common::config(PowerStatus::On), |server| {
+            server.faults(profile)
+        });
+        (0..20)
+            .map(|_| test.device.get_power_status().is_ok())
+            .collect::<Vec<_>>()
+    };
+    let first = outcomes(11);
+    assert_eq!(first, outcomes(11));
+    assert!(first.contains(&true) && first.contains(&false));
+}
+
+#[test]
+fn each_connection_gets_the_same_faults_however_they_interleave() {
+    // Whether each of 20 POWR ? requests on the first connection was answered with ERR4,
+    // with another connection sending requests in between when `interleave` is set
+    let outcomes = |interleave: bool| {
+        let test = with_faults(PowerStatus::On, "err4@0.5");
+        let connect = || {
+            let stream = TcpStream::connect(test.server.local_addr()).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut greeting = Vec::new();
+            reader.read_until(b'\r', &mut greeting).unwrap();
+            (stream, reader)
+        };
+        let request = |(ref mut stream, ref mut reader): &mut (TcpStream, BufReader<TcpStream>)| {
+            stream.write_all(b"%1POWR ?\r").unwrap();
+            let mut reply = Vec::new();
+            reader.read_until(b'\r', &mut reply).unwrap();
+            reply == b"%1POWR=ERR4\r"
+        };
+        let mut first = connect();
+        let mut second = connect();
+        (0..20)
+            .map(|_| {
+                if interleave {
+                    request(&mut second);
+                }
+                request(&mut first)
+            })
+            .collect::<Vec<_>>()
+    };
+    let alone = outcomes(false);
+    assert_eq!(alone, outcomes(true));
+    assert!(alone.contains(&true) && alone.contains(&false));
+}
@@ -0,0 +1,129 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+use pjlink::{Fleet, PjlinkDevice};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A fleet of devices that are never contacted, the operations only look at their ports
+fn fleet(count: u16) -> Fleet {
+    let mut fleet = Fleet::new();
+    for port in 1..=count {
+        fleet.add(
+            PjlinkDevice::builder("127.0.0.1")
+                .port(port)
+                .build()
+                .unwrap(),
+        );
+    }
+    fleet
+}
+
+#[test]
+fn results_are_keyed_by_host_and_port() {
+    let mut fleet = fleet(2);
+    fleet.add(PjlinkDevice::new("192.0.2.1").unwrap());
+
+    let results = fleet.run(|device| Ok(device.port));
+    let keys: Vec<&str> = results.keys().map(String::as_str).collect();
+    assert_eq!(keys, vec!["127.0.0.1:1", "127.0.0.1:2", "192.0.2.1"]);
+    assert_eq!(*results["127.0.0.1:2"].as_ref().unwrap(), 2);
+    assert_eq!(*results["192.0.2.1"].as_ref().unwrap(), 4352);
+}
+
+#[test]
+fn no_more_than_concurrency_devices_at_once() {
+    let fleet = fleet(6).concurrency(2);
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+
+    let results = {
+        let running = Arc::clone(&running);
+        let most = Arc::clone(&most);
+        fleet.run(move |_| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        })
+    };
+    assert_eq!(results.len(), 6);
+    assert!(results.values().all(Result::is_ok));
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn devices_that_miss_the_deadline_time_out() {
+    let fleet = fleet(3).deadline(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let results = fleet.run(|device| {
+        if device.port == 2 {
+            thread::sleep(Duration::from_secs(2));
+        }
+        Ok(device.port)
+    });
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    assert_eq!(*results["127.0.0.1:1"].as_ref().unwrap(), 1);
+    assert_eq!(*results["127.0.0.1:3"].as_ref().unwrap(), 3);
+    let err = results["127.0.0.1:2"].as_ref().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn devices_not_started_by_the_deadline_time_out_without_running() {
+    let fleet = fleet(4).concurrency(1).deadline(Duration::from_millis(150));
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    let results = {
+        let ran = Arc::clone(&ran);
+        fleet.run(move |_| {
+            ran.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            Ok(())
+        })
+    };
+    // Let a straggler finish before counting
+    thread::sleep(Duration::from_millis(200));
+
+    let timed_out = results
+        .values()
+        .filter(|result| {
+            result
+                .as_ref()
+                .err()
+                .is_some_and(|err| err.kind() == ErrorKind::TimedOut)
+        })
+        .count();
+    assert_eq!(timed_out, 3);
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn run_on_reports_unknown_keys() {
+    let fleet = fleet(3);
+
+    let results = fleet.run_on(&["127.0.0.1:3", "127.0.0.1:9"], |device| Ok(device.port));
+    assert_eq!(results.len(), 2);
+    assert_eq!(*results["127.0.0.1:3"].as_ref().unwrap(), 3);
+    let err = results["127.0.0.1:9"].as_ref().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
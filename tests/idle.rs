@@ -0,0 +1,183 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use common::TestDevice;
+use pjlink::server::EmulatorConfig;
+use pjlink::{AvMute, Fleet, IdleEvent, IdlePolicy, IdleShutdown, PowerStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn watch(test: &TestDevice) -> (Fleet, IdleShutdown) {
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+    let idle = IdleShutdown::new(
+        fleet.clone(),
+        IdlePolicy {
+            idle_timeout: 30 * MINUTE,
+            warning: 5 * MINUTE,
+            poll_interval: MINUTE,
+        },
+    );
+    (fleet, idle)
+}
+
+fn class2_without_signal() -> TestDevice {
+    let test = common::spawn(EmulatorConfig {
+        class: 2,
+        ..common::config(PowerStatus::On)
+    });
+    test.emulator.set_signal(false);
+    test
+}
+
+#[test]
+fn a_device_reaching_the_timeout_unwarned_is_warned_first() {
+    let test = class2_without_signal();
+    let (fleet, idle) = watch(&test);
+    let start = Instant::now();
+    assert!(idle.check(start).is_empty());
+
+    // No check fell within the warning time
+    let events = idle.check(start + 31 * MINUTE);
+    assert_eq!(
+        events,
+        vec![IdleEvent::Warning {
+            device: Fleet::key(&fleet.devices()[0]),
+            remaining: 5 * MINUTE,
+        }]
+    );
+    assert!(idle.check(start + 35 * MINUTE).is_empty());
+    assert_eq!(test.device.get_power_status().unwrap(), PowerStatus::On);
+
+    let events = idle.check(start + 36 * MINUTE);
+    assert!(matches!(
+        events[0],
+        IdleEvent::Shutdown { result: Ok(()), .. }
+    ));
+    assert_eq!(
+        test.device.get_power_status().unwrap(),
+        PowerStatus::Cooling
+    );
+}
+
+#[test]
+fn a_warned_device_is_turned_off_at_the_timeout() {
+    let test = class2_without_signal();
+    let (_, idle) = watch(&test);
+    let start = Instant::now();
+    idle.check(start);
+
+    let events = idle.check(start + 27 * MINUTE);
+    assert!(matches!(events[0], IdleEvent::Warning { remaining, .. } if remaining == 3 * MINUTE));
+    assert!(idle.check(start + 29 * MINUTE).is_empty());
+    assert!(matches!(
+        idle.check(start + 30 * MINUTE)[0],
+        IdleEvent::Shutdown { .. }
+    ));
+}
+
+#[test]
+fn a_signal_or_a_reset_starts_over() {
+    let test = class2_without_signal();
+    let (fleet, idle) = watch(&test);
+    let start = Instant::now();
+    idle.check(start);
+    assert!(!idle.check(start + 26 * MINUTE).is_empty());
+
+    test.emulator.set_signal(true);
+    assert!(idle.check(start + 30 * MINUTE).is_empty());
+    test.emulator.set_signal(false);
+    idle.check(start + 31 * MINUTE);
+    assert!(idle.check(start + 40 * MINUTE).is_empty());
+
+    idle.reset(&Fleet::key(&fleet.devices()[0]));
+    idle.check(start + 50 * MINUTE);
+    assert!(idle.check(start + 70 * MINUTE).is_empty());
+}
+
+#[test]
+fn a_muted_class1_device_is_not_idle() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    test.device
+        .set_avmute(AvMute {
+            video: true,
+            audio: true,
+        })
+        .unwrap();
+    let (_, idle) = watch(&test);
+    let start = Instant::now();
+    idle.check(start);
+    assert!(idle.check(start + 31 * MINUTE).is_empty());
+    assert!(idle.check(start + 40 * MINUTE).is_empty());
+    assert_eq!(test.device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn an_exempt_device_stays_on() {
+    let test = class2_without_signal();
+    let (fleet, idle) = watch(&test);
+    idle.exempt(&Fleet::key(&fleet.devices()[0]));
+    let start = Instant::now();
+    idle.check(start);
+    assert!(idle.check(start + 31 * MINUTE).is_empty());
+    assert!(idle.check(start + 40 * MINUTE).is_empty());
+}
+
+#[test]
+fn a_later_class_without_a_signal_is_idle() {
+    // Class 10 is compared as a number, not as text that sorts before "2"
+    let test = common::spawn(EmulatorConfig {
+        class: 10,
+        ..common::config(PowerStatus::On)
+    });
+    test.emulator.set_signal(false);
+    let (_fleet, idle) = watch(&test);
+    let start = Instant::now();
+    assert!(idle.check(start).is_empty());
+    assert!(matches!(
+        idle.check(start + 26 * MINUTE)[0],
+        IdleEvent::Warning { .. }
+    ));
+}
+
+#[test]
+fn the_background_thread_turns_an_idle_device_off() {
+    let test = class2_without_signal();
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+    let mut idle = IdleShutdown::new(
+        fleet,
+        IdlePolicy {
+            idle_timeout: Duration::from_millis(300),
+            warning: Duration::from_millis(100),
+            poll_interval: Duration::from_millis(50),
+        },
+    );
+
+    idle.start().unwrap();
+    assert!(idle.is_running());
+    let started = Instant::now();
+    while test.emulator.power_status() == PowerStatus::On {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+    idle.stop();
+    assert!(!idle.is_running());
+}
@@ -0,0 +1,186 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::{EmulatorConfig, UdpServer};
+use pjlink::{
+    AuthLockedOut, AuthLockout, AvMute, InputType, ManagedDevice, PjlinkErrorCode, PowerStatus,
+};
+use std::net::UdpSocket;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn managed(test: &common::TestDevice) -> ManagedDevice {
+    let device = test.builder().build().unwrap();
+    ManagedDevice::new(Arc::new(device)).poll_interval(Duration::from_millis(50))
+}
+
+fn wait_for_status(managed: &ManagedDevice) {
+    for _ in 0..500 {
+        if managed.status().is_some() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("no status");
+}
+
+// Receive until `wanted`, returning everything received on the way
+fn receive_until<T: PartialEq + Clone>(changes: &Receiver<T>, wanted: &T) -> Vec<T> {
+    let mut seen = Vec::new();
+    loop {
+        let change = changes.recv_timeout(TIMEOUT).unwrap();
+        seen.push(change.clone());
+        if change == *wanted {
+            return seen;
+        }
+    }
+}
+
+#[test]
+fn changes_are_called_back_after_the_first_snapshot() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let mut managed = managed(&test);
+    let (power_sender, power) = channel();
+    managed.on_power_change(move |status| {
+        let _ = power_sender.send(status);
+    });
+    let (input_sender, input) = channel();
+    managed.on_input_change(move |input| {
+        let _ = input_sender.send(input);
+    });
+    let (mute_sender, mute) = channel();
+    managed.on_avmute_change(move |mute| {
+        let _ = mute_sender.send(mute);
+    });
+    managed.start().unwrap();
+    wait_for_status(&managed);
+
+    test.device.power_on_and_wait(TIMEOUT).unwrap();
+    assert_eq!(
+        receive_until(&power, &PowerStatus::On),
+        vec![PowerStatus::Warmup, PowerStatus::On]
+    );
+    // The input becomes available once the device is on
+    assert_eq!(input.recv_timeout(TIMEOUT).unwrap(), InputType::RGB(1));
+
+    let muted = AvMute {
+        audio: true,
+        video: true,
+    };
+    test.device.set_avmute(muted).unwrap();
+    assert_eq!(mute.recv_timeout(TIMEOUT).unwrap(), muted);
+
+    managed.stop();
+    assert!(!managed.is_running());
+}
+
+#[test]
+fn a_callback_can_add_a_callback() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let mut managed = managed(&test);
+    managed.start().unwrap();
+    wait_for_status(&managed);
+    let managed = Arc::new(managed);
+
+    let (sender, added) = channel();
+    let inner = Arc::clone(&managed);
+    managed.on_power_change(move |status| {
+        if status == PowerStatus::Warmup {
+            let sender = sender.clone();
+            inner.on_power_change(move |status| {
+                let _ = sender.send(status);
+            });
+        }
+    });
+
+    test.device.power_on().unwrap();
+    assert_eq!(added.recv_timeout(TIMEOUT).unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn poll_errors_are_called_back_up_to_the_lockout() {
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.password("secret").unwrap()
+    });
+    let device = test
+        .builder()
+        .password("wrong")
+        .auth_lockout(AuthLockout {
+            max_failures: 2,
+            cooldown: Duration::from_secs(60),
+        })
+        .build()
+        .unwrap();
+    let mut managed = ManagedDevice::new(Arc::new(device)).poll_interval(Duration::from_millis(50));
+    let (sender, errors) = channel();
+    managed.on_poll_error(move |err| {
+        let locked_out = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<AuthLockedOut>())
+            .is_some();
+        let _ = sender.send((PjlinkErrorCode::from_error(err), locked_out));
+    });
+    managed.start().unwrap();
+
+    let rejected = (Some(PjlinkErrorCode::Authorization), false);
+    assert_eq!(errors.recv_timeout(TIMEOUT).unwrap(), rejected);
+    assert_eq!(errors.recv_timeout(TIMEOUT).unwrap(), rejected);
+    assert_eq!(errors.recv_timeout(TIMEOUT).unwrap(), (None, true));
+    assert!(managed.status().is_none());
+}
+
+#[test]
+fn notifications_are_heard_between_polls() {
+    let test = common::spawn(EmulatorConfig {
+        class: 2,
+        ..common::config(PowerStatus::Off)
+    });
+    // A free port for the managed device to listen for notifications on
+    let listen = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _udp = UdpServer::bind("127.0.0.1:0", test.emulator.clone())
+        .unwrap()
+        .controller(listen)
+        .spawn()
+        .unwrap();
+
+    // Far too slow to see the change by polling
+    let device = test.builder().build().unwrap();
+    let mut managed = ManagedDevice::new(Arc::new(device))
+        .poll_interval(Duration::from_secs(60))
+        .notifications(listen);
+    let (sender, power) = channel();
+    managed.on_power_change(move |status| {
+        let _ = sender.send(status);
+    });
+    managed.start().unwrap();
+    wait_for_status(&managed);
+
+    test.emulator.set_power_status(PowerStatus::On);
+    assert_eq!(power.recv_timeout(TIMEOUT).unwrap(), PowerStatus::On);
+    assert_eq!(
+        managed.status().unwrap().power.value(),
+        Some(&PowerStatus::On)
+    );
+}
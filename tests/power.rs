@@ -0,0 +1,118 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::FaultProfile;
+use pjlink::{PjlinkErrorCode, PowerStatus, RetryPolicy};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn power_on_and_wait_from_off() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let mut seen = Vec::new();
+    let status = test
+        .device
+        .power_on_and_wait_with_progress(TIMEOUT, |status| seen.push(status))
+        .unwrap();
+    assert_eq!(status, PowerStatus::On);
+    assert_eq!(
+        seen,
+        vec![PowerStatus::Off, PowerStatus::Warmup, PowerStatus::On]
+    );
+}
+
+#[test]
+fn power_on_and_wait_while_warming_up_only_waits() {
+    let test = common::spawn(common::config(PowerStatus::Warmup));
+
+    // The emulator refuses a power command in the middle of a transition
+    let err = test.device.power_on().unwrap_err();
+    assert_eq!(
+        PjlinkErrorCode::from_error(&err),
+        Some(PjlinkErrorCode::Unavailable)
+    );
+
+    let mut seen = Vec::new();
+    let status = test
+        .device
+        .power_on_and_wait_with_progress(TIMEOUT, |status| seen.push(status))
+        .unwrap();
+    assert_eq!(status, PowerStatus::On);
+    assert_eq!(seen, vec![PowerStatus::Warmup, PowerStatus::On]);
+}
+
+#[test]
+fn power_off_and_wait_while_cooling_down_only_waits() {
+    let test = common::spawn(common::config(PowerStatus::Cooling));
+    let mut seen = Vec::new();
+    let status = test
+        .device
+        .power_off_and_wait_with_progress(TIMEOUT, |status| seen.push(status))
+        .unwrap();
+    assert_eq!(status, PowerStatus::Off);
+    assert_eq!(seen, vec![PowerStatus::Cooling, PowerStatus::Off]);
+}
+
+#[test]
+fn power_on_and_wait_lets_cooling_finish_first() {
+    let test = common::spawn(common::config(PowerStatus::Cooling));
+    let mut seen = Vec::new();
+    let status = test
+        .device
+        .power_on_and_wait_with_progress(TIMEOUT, |status| seen.push(status))
+        .unwrap();
+    assert_eq!(status, PowerStatus::On);
+    assert_eq!(
+        seen,
+        vec![
+            PowerStatus::Cooling,
+            PowerStatus::Off,
+            PowerStatus::Warmup,
+            PowerStatus::On
+        ]
+    );
+}
+
+#[test]
+fn power_on_and_wait_when_already_on() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let mut seen = Vec::new();
+    test.device
+        .power_on_and_wait_with_progress(TIMEOUT, |status| seen.push(status))
+        .unwrap();
+    assert_eq!(seen, vec![PowerStatus::On]);
+}
+
+#[test]
+fn power_on_and_wait_rides_out_dropped_connections() {
+    let profile = FaultProfile::parse(1, "POWR:close*2").unwrap();
+    let test = common::serve(common::config(PowerStatus::Off), |server| {
+        server.faults(profile)
+    });
+    // Without retries of its own, so every dropped connection reaches power_on_and_wait
+    let device = test
+        .builder()
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap();
+    assert!(device.get_power_status().is_err());
+
+    let status = device.power_on_and_wait(TIMEOUT).unwrap();
+    assert_eq!(status, PowerStatus::On);
+}
@@ -0,0 +1,176 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::{
+    AvMute, CommandOutcome, CommandQueue, InputType, PjlinkDevice, PowerStatus, PowerTimeout,
+    QueuedCommand,
+};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+const POLL: Duration = Duration::from_millis(50);
+
+const UNMUTED: AvMute = AvMute {
+    audio: false,
+    video: false,
+};
+
+fn queue(device: PjlinkDevice, hold_timeout: Duration) -> CommandQueue {
+    CommandQueue::with_hold_timeout(Arc::new(device), hold_timeout, POLL)
+}
+
+#[test]
+fn power_on_input_and_unmute_while_warming_up() {
+    let test = common::spawn(common::config(PowerStatus::Warmup));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    // The device is already on its way, so the power command is not sent again
+    let power = queue.push(QueuedCommand::PowerOn);
+    let input = queue.push(QueuedCommand::SetInput(InputType::Digital(2)));
+    let mute = queue.push(QueuedCommand::SetAvMute(UNMUTED));
+
+    assert_eq!(
+        power.wait().unwrap(),
+        CommandOutcome::Power(PowerStatus::Warmup)
+    );
+    assert_eq!(
+        input.wait().unwrap(),
+        CommandOutcome::Input(InputType::Digital(2))
+    );
+    assert_eq!(mute.wait().unwrap(), CommandOutcome::AvMute(UNMUTED));
+    assert_eq!(test.emulator.power_status(), PowerStatus::On);
+    assert_eq!(test.emulator.input(), InputType::Digital(2));
+}
+
+#[test]
+fn power_on_input_and_unmute_while_cooling_down() {
+    let test = common::spawn(common::config(PowerStatus::Cooling));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    // The emulator refuses POWR 1 while cooling, the queue waits for Off first
+    let power = queue.push(QueuedCommand::PowerOn);
+    let input = queue.push(QueuedCommand::SetInput(InputType::Digital(2)));
+    let mute = queue.push(QueuedCommand::SetAvMute(UNMUTED));
+
+    assert_eq!(
+        power.wait().unwrap(),
+        CommandOutcome::Power(PowerStatus::Warmup)
+    );
+    assert_eq!(
+        input.wait().unwrap(),
+        CommandOutcome::Input(InputType::Digital(2))
+    );
+    assert_eq!(mute.wait().unwrap(), CommandOutcome::AvMute(UNMUTED));
+    assert_eq!(test.emulator.power_status(), PowerStatus::On);
+    assert_eq!(test.emulator.input(), InputType::Digital(2));
+}
+
+#[test]
+fn power_off_while_warming_up_waits_for_on() {
+    let test = common::spawn(common::config(PowerStatus::Warmup));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    let power = queue.push(QueuedCommand::PowerOff);
+    assert_eq!(
+        power.wait().unwrap(),
+        CommandOutcome::Power(PowerStatus::Cooling)
+    );
+}
+
+#[test]
+fn commands_after_a_held_command_wait_their_turn() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    let power = queue.push(QueuedCommand::PowerOn);
+    let input = queue.push(QueuedCommand::SetInput(InputType::Digital(1)));
+    // A raw command is never held itself, but it is not sent before the input
+    let raw = queue.push(QueuedCommand::Raw(String::from("POWR ?")));
+
+    assert_eq!(
+        power.wait().unwrap(),
+        CommandOutcome::Power(PowerStatus::Warmup)
+    );
+    assert_eq!(
+        input.wait().unwrap(),
+        CommandOutcome::Input(InputType::Digital(1))
+    );
+    assert_eq!(
+        raw.wait().unwrap(),
+        CommandOutcome::Raw(String::from("%1POWR=1"))
+    );
+}
+
+#[test]
+fn a_held_command_times_out_and_the_queue_moves_on() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let queue = queue(test.device, Duration::from_millis(300));
+
+    let input = queue.push(QueuedCommand::SetInput(InputType::Digital(1)));
+    let raw = queue.push(QueuedCommand::Raw(String::from("POWR ?")));
+
+    let err = input.wait().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let timeout = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<PowerTimeout>())
+        .unwrap();
+    assert_eq!(timeout.target, PowerStatus::On);
+    assert_eq!(timeout.last, Some(PowerStatus::Off));
+
+    assert_eq!(
+        raw.wait().unwrap(),
+        CommandOutcome::Raw(String::from("%1POWR=0"))
+    );
+    assert_eq!(test.emulator.input(), InputType::RGB(1));
+}
+
+#[test]
+fn finish_executes_the_queued_commands_first() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    let power = queue.push(QueuedCommand::PowerOn);
+    let input = queue.push(QueuedCommand::SetInput(InputType::Digital(1)));
+    assert!(input.try_result().is_none());
+
+    queue.finish();
+    assert!(power.try_result().unwrap().is_ok());
+    assert_eq!(
+        input.try_result().unwrap().unwrap(),
+        CommandOutcome::Input(InputType::Digital(1))
+    );
+}
+
+#[test]
+fn a_result_is_only_reported_once_and_then_the_queue_is_closed() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let queue = queue(test.device, Duration::from_secs(5));
+
+    let raw = queue.push(QueuedCommand::Raw(String::from("POWR ?")));
+    queue.finish();
+
+    assert_eq!(
+        raw.try_result().unwrap().unwrap(),
+        CommandOutcome::Raw(String::from("%1POWR=0"))
+    );
+    // The worker has stopped and there is nothing more to come
+    let err = raw.try_result().unwrap().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+}
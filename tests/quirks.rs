@@ -0,0 +1,82 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use common::TestDevice;
+use pjlink::server::EmulatorConfig;
+use pjlink::{PowerStatus, Quirks};
+use std::time::Duration;
+
+fn device(manufacturer: &str, product_name: &str) -> TestDevice {
+    common::spawn(EmulatorConfig {
+        manufacturer: manufacturer.to_string(),
+        product_name: product_name.to_string(),
+        ..common::config(PowerStatus::On)
+    })
+}
+
+const LENIENT: Quirks = Quirks {
+    greeting_delay: Duration::from_millis(0),
+    close_after_reply: false,
+    reconnect_delay: Duration::from_millis(0),
+    lenient_replies: true,
+    extended_inputs: false,
+};
+
+#[test]
+fn the_built_in_table_is_matched_by_manufacturer_and_product() {
+    let test = device("Sony", "VPL-FHZ70");
+    let quirks = test.device.detect_quirks().unwrap();
+    assert!(quirks.close_after_reply);
+    assert_eq!(test.device.quirks(), quirks);
+}
+
+#[test]
+fn known_quirks_of_the_device_come_before_the_built_in_table() {
+    let test = device("Sony", "VPL-FHZ70");
+    let device = test
+        .builder()
+        .known_quirks("SONY", "vpl-f", LENIENT)
+        .build()
+        .unwrap();
+    assert_eq!(device.detect_quirks().unwrap(), LENIENT);
+}
+
+#[test]
+fn an_unknown_device_keeps_its_quirks() {
+    let test = device("Acme", "PJ-200");
+    let device = test.builder().quirks(LENIENT).build().unwrap();
+    assert_eq!(device.detect_quirks().unwrap(), LENIENT);
+
+    // Identifying the device does not leave lenient replies switched on
+    assert_eq!(test.device.detect_quirks().unwrap(), Quirks::default());
+    assert_eq!(test.device.quirks(), Quirks::default());
+}
+
+#[test]
+fn auto_quirks_identifies_the_device_before_the_first_command() {
+    let test = device("Acme", "PJ-200");
+    let device = test
+        .builder()
+        .known_quirks("acme", "", LENIENT)
+        .auto_quirks()
+        .build()
+        .unwrap();
+    assert_eq!(device.quirks(), Quirks::default());
+    assert_eq!(device.get_power_status().unwrap(), PowerStatus::On);
+    assert_eq!(device.quirks(), LENIENT);
+}
@@ -0,0 +1,238 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::{AvMute, Change, DeviceState, Fleet, InputType, PowerStatus, Reconciler, RoomState};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const UNMUTED: AvMute = AvMute {
+    audio: false,
+    video: false,
+};
+
+// A fleet of the device and a desired state that has it on Digital 2 and unmuted
+fn room(test: &common::TestDevice) -> (Fleet, RoomState) {
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+    let mut desired = RoomState::default();
+    desired.devices.insert(
+        Fleet::key(&fleet.devices()[0]),
+        DeviceState {
+            on: true,
+            input: Some(InputType::Digital(2)),
+            avmute: Some(UNMUTED),
+            freeze: None,
+        },
+    );
+    (fleet, desired)
+}
+
+fn changes(fleet: &Fleet, desired: &RoomState, dry_run: bool) -> Vec<Change> {
+    let results = fleet.reconcile(desired, dry_run);
+    let corrections = results.values().next().unwrap().as_ref().unwrap();
+    assert!(corrections.iter().all(|c| c.dry_run == dry_run));
+    corrections.iter().map(|c| c.change).collect()
+}
+
+#[test]
+fn a_dry_run_plans_the_input_and_mute_of_a_device_that_is_off() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let (fleet, desired) = room(&test);
+
+    // The device can't tell its input while it is off, but it is already unmuted
+    assert_eq!(
+        changes(&fleet, &desired, true),
+        vec![Change::PowerOn, Change::Input(InputType::Digital(2), None),]
+    );
+    assert_eq!(test.emulator.power_status(), PowerStatus::Off);
+}
+
+#[test]
+fn a_dry_run_plans_the_input_of_a_device_that_is_warming_up() {
+    let test = common::spawn(common::config(PowerStatus::Warmup));
+    let (fleet, desired) = room(&test);
+
+    assert_eq!(
+        changes(&fleet, &desired, true),
+        vec![Change::Input(InputType::Digital(2), None),]
+    );
+}
+
+#[test]
+fn a_dry_run_logs_a_plan_again_only_when_it_changes() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let (fleet, desired) = room(&test);
+    let reconciler = Reconciler::dry_run(fleet, desired);
+
+    reconciler.run_once();
+    assert_eq!(reconciler.log().len(), 2);
+    // Every pass still returns the whole plan
+    let results = reconciler.run_once();
+    assert_eq!(results.values().next().unwrap().as_ref().unwrap().len(), 2);
+    assert_eq!(reconciler.log().len(), 2);
+
+    test.emulator.set_power_status(PowerStatus::On);
+    reconciler.run_once();
+    reconciler.run_once();
+    let log: Vec<Change> = reconciler.log().iter().map(|c| c.change).collect();
+    assert_eq!(
+        log[2..].to_vec(),
+        vec![Change::Input(
+            InputType::Digital(2),
+            Some(InputType::RGB(1))
+        )]
+    );
+}
+
+#[test]
+fn passes_converge_once_the_device_has_warmed_up() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let (fleet, mut desired) = room(&test);
+    let key = Fleet::key(&fleet.devices()[0]);
+    desired.devices.get_mut(&key).unwrap().avmute = Some(AvMute {
+        audio: false,
+        video: true,
+    });
+
+    // Turned on first, left alone while it warms up, then corrected
+    assert_eq!(changes(&fleet, &desired, false), vec![Change::PowerOn]);
+    assert_eq!(changes(&fleet, &desired, false), vec![]);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        changes(&fleet, &desired, false),
+        vec![
+            Change::Input(InputType::Digital(2), Some(InputType::RGB(1))),
+            Change::AvMute(
+                AvMute {
+                    audio: false,
+                    video: true,
+                },
+                Some(UNMUTED)
+            ),
+        ]
+    );
+    assert_eq!(changes(&fleet, &desired, false), vec![]);
+    assert_eq!(test.emulator.power_status(), PowerStatus::On);
+    assert_eq!(test.emulator.input(), InputType::Digital(2));
+    assert!(test.emulator.avmute().video);
+}
+
+#[test]
+fn devices_warming_up_or_cooling_down_are_left_alone() {
+    let warming = common::spawn(common::config(PowerStatus::Warmup));
+    let (fleet, mut desired) = room(&warming);
+    desired.devices.values_mut().next().unwrap().on = false;
+    assert_eq!(changes(&fleet, &desired, false), vec![]);
+    assert_eq!(warming.emulator.power_status(), PowerStatus::Warmup);
+
+    let cooling = common::spawn(common::config(PowerStatus::Cooling));
+    let (fleet, desired) = room(&cooling);
+    assert_eq!(changes(&fleet, &desired, false), vec![]);
+    assert_eq!(cooling.emulator.power_status(), PowerStatus::Cooling);
+}
+
+#[test]
+fn a_failed_command_ends_the_pass_for_the_device() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let (fleet, mut desired) = room(&test);
+    {
+        let state = desired.devices.values_mut().next().unwrap();
+        // Not one of the inputs of the emulator
+        state.input = Some(InputType::Storage(9));
+        state.avmute = Some(AvMute {
+            audio: true,
+            video: true,
+        });
+    }
+
+    let results = fleet.reconcile(&desired, false);
+    let corrections = results.values().next().unwrap().as_ref().unwrap();
+    assert_eq!(corrections.len(), 1);
+    assert_eq!(
+        corrections[0].change,
+        Change::Input(InputType::Storage(9), Some(InputType::RGB(1)))
+    );
+    assert!(corrections[0].error.is_some());
+    assert_eq!(test.emulator.avmute(), UNMUTED);
+}
+
+#[test]
+fn a_key_without_a_device_is_not_found() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let (fleet, mut desired) = room(&test);
+    let state = *desired.devices.values().next().unwrap();
+    desired
+        .devices
+        .insert(String::from("192.0.2.1:4352"), state);
+
+    let results = fleet.reconcile(&desired, false);
+    assert_eq!(
+        results["192.0.2.1:4352"].as_ref().unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert!(results[&Fleet::key(&fleet.devices()[0])].is_ok());
+}
+
+#[test]
+fn callbacks_may_read_the_log_and_add_callbacks() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let (fleet, desired) = room(&test);
+    let reconciler = Arc::new(Reconciler::new(fleet, desired));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    {
+        let reconciler = Arc::downgrade(&reconciler);
+        let seen = Arc::clone(&seen);
+        reconciler
+            .upgrade()
+            .unwrap()
+            .on_correction(move |correction| {
+                let reconciler = reconciler.upgrade().unwrap();
+                seen.lock().unwrap().push(reconciler.log().len());
+                reconciler.on_correction(|_| ());
+                assert_eq!(correction.change, Change::PowerOn);
+            });
+    }
+
+    reconciler.run_once();
+    assert_eq!(*seen.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn the_background_thread_converges_until_it_is_stopped() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let (fleet, desired) = room(&test);
+    let mut reconciler = Reconciler::new(fleet, desired).interval(Duration::from_millis(50));
+
+    reconciler.start().unwrap();
+    assert!(reconciler.is_running());
+    let started = Instant::now();
+    while test.emulator.input() != InputType::Digital(2) {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+    reconciler.stop();
+    assert!(!reconciler.is_running());
+
+    let logged = reconciler.log().len();
+    test.emulator.set_power_status(PowerStatus::Off);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(reconciler.log().len(), logged);
+    assert_eq!(test.emulator.power_status(), PowerStatus::Off);
+}
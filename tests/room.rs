@@ -0,0 +1,91 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::{EmulatorConfig, FaultProfile};
+use pjlink::{AvMute, Fleet, PjlinkDevice, PowerStatus, RoomPresets};
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+#[test]
+fn a_preset_for_a_mixed_case_host_survives_a_reload() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let mut fleet = Fleet::new();
+    fleet.add(
+        PjlinkDevice::builder("LocalHost")
+            .port(test.server.local_addr().port())
+            .build()
+            .unwrap(),
+    );
+
+    let mut presets = RoomPresets::new();
+    presets.insert("lecture", fleet.capture_state().unwrap());
+    let path = env::temp_dir().join(format!("pjlink-room-{}.presets", process::id()));
+    presets.save(&path).unwrap();
+    let loaded = RoomPresets::load(&path);
+    fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded, presets);
+
+    let muted = AvMute {
+        video: true,
+        audio: true,
+    };
+    test.device.set_avmute(muted).unwrap();
+    let results = fleet.restore_state(loaded.get("lecture").unwrap(), Duration::from_secs(5));
+    assert_eq!(results.len(), 1);
+    assert!(results.values().all(|result| result.is_ok()));
+    assert_ne!(test.device.get_avmute().unwrap(), muted);
+}
+
+#[test]
+fn device_keys_keep_their_case() {
+    let presets = RoomPresets::parse("[Lecture]\nProjector-A = power off\n").unwrap();
+    let state = presets.get("Lecture").unwrap();
+    assert!(state.devices.contains_key("Projector-A"));
+}
+
+fn capture(class: u8, faults: &str) -> Result<Option<bool>, std::io::Error> {
+    let profile = FaultProfile::parse(1, faults).unwrap();
+    let test = common::serve(
+        EmulatorConfig {
+            class,
+            ..common::config(PowerStatus::On)
+        },
+        |server| server.faults(profile),
+    );
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+    let state = fleet.capture_state()?;
+    Ok(state.devices.values().next().unwrap().freeze)
+}
+
+#[test]
+fn freeze_is_captured_from_class_2_devices_only() {
+    assert_eq!(capture(2, "").unwrap(), Some(false));
+    assert_eq!(capture(1, "").unwrap(), None);
+}
+
+#[test]
+fn a_failed_freeze_query_fails_the_capture() {
+    let err = capture(2, "FREZ:err4").unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("Could not capture the state of"));
+}
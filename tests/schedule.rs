@@ -0,0 +1,330 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::{ExecutionOutcome, Fleet, PowerStatus, Schedule, Scheduler};
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// A UTC date and time
+fn utc(year: i64, month: i64, day: i64, hour: u64, minute: u64) -> SystemTime {
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era - 719_468) as u64;
+    UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60)
+}
+
+// A schedule with one rule sending a harmless query to nowhere
+fn rule(settings: &str, rule: &str) -> Schedule {
+    Schedule::parse(&format!(
+        "{}\n[rule test]\n{}\ntarget = 127.0.0.1\naction = send POWR ?\n",
+        settings, rule
+    ))
+    .unwrap()
+}
+
+fn next(schedule: &Schedule, after: SystemTime) -> SystemTime {
+    schedule.next_run("test", after).unwrap()
+}
+
+fn parse_error(text: &str) -> String {
+    Schedule::parse(text).unwrap_err().to_string()
+}
+
+#[test]
+fn cron_steps() {
+    let schedule = rule("", "cron = */15 * * * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 10, 1)),
+        utc(2026, 10, 19, 10, 15)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 10, 45)),
+        utc(2026, 10, 19, 11, 0)
+    );
+
+    // 5/20 is 5, 25 and 45
+    let schedule = rule("", "cron = 5/20 * * * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 10, 30)),
+        utc(2026, 10, 19, 10, 45)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 10, 45)),
+        utc(2026, 10, 19, 11, 5)
+    );
+}
+
+#[test]
+fn cron_ranges_and_lists() {
+    // Weekdays at 9:00, 13:00 and 17:00
+    let schedule = rule("", "cron = 0 9-17/4 * * 1-5");
+    // Friday evening to Monday morning
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 23, 18, 0)),
+        utc(2026, 10, 26, 9, 0)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 26, 9, 0)),
+        utc(2026, 10, 26, 13, 0)
+    );
+
+    let schedule = rule("", "cron = 30 7,19 * * 0,6");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 0, 0)),
+        utc(2026, 10, 24, 7, 30)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 24, 7, 30)),
+        utc(2026, 10, 24, 19, 30)
+    );
+}
+
+#[test]
+fn cron_day_of_month_or_day_of_week() {
+    // The 1st of the month or any Monday. 2026-11-01 is a Sunday.
+    let schedule = rule("", "cron = 0 8 1 * 1");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 27, 0, 0)),
+        utc(2026, 11, 1, 8, 0)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 11, 1, 8, 0)),
+        utc(2026, 11, 2, 8, 0)
+    );
+
+    // With an unrestricted day of the week only the day of the month counts
+    let schedule = rule("", "cron = 0 8 1 * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 11, 1, 8, 0)),
+        utc(2026, 12, 1, 8, 0)
+    );
+
+    // 7 is Sunday as well
+    let schedule = rule("", "cron = 0 8 * * 7");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 0, 0)),
+        utc(2026, 10, 25, 8, 0)
+    );
+}
+
+#[test]
+fn cron_errors_name_the_line() {
+    let rule = |cron: &str| {
+        parse_error(&format!(
+            "[rule test]\ncron = {}\ntarget = a\naction = power on\n",
+            cron
+        ))
+    };
+    for cron in &[
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "*/0 * * * *",
+        "* * * *",
+    ] {
+        assert_eq!(rule(cron), "Line 2: expected minute hour day month weekday");
+    }
+}
+
+#[test]
+fn utc_offset_is_local_time() {
+    let schedule = rule("utc_offset = -05:30", "cron = 0 22 * * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 19, 0, 0)),
+        utc(2026, 10, 19, 3, 30)
+    );
+}
+
+#[test]
+fn holidays_are_skipped_unless_the_rule_runs_on_them() {
+    let holidays = "[holidays]\ndate = 2026-12-25\n";
+    let skip = Schedule::parse(&format!(
+        "{}[rule test]\ncron = 0 22 * * *\ntarget = a\naction = power off\n",
+        holidays
+    ))
+    .unwrap();
+    assert_eq!(
+        next(&skip, utc(2026, 12, 24, 23, 0)),
+        utc(2026, 12, 26, 22, 0)
+    );
+
+    let run = Schedule::parse(&format!(
+        "{}[rule test]\ncron = 0 22 * * *\ntarget = a\naction = power off\nholidays = run\n",
+        holidays
+    ))
+    .unwrap();
+    assert_eq!(
+        next(&run, utc(2026, 12, 24, 23, 0)),
+        utc(2026, 12, 25, 22, 0)
+    );
+
+    // A run on a holiday is logged without running
+    let scheduler = Scheduler::new(Fleet::new(), skip);
+    scheduler.tick(utc(2026, 12, 25, 21, 59));
+    let executions = scheduler.tick(utc(2026, 12, 25, 22, 0));
+    assert_eq!(executions.len(), 1);
+    assert_eq!(executions[0].outcome, ExecutionOutcome::Holiday);
+}
+
+#[test]
+fn clock_changes_move_local_time() {
+    let settings = "utc_offset = +01:00\n\n[clock changes]\nchange = 2026-03-29 02:00, +02:00\nchange = 2026-10-25 03:00, +01:00\n";
+
+    // 8:00 local is 7:00 UTC in winter and 6:00 UTC in summer
+    let schedule = rule(settings, "cron = 0 8 * * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 3, 28, 0, 0)),
+        utc(2026, 3, 28, 7, 0)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 3, 28, 7, 0)),
+        utc(2026, 3, 29, 6, 0)
+    );
+    assert_eq!(
+        next(&schedule, utc(2026, 10, 24, 7, 0)),
+        utc(2026, 10, 25, 7, 0)
+    );
+
+    // 2:30 does not happen when the clocks go forward
+    let schedule = rule(settings, "cron = 30 2 * * *");
+    assert_eq!(
+        next(&schedule, utc(2026, 3, 28, 2, 0)),
+        utc(2026, 3, 30, 0, 30)
+    );
+
+    // and happens twice when they go back, the rule runs once
+    let scheduler = Scheduler::new(Fleet::new(), schedule);
+    scheduler.tick(utc(2026, 10, 24, 23, 0));
+    let executions = scheduler.tick(utc(2026, 10, 25, 3, 0));
+    assert_eq!(executions.len(), 1);
+    assert_eq!(executions[0].scheduled, utc(2026, 10, 25, 0, 30));
+}
+
+#[test]
+fn clock_changes_have_to_be_in_order() {
+    assert_eq!(
+        parse_error(
+            "[clock changes]\nchange = 2026-10-25 03:00, +01:00\nchange = 2026-03-29 02:00, +02:00\n"
+        ),
+        "Line 3: clock changes have to be in order"
+    );
+    assert_eq!(
+        parse_error("[clock changes]\nchange = 2026-10-25 03:00\n"),
+        "Line 2: expected a change like 2026-03-29 02:00, +02:00"
+    );
+}
+
+#[test]
+fn a_restart_catches_up_or_logs_missed_runs() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let key = Fleet::key(&test.device);
+    let mut fleet = Fleet::new();
+    fleet.add(test.builder().build().unwrap());
+
+    let state = env::temp_dir().join(format!("pjlink-schedule-{}.state", process::id()));
+    let _ = fs::remove_file(&state);
+    let schedule = Schedule::parse(&format!(
+        "catch_up = 60\nstate = {}\n\n\
+         [rule early]\ncron = 0 8 * * *\ntarget = {key}\naction = avmute 31\n\n\
+         [rule late]\ncron = 10 9 * * *\ntarget = {key}\naction = avmute 30\n",
+        state.display(),
+        key = key
+    ))
+    .unwrap();
+
+    // Runs nothing, but remembers 7:00
+    let scheduler = Scheduler::new(fleet.clone(), schedule.clone());
+    assert!(scheduler.tick(utc(2026, 10, 19, 7, 0)).is_empty());
+    drop(scheduler);
+    let seconds = utc(2026, 10, 19, 7, 0).duration_since(UNIX_EPOCH).unwrap();
+    assert!(fs::read_to_string(&state)
+        .unwrap()
+        .contains(&format!("last = {}", seconds.as_secs())));
+    // The state was written to a temporary file first and renamed
+    assert!(!state.with_extension("state.tmp").exists());
+
+    // Started again at 9:30, 8:00 is too late to catch up and 9:10 is not
+    let scheduler = Scheduler::new(fleet.clone(), schedule.clone());
+    let executions = scheduler.tick(utc(2026, 10, 19, 9, 30));
+    assert_eq!(executions.len(), 2);
+    assert_eq!(executions[0].rule, "early");
+    assert_eq!(executions[0].outcome, ExecutionOutcome::Missed);
+    assert_eq!(executions[1].rule, "late");
+    assert!(executions[1].succeeded());
+    drop(scheduler);
+
+    // Started again in the same minute, nothing runs twice
+    let scheduler = Scheduler::new(fleet, schedule);
+    assert!(scheduler.tick(utc(2026, 10, 19, 9, 30)).is_empty());
+    fs::remove_file(&state).unwrap();
+}
+
+#[test]
+fn without_a_state_file_a_restart_does_not_catch_up() {
+    let schedule = rule("catch_up = 60", "cron = 10 9 * * *");
+    let scheduler = Scheduler::new(Fleet::new(), schedule);
+    assert!(scheduler.tick(utc(2026, 10, 19, 9, 30)).is_empty());
+}
+
+#[test]
+fn callbacks_may_read_the_log_and_add_callbacks() {
+    let schedule = rule("", "cron = * * * * *");
+    let scheduler = Arc::new(Scheduler::new(Fleet::new(), schedule));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    {
+        let weak = Arc::downgrade(&scheduler);
+        let seen = Arc::clone(&seen);
+        scheduler.on_execution(move |execution| {
+            let scheduler = weak.upgrade().unwrap();
+            seen.lock().unwrap().push(scheduler.log().len());
+            scheduler.on_execution(|_| ());
+            assert_eq!(execution.rule, "test");
+        });
+    }
+
+    assert_eq!(scheduler.tick(utc(2026, 10, 19, 9, 0)).len(), 1);
+    assert_eq!(*seen.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn the_background_thread_ticks_until_it_is_stopped() {
+    let schedule = rule("", "cron = * * * * *");
+    let mut scheduler = Scheduler::new(Fleet::new(), schedule);
+
+    scheduler.start().unwrap();
+    assert!(scheduler.is_running());
+    let started = Instant::now();
+    while scheduler.log().is_empty() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+    scheduler.stop();
+    assert!(!scheduler.is_running());
+    assert_eq!(scheduler.log()[0].rule, "test");
+}
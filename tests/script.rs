@@ -0,0 +1,169 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::{InputType, PjlinkDevice, PowerStatus, Script};
+
+fn parse_error(text: &str) -> String {
+    Script::parse(text).unwrap_err().to_string()
+}
+
+// The lines printed by a script
+fn printed(device: &PjlinkDevice, text: &str) -> Vec<String> {
+    let mut printed = Vec::new();
+    Script::parse(text)
+        .unwrap()
+        .run(device, |message| printed.push(message.text.clone()))
+        .unwrap();
+    printed
+}
+
+#[test]
+fn parse_errors_name_the_line() {
+    let cases = [
+        (
+            "power on\n\ninput digital\n",
+            "Line 3: expected an input like digital 1 or 31",
+        ),
+        (
+            "power on\npower up\n",
+            "Line 2: expected power on or power off",
+        ),
+        (
+            "# comment\nfly away\n",
+            "Line 2: unknown statement fly away",
+        ),
+        (
+            "wait 5 minutes\n",
+            "Line 1: expected wait 5s or wait until on",
+        ),
+        ("wait 5y\n", "Line 1: invalid duration 5y"),
+        ("print a; wait soon\n", "Line 1: invalid duration soon"),
+        (
+            "print a\nwait 10000000000000000000h\n",
+            "Line 2: invalid duration 10000000000000000000h",
+        ),
+        ("if power is on\n  print on\n", "Line 1: if without end"),
+        (
+            "power on\nrepeat 2\n  wait 1s\n",
+            "Line 2: repeat without end",
+        ),
+        ("while muted\nwait 1s\n", "Line 1: while without end"),
+        (
+            "power on\nend\n",
+            "Line 2: else or end without if, repeat or while",
+        ),
+        ("else\n", "Line 1: else or end without if, repeat or while"),
+        (
+            "if muted\nelse\nelse\nend\n",
+            "Line 3: expected end after else",
+        ),
+        ("repeat 2\nelse\nend\n", "Line 2: else in a repeat loop"),
+        ("repeat many\nend\n", "Line 1: invalid number of times many"),
+        ("if lamp hours ~ 5\nend\n", "Line 1: unknown comparison ~"),
+        (
+            "\n\nif the moon is full\nend\n",
+            "Line 3: unknown condition the moon is full",
+        ),
+        ("if muted\nelse if\nend\n", "Line 2: missing condition"),
+        (
+            "if muted\nelse unless\nend\n",
+            "Line 2: expected else or else if",
+        ),
+    ];
+    for &(script, error) in &cases {
+        assert_eq!(parse_error(script), error, "{:?}", script);
+    }
+}
+
+#[test]
+fn device_errors_name_the_line() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let script = Script::parse("print starting\n\ninput digital 1\n").unwrap();
+    let err = script.run(&test.device, |_| ()).unwrap_err();
+    assert!(err.to_string().starts_with("Line 3: "), "{}", err);
+
+    let err = Script::parse("print a\nfail Not today\n")
+        .unwrap()
+        .run(&test.device, |_| ())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Line 2: Not today");
+}
+
+#[test]
+fn else_if_chains_take_the_first_match() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let chain = "if power is off\n\
+                   print off\n\
+                 else if class > 1\n\
+                   print class 2\n\
+                 else if power is on\n\
+                   print on\n\
+                 else if not errors\n\
+                   print no errors\n\
+                 else\n\
+                   print else\n\
+                 end\n";
+    assert_eq!(printed(&test.device, chain), vec!["on"]);
+
+    test.device.set_input(InputType::Digital(1)).unwrap();
+    let chain = "if input is rgb 1\n\
+                   print rgb\n\
+                 else if input is digital 1\n\
+                   print digital\n\
+                 end\n\
+                 if muted\n\
+                   print muted\n\
+                 else if video muted\n\
+                   print video muted\n\
+                 else\n\
+                   print neither\n\
+                 end\n";
+    assert_eq!(printed(&test.device, chain), vec!["digital", "neither"]);
+}
+
+#[test]
+fn while_loops_run_until_the_condition_fails() {
+    let test = common::spawn(common::config(PowerStatus::Off));
+    let script = "power on\n\
+                  while not power is on\n\
+                    wait 50ms\n\
+                  end\n\
+                  print on\n\
+                  while power is off\n\
+                    print never\n\
+                  end\n";
+    assert_eq!(printed(&test.device, script), vec!["on"]);
+    assert_eq!(test.device.get_power_status().unwrap(), PowerStatus::On);
+}
+
+#[test]
+fn repeat_and_nesting() {
+    let test = common::spawn(common::config(PowerStatus::On));
+    let script = "repeat 2\n\
+                    print outer\n\
+                    repeat 2\n\
+                      if not muted\n\
+                        print inner\n\
+                      end\n\
+                    end\n\
+                  end\n";
+    assert_eq!(
+        printed(&test.device, script),
+        vec!["outer", "inner", "inner", "outer", "inner", "inner"]
+    );
+}
@@ -0,0 +1,112 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use common::TestDevice;
+use pjlink::server::EmulatorConfig;
+use pjlink::{Fleet, PowerStatus, Stagger};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+fn spawn(count: usize, power: PowerStatus, warmup: Duration) -> (Fleet, Vec<TestDevice>) {
+    let mut fleet = Fleet::new();
+    let mut tests = Vec::new();
+    for _ in 0..count {
+        let test = common::spawn(EmulatorConfig {
+            warmup,
+            ..common::config(power)
+        });
+        fleet.add(test.builder().build().unwrap());
+        tests.push(test);
+    }
+    (fleet, tests)
+}
+
+fn power(tests: &[TestDevice]) -> Vec<PowerStatus> {
+    tests
+        .iter()
+        .map(|test| test.emulator.power_status())
+        .collect()
+}
+
+#[test]
+fn waves_are_turned_on_in_order() {
+    let (fleet, tests) = spawn(4, PowerStatus::Off, Duration::from_millis(250));
+
+    let report = fleet.staggered_power_on(Stagger {
+        batch_size: 2,
+        delay: Duration::from_millis(400),
+        wait_for_warmup: false,
+        warmup_timeout: Duration::from_secs(5),
+    });
+    assert!(report.is_success());
+    assert_eq!(report.succeeded.len(), 4);
+
+    // The first wave has warmed up during the delay, the second has only just started
+    assert_eq!(
+        power(&tests),
+        vec![
+            PowerStatus::On,
+            PowerStatus::On,
+            PowerStatus::Warmup,
+            PowerStatus::Warmup
+        ]
+    );
+}
+
+#[test]
+fn failures_are_reported_and_the_other_waves_go_on() {
+    let (fleet, tests) = spawn(3, PowerStatus::Off, Duration::from_millis(100));
+    // The emulator refuses to turn on while cooling down
+    tests[0].emulator.set_power_status(PowerStatus::Cooling);
+    let cooling = Fleet::key(&tests[0].device);
+
+    let report = fleet.staggered_power_on(Stagger {
+        batch_size: 1,
+        delay: Duration::from_millis(10),
+        wait_for_warmup: false,
+        warmup_timeout: Duration::from_secs(5),
+    });
+    assert!(!report.is_success());
+    assert_eq!(report.failed.len(), 1);
+    assert!(report.failed.contains_key(&cooling));
+    assert_eq!(report.succeeded.len(), 2);
+    assert!(!report.succeeded.contains(&cooling));
+}
+
+#[test]
+fn the_fleet_deadline_covers_every_wave() {
+    let (fleet, tests) = spawn(4, PowerStatus::Off, Duration::from_millis(100));
+    let fleet = fleet.deadline(Duration::from_millis(250));
+
+    let started = Instant::now();
+    let report = fleet.staggered_power_on(Stagger {
+        batch_size: 1,
+        delay: Duration::from_millis(150),
+        wait_for_warmup: false,
+        warmup_timeout: Duration::from_secs(5),
+    });
+    assert!(started.elapsed() < Duration::from_millis(400));
+
+    assert_eq!(report.succeeded.len(), 2);
+    assert_eq!(report.failed.len(), 2);
+    for test in &tests[2..] {
+        let err = &report.failed[&Fleet::key(&test.device)];
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+    assert_eq!(power(&tests)[2..], [PowerStatus::Off, PowerStatus::Off]);
+}
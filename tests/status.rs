@@ -0,0 +1,64 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+mod common;
+
+use pjlink::server::FaultProfile;
+use pjlink::{AuthLockedOut, AuthLockout, PjlinkErrorCode, PowerStatus, StatusField};
+use std::time::Duration;
+
+#[test]
+fn a_failed_query_is_marked_in_its_field() {
+    let profile = FaultProfile::parse(1, "LAMP:err4").unwrap();
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.faults(profile)
+    });
+
+    let status = test.device.get_status_snapshot().unwrap();
+    assert_eq!(status.power, StatusField::Value(PowerStatus::On));
+    assert!(matches!(status.lamps, StatusField::Failed(_)));
+    assert!(status.name.value().is_some());
+}
+
+#[test]
+fn a_rejected_password_ends_the_snapshot() {
+    let test = common::serve(common::config(PowerStatus::On), |server| {
+        server.password("secret").unwrap()
+    });
+    let device = test
+        .builder()
+        .password("wrong")
+        .auth_lockout(AuthLockout {
+            max_failures: 2,
+            cooldown: Duration::from_secs(60),
+        })
+        .build()
+        .unwrap();
+
+    let err = device.get_status_snapshot().unwrap_err();
+    assert_eq!(
+        PjlinkErrorCode::from_error(&err),
+        Some(PjlinkErrorCode::Authorization)
+    );
+
+    // The snapshot counted once, so the device is not locked out yet
+    let err = device.get_power_status().unwrap_err();
+    assert!(err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<AuthLockedOut>())
+        .is_none());
+    assert!(device.get_status_snapshot().is_err());
+}
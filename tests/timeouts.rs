@@ -0,0 +1,84 @@
+// Copyright 2018 Rick Russell
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate pjlink;
+
+use pjlink::{Fleet, ManagedDevice, PjlinkDevice, PowerStatus};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IO_TIMEOUT: Duration = Duration::from_millis(200);
+
+// A server that accepts connections and never sends the greeting
+fn silent_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let mut open: Vec<TcpStream> = Vec::new();
+        for stream in listener.incoming().flatten() {
+            open.push(stream);
+        }
+    });
+    port
+}
+
+fn silent_device() -> PjlinkDevice {
+    PjlinkDevice::builder("127.0.0.1")
+        .port(silent_server())
+        .io_timeout(IO_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn a_silent_device_times_out() {
+    let device = silent_device();
+    let started = Instant::now();
+    assert!(device.get_power_status().is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn wait_for_power_gives_up_on_a_silent_device() {
+    let device = silent_device();
+    let started = Instant::now();
+    let result = device.wait_for_power(
+        PowerStatus::On,
+        Duration::from_millis(500),
+        Duration::from_millis(100),
+    );
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn a_managed_device_stops_while_its_device_is_silent() {
+    let mut managed = ManagedDevice::new(Arc::new(silent_device()));
+    managed.start().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    let started = Instant::now();
+    managed.stop();
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn a_fleet_gets_an_error_for_a_silent_device() {
+    let mut fleet = Fleet::new();
+    fleet.add(silent_device());
+    let results = fleet.run(|device| device.get_power_status());
+    assert!(results.values().all(|result| result.is_err()));
+}